@@ -0,0 +1,505 @@
+#[macro_use]
+extern crate anyhow;
+use anyhow::Result;
+use std::convert::TryInto;
+use std::ops::{Bound, RangeBounds};
+use thiserror::Error;
+use traits::{Extent, FormatError, FormatHeader};
+#[derive(Error, Debug)]
+enum BTreeError {
+    #[error("Invalid Extent size({size})")]
+    InvalidExtentSize { size: usize },
+    #[error("extent does not hold a tree")]
+    InvalidHeader,
+}
+/// Entries of the tree. Entries are sorted by key then by value so that a key may hold many
+/// values
+pub type Entry = (u64, u64);
+///# B+tree stored in fixed size pages of an extent
+///
+///Page 0 is the header, every other page is a node.
+///
+///Header Layout
+///
+///|Size (bytes) | Description|
+///|-------------|------------|
+///|16|`FormatHeader`|
+///|8|Page number of root|
+///|8|Number of pages|
+///|8|Tag supplied by the creator of the tree|
+///
+///Version 0 trees have no format header and are rewritten by `upgrade`.
+///
+///Node Layout
+///
+///|Size (bytes) | Description|
+///|-------------|------------|
+///|8|1 if leaf 0 if internal|
+///|8|Number of entries|
+///|8|Page number of next leaf, 0 if last leaf or internal|
+///|16 * entries|Entries (leaf)|
+///|8 * (entries + 1) then 16 * entries|Children then separators (internal)|
+///
+///Removed entries do not merge nodes, empty leaves stay in the leaf chain.
+pub struct BTree<E: Extent> {
+    extent: E,
+}
+struct Page {
+    leaf: bool,
+    next: u64,
+    entries: Vec<Entry>,
+    children: Vec<u64>,
+}
+impl<E: Extent> BTree<E> {
+    pub const PAGE_SIZE: usize = 4096;
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WBTR";
+    //offsets of the fields of the header
+    const ROOT: usize = FormatHeader::SIZE;
+    const PAGES: usize = Self::ROOT + 8;
+    const TAG: usize = Self::PAGES + 8;
+    const PAGE_HEADER_SIZE: usize = 24;
+    const LEAF_CAPACITY: usize = (Self::PAGE_SIZE - Self::PAGE_HEADER_SIZE) / 16;
+    const INTERNAL_CAPACITY: usize = (Self::PAGE_SIZE - Self::PAGE_HEADER_SIZE - 8) / 24;
+    /// Creates an empty tree
+    pub fn new(mut extent: E, tag: u64) -> Result<Self> {
+        extent.resize(2 * Self::PAGE_SIZE)?;
+        let mut tree = Self { extent };
+        tree.extent.write_at(0, &Self::format_header().to_binary());
+        tree.write_u64(Self::ROOT, 1);
+        tree.write_u64(Self::PAGES, 2);
+        tree.write_u64(Self::TAG, tag);
+        tree.write_page(
+            1,
+            &Page {
+                leaf: true,
+                next: 0,
+                entries: vec![],
+                children: vec![],
+            },
+        );
+        Ok(tree)
+    }
+    /// Loads a tree created by `new`. Fails if extent is in invalid state or is stored in
+    /// another version
+    pub fn load(extent: E) -> Result<Self> {
+        Self::check_format(&extent)?;
+        let tree = Self { extent };
+        if tree.pages() as usize * Self::PAGE_SIZE != tree.extent.len() {
+            return Err(anyhow!(
+                "{}",
+                BTreeError::InvalidExtentSize {
+                    size: tree.extent.len()
+                }
+            ));
+        }
+        Ok(tree)
+    }
+    /// Rewrites a tree stored in an older version in the current one. Returns whether the tree
+    /// was rewritten
+    pub fn upgrade(extent: &mut E) -> Result<bool> {
+        if let Err(error) = Self::check_format(extent) {
+            return match error.downcast_ref::<FormatError>() {
+                Some(FormatError::Outdated { found: 0, .. }) => {
+                    //version 0 starts with the fields of the header
+                    let fields = extent.as_slice(0..24).into_owned();
+                    extent.write_at(0, &Self::format_header().to_binary());
+                    extent.write_at(Self::ROOT, &fields);
+                    extent.flush(None)?;
+                    Ok(true)
+                }
+                _ => Err(error),
+            };
+        }
+        Ok(false)
+    }
+    fn format_header() -> FormatHeader {
+        FormatHeader::new(Self::MAGIC, Self::VERSION, 0)
+    }
+    /// Checks extent for damage and that it holds a tree stored in the current version
+    fn check_format(extent: &E) -> Result<()> {
+        extent.verify()?;
+        if extent.len() < 2 * Self::PAGE_SIZE {
+            return Err(anyhow!(
+                "{}",
+                BTreeError::InvalidExtentSize { size: extent.len() }
+            ));
+        }
+        let start = extent.as_slice(0..FormatHeader::SIZE);
+        match FormatHeader::parse(&start, Self::MAGIC) {
+            Some(header) => Ok(header.check("tree", Self::VERSION, 0)?),
+            //version 0 starts with the number of the root page then the number of pages
+            None if u64::from_le_bytes(start[8..16].try_into().unwrap()) as usize
+                * Self::PAGE_SIZE
+                == extent.len() =>
+            {
+                Err(FormatError::Outdated {
+                    structure: "tree",
+                    found: 0,
+                    supported: Self::VERSION,
+                }
+                .into())
+            }
+            None => Err(anyhow!("{}", BTreeError::InvalidHeader)),
+        }
+    }
+    /// Gets the tag given to `new`
+    pub fn tag(&self) -> u64 {
+        self.read_u64(Self::TAG)
+    }
+    /// Writes the tree back to storage, see `Extent::flush`
+    pub fn flush(&mut self) -> Result<()> {
+        self.extent.flush(None)
+    }
+    /// Checks the storage of the tree for damage, see `Extent::verify`
+    pub fn verify(&self) -> Result<()> {
+        self.extent.verify()
+    }
+    /// Inserts an entry, inserting an entry already in the tree does nothing
+    pub fn insert(&mut self, key: u64, value: u64) -> Result<()> {
+        let root = self.root();
+        if let Some((separator, right)) = self.insert_into(root, (key, value))? {
+            let new_root = self.allocate()?;
+            self.write_page(
+                new_root,
+                &Page {
+                    leaf: false,
+                    next: 0,
+                    entries: vec![separator],
+                    children: vec![root, right],
+                },
+            );
+            self.write_u64(Self::ROOT, new_root);
+        }
+        Ok(())
+    }
+    /// Removes an entry. Returns whether the entry was in the tree
+    pub fn remove(&mut self, key: u64, value: u64) -> bool {
+        let page_num = self.find_leaf((key, value));
+        let mut page = self.read_page(page_num);
+        match page.entries.binary_search(&(key, value)) {
+            Ok(index) => {
+                page.entries.remove(index);
+                self.write_page(page_num, &page);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+    /// Iterates in order over the entries whose key is in range
+    pub fn range<R: RangeBounds<u64>>(&self, range: R) -> Range<'_, E> {
+        let end = match range.end_bound() {
+            Bound::Included(end) => Bound::Included(*end),
+            Bound::Excluded(end) => Bound::Excluded(*end),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) if *start == u64::MAX => {
+                return Range {
+                    tree: self,
+                    entries: vec![].into_iter(),
+                    next: 0,
+                    end,
+                }
+            }
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let page = self.read_page(self.find_leaf((start, 0)));
+        let entries: Vec<Entry> = page
+            .entries
+            .into_iter()
+            .filter(|(key, _)| *key >= start)
+            .collect();
+        Range {
+            tree: self,
+            entries: entries.into_iter(),
+            next: page.next,
+            end,
+        }
+    }
+    /// Iterates in order over every entry
+    pub fn iter(&self) -> Range<'_, E> {
+        self.range(..)
+    }
+    fn insert_into(&mut self, page_num: u64, entry: Entry) -> Result<Option<(Entry, u64)>> {
+        let mut page = self.read_page(page_num);
+        if page.leaf {
+            match page.entries.binary_search(&entry) {
+                Ok(_) => return Ok(None),
+                Err(index) => page.entries.insert(index, entry),
+            }
+            if page.entries.len() > Self::LEAF_CAPACITY {
+                let right_entries = page.entries.split_off(page.entries.len() / 2);
+                let separator = right_entries[0];
+                let right_num = self.allocate()?;
+                let right = Page {
+                    leaf: true,
+                    next: page.next,
+                    entries: right_entries,
+                    children: vec![],
+                };
+                page.next = right_num;
+                self.write_page(right_num, &right);
+                self.write_page(page_num, &page);
+                return Ok(Some((separator, right_num)));
+            }
+            self.write_page(page_num, &page);
+            return Ok(None);
+        }
+        let child_index = child_index(&page.entries, &entry);
+        if let Some((separator, new_child)) = self.insert_into(page.children[child_index], entry)? {
+            page.entries.insert(child_index, separator);
+            page.children.insert(child_index + 1, new_child);
+            if page.entries.len() > Self::INTERNAL_CAPACITY {
+                let middle = page.entries.len() / 2;
+                let right_entries = page.entries.split_off(middle + 1);
+                let separator = page.entries.pop().unwrap();
+                let right_children = page.children.split_off(middle + 1);
+                let right_num = self.allocate()?;
+                self.write_page(
+                    right_num,
+                    &Page {
+                        leaf: false,
+                        next: 0,
+                        entries: right_entries,
+                        children: right_children,
+                    },
+                );
+                self.write_page(page_num, &page);
+                return Ok(Some((separator, right_num)));
+            }
+            self.write_page(page_num, &page);
+        }
+        Ok(None)
+    }
+    /// Finds the leaf that entry belongs in
+    fn find_leaf(&self, entry: Entry) -> u64 {
+        let mut page_num = self.root();
+        loop {
+            let page = self.read_page(page_num);
+            if page.leaf {
+                return page_num;
+            }
+            page_num = page.children[child_index(&page.entries, &entry)];
+        }
+    }
+    fn allocate(&mut self) -> Result<u64> {
+        let page_num = self.pages();
+        self.extent
+            .resize((page_num as usize + 1) * Self::PAGE_SIZE)?;
+        self.write_u64(Self::PAGES, page_num + 1);
+        Ok(page_num)
+    }
+    fn root(&self) -> u64 {
+        self.read_u64(Self::ROOT)
+    }
+    fn pages(&self) -> u64 {
+        self.read_u64(Self::PAGES)
+    }
+    fn read_page(&self, page_num: u64) -> Page {
+        let start = page_num as usize * Self::PAGE_SIZE;
+        let leaf = self.read_u64(start) == 1;
+        let count = self.read_u64(start + 8) as usize;
+        let next = self.read_u64(start + 16);
+        let body = start + Self::PAGE_HEADER_SIZE;
+        if leaf {
+            let entries = (0..count)
+                .map(|i| {
+                    (
+                        self.read_u64(body + i * 16),
+                        self.read_u64(body + i * 16 + 8),
+                    )
+                })
+                .collect();
+            Page {
+                leaf,
+                next,
+                entries,
+                children: vec![],
+            }
+        } else {
+            let children = (0..count + 1)
+                .map(|i| self.read_u64(body + i * 8))
+                .collect();
+            let separators = body + (count + 1) * 8;
+            let entries = (0..count)
+                .map(|i| {
+                    (
+                        self.read_u64(separators + i * 16),
+                        self.read_u64(separators + i * 16 + 8),
+                    )
+                })
+                .collect();
+            Page {
+                leaf,
+                next,
+                entries,
+                children,
+            }
+        }
+    }
+    fn write_page(&mut self, page_num: u64, page: &Page) {
+        let start = page_num as usize * Self::PAGE_SIZE;
+        self.write_u64(start, if page.leaf { 1 } else { 0 });
+        self.write_u64(start + 8, page.entries.len() as u64);
+        self.write_u64(start + 16, page.next);
+        let mut offset = start + Self::PAGE_HEADER_SIZE;
+        for child in page.children.iter() {
+            self.write_u64(offset, *child);
+            offset += 8;
+        }
+        for (key, value) in page.entries.iter() {
+            self.write_u64(offset, *key);
+            self.write_u64(offset + 8, *value);
+            offset += 16;
+        }
+    }
+    fn read_u64(&self, start: usize) -> u64 {
+        let mut buffer = [0u8; 8];
+        self.extent.read_at(start, &mut buffer);
+        u64::from_le_bytes(buffer)
+    }
+    fn write_u64(&mut self, start: usize, data: u64) {
+        self.extent.write_at(start, &data.to_le_bytes());
+    }
+}
+/// Index of the child of an internal node that entry belongs in. Entries equal to a separator
+/// are stored right of it
+fn child_index(separators: &[Entry], entry: &Entry) -> usize {
+    match separators.binary_search(entry) {
+        Ok(index) => index + 1,
+        Err(index) => index,
+    }
+}
+/// Iterator over entries of a `BTree` returned by `BTree::range`
+pub struct Range<'a, E: Extent> {
+    tree: &'a BTree<E>,
+    entries: std::vec::IntoIter<Entry>,
+    next: u64,
+    end: Bound<u64>,
+}
+impl<'a, E: Extent> Iterator for Range<'a, E> {
+    type Item = Entry;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                let in_range = match self.end {
+                    Bound::Included(end) => entry.0 <= end,
+                    Bound::Excluded(end) => entry.0 < end,
+                    Bound::Unbounded => true,
+                };
+                if in_range {
+                    return Some(entry);
+                }
+                self.entries = vec![].into_iter();
+                self.next = 0;
+                return None;
+            }
+            if self.next == 0 {
+                return None;
+            }
+            let page = self.tree.read_page(self.next);
+            self.entries = page.entries.into_iter();
+            self.next = page.next;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::{DrianableExtent, InMemoryExtent};
+    #[test]
+    fn empty() {
+        let tree = BTree::new(InMemoryExtent::new(), 5).ok().unwrap();
+        assert_eq!(tree.iter().count(), 0);
+        assert_eq!(tree.tag(), 5);
+    }
+    #[test]
+    fn insert_many() {
+        let mut tree = BTree::new(InMemoryExtent::new(), 0).ok().unwrap();
+        // insert out of order so that splits happen in the middle of nodes
+        for i in 0..20_000u64 {
+            let key = (i * 7919) % 20_000;
+            tree.insert(key, i).ok().unwrap();
+        }
+        let keys: Vec<u64> = tree.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, (0..20_000).collect::<Vec<_>>());
+    }
+    #[test]
+    fn range() {
+        let mut tree = BTree::new(InMemoryExtent::new(), 0).ok().unwrap();
+        for i in 0..5000u64 {
+            tree.insert(i % 100, i).ok().unwrap();
+        }
+        let entries: Vec<Entry> = tree.range(20..30).collect();
+        assert_eq!(entries.len(), 500);
+        assert!(entries.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(entries.iter().all(|(key, _)| *key >= 20 && *key < 30));
+        assert_eq!(tree.range(20..=30).count(), 550);
+        assert_eq!(tree.range(95..).count(), 250);
+        assert_eq!(tree.range(200..).count(), 0);
+    }
+    #[test]
+    fn remove() {
+        let mut tree = BTree::new(InMemoryExtent::new(), 0).ok().unwrap();
+        for i in 0..2000u64 {
+            tree.insert(i, i).ok().unwrap();
+        }
+        for i in (0..2000u64).filter(|i| i % 2 == 0) {
+            assert!(tree.remove(i, i));
+        }
+        assert!(!tree.remove(0, 0));
+        let keys: Vec<u64> = tree.range(..10).map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![1, 3, 5, 7, 9]);
+        assert_eq!(tree.iter().count(), 1000);
+    }
+    #[test]
+    fn load() {
+        let mut extent = InMemoryExtent::new();
+        {
+            let mut tree = BTree::new(DrianableExtent::new(&mut extent), 3)
+                .ok()
+                .unwrap();
+            for i in 0..1000u64 {
+                tree.insert(i, i * 2).ok().unwrap();
+            }
+        }
+        let tree = BTree::load(extent).ok().unwrap();
+        assert_eq!(tree.tag(), 3);
+        assert_eq!(
+            tree.range(10..12).collect::<Vec<_>>(),
+            vec![(10, 20), (11, 22)]
+        );
+        assert!(BTree::load(InMemoryExtent::new()).is_err());
+    }
+    #[test]
+    fn upgrade() {
+        let mut extent = InMemoryExtent::new();
+        {
+            let mut tree = BTree::new(DrianableExtent::new(&mut extent), 3)
+                .ok()
+                .unwrap();
+            for i in 0..1000u64 {
+                tree.insert(i, i * 2).ok().unwrap();
+            }
+        }
+        //turn the header back into version 0
+        let fields = extent.as_slice(16..40).into_owned();
+        extent.write_at(0, &fields);
+        extent.write_at(24, &[0; 16]);
+        let error = BTree::load(DrianableExtent::new(&mut extent))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<FormatError>(),
+            Some(FormatError::Outdated { found: 0, .. })
+        ));
+        assert!(BTree::upgrade(&mut extent).ok().unwrap());
+        assert!(!BTree::upgrade(&mut extent).ok().unwrap());
+        let tree = BTree::load(extent).ok().unwrap();
+        assert_eq!(tree.tag(), 3);
+        assert_eq!(tree.iter().count(), 1000);
+    }
+}
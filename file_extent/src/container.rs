@@ -0,0 +1,588 @@
+use super::sync_metadata;
+use anyhow::Result;
+use crc32fast::hash;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use traits::{CachedExtent, FormatHeader, PageStore};
+#[derive(Error, Debug)]
+enum ContainerError {
+    #[error("{path} is not a container")]
+    NotContainer { path: String },
+    #[error("directory of {path} is damaged")]
+    DamagedDirectory { path: String },
+    #[error("{path} is opened read only")]
+    ReadOnly { path: String },
+    #[error("{path} has no extent named {name}")]
+    NoExtent { path: String, name: String },
+}
+/// A sub extent, the chunks of the file holding it in order
+#[derive(Clone, Default)]
+struct Entry {
+    len: usize,
+    //offset and size of each chunk, sizes are multiples of the page size
+    chunks: Vec<(usize, usize)>,
+}
+impl Entry {
+    fn capacity(&self) -> usize {
+        self.chunks.iter().map(|(_, size)| size).sum()
+    }
+}
+struct State {
+    file: File,
+    path: String,
+    read_only: bool,
+    entries: BTreeMap<String, Entry>,
+    //length of the file
+    end: usize,
+    //space not used by the committed directory or any extent, offset to size
+    free: BTreeMap<usize, usize>,
+    //space given up since the last commit, it is still used by the committed directory
+    released: Vec<(usize, usize)>,
+    //offset and length of the committed directory
+    directory: (usize, usize),
+    //the directory has changed since the last commit
+    changed: bool,
+    //the file was created or resized since the last commit
+    resized: bool,
+}
+///# Container
+///
+///Keeps many named extents in a single file. Each `SubExtent` is stored in chunks of the file
+///and grows on its own, a chunk at least as large as the extent is added when it runs out of
+///room so that an extent is made of few chunks.
+///
+///Layout of the file:
+///
+///|`FormatHeader` (16 bytes)|directory offset (8 bytes)|directory length (8 bytes)|
+///|crc of directory (4 bytes)|crc of bytes before (4 bytes)|padding to a page|chunks...|
+///
+///The directory holds for every extent the length of its name (4 bytes), the name, the length
+///of the extent (8 bytes), the number of chunks (4 bytes) and the offset and size of each chunk
+///(8 bytes each). Changes to the directory are only written on `commit`, the new directory is
+///written into free space then the superblock is pointed at it, so a crash leaves the directory
+///of the last commit. Space freed since then is only reused after the next commit.
+///
+///Handles to the container share the same state.
+#[derive(Clone)]
+pub struct Container {
+    state: Arc<Mutex<State>>,
+}
+impl Container {
+    pub const PAGE_SIZE: usize = 4096;
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WCTR";
+    const SUPERBLOCK_SIZE: usize = FormatHeader::SIZE + 24;
+    /// Opens the container at path_string, creating it if it does not exist or is empty
+    pub fn open(path_string: String) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path_string)?;
+        Self::from_file(file, path_string, false)
+    }
+    /// Opens an existing container without write access. Writing to its extents panics and
+    /// resizing them fails
+    pub fn open_read_only(path_string: String) -> Result<Self> {
+        let file = File::open(&path_string)?;
+        Self::from_file(file, path_string, true)
+    }
+    fn from_file(file: File, path: String, read_only: bool) -> Result<Self> {
+        let end = file.metadata()?.len() as usize;
+        let mut state = State {
+            file,
+            path,
+            read_only,
+            entries: BTreeMap::new(),
+            end,
+            free: BTreeMap::new(),
+            released: vec![],
+            directory: (0, 0),
+            changed: false,
+            resized: false,
+        };
+        if end == 0 && !read_only {
+            state.end = Self::PAGE_SIZE;
+            state.file.set_len(Self::PAGE_SIZE as u64)?;
+            state.changed = true;
+            state.resized = true;
+            state.commit()?;
+        } else {
+            state.load()?;
+        }
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+    /// Opens the extent called name, it is created empty if it does not exist
+    pub fn open_extent(&self, name: &str) -> Result<SubExtent> {
+        let mut state = self.state.lock().unwrap();
+        if !state.entries.contains_key(name) {
+            if state.read_only {
+                return Err(anyhow!("{}", state.no_extent(name)));
+            }
+            state.entries.insert(name.to_string(), Entry::default());
+            state.changed = true;
+        }
+        let len = state.entries[name].len;
+        Ok(SubExtent::new(RawSubExtent {
+            state: self.state.clone(),
+            name: name.to_string(),
+            len,
+            read_only: state.read_only,
+        }))
+    }
+    /// Whether there is an extent called name
+    pub fn contains(&self, name: &str) -> bool {
+        self.state.lock().unwrap().entries.contains_key(name)
+    }
+    /// Gets the names of every extent in order
+    pub fn names(&self) -> Vec<String> {
+        self.state.lock().unwrap().entries.keys().cloned().collect()
+    }
+    /// Removes the extent called name. Open handles to it must be dropped first
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check_writable()?;
+        match state.entries.remove(name) {
+            Some(entry) => {
+                state.released.extend(entry.chunks);
+                state.changed = true;
+                Ok(())
+            }
+            None => Err(anyhow!("{}", state.no_extent(name))),
+        }
+    }
+    /// Writes the directory if it has changed and syncs the file. Data written by extents
+    /// before the commit survives a crash
+    pub fn commit(&self) -> Result<()> {
+        self.state.lock().unwrap().commit()
+    }
+    /// Gets the length of the file
+    pub fn file_len(&self) -> usize {
+        self.state.lock().unwrap().end
+    }
+}
+impl State {
+    fn no_extent(&self, name: &str) -> ContainerError {
+        ContainerError::NoExtent {
+            path: self.path.clone(),
+            name: name.to_string(),
+        }
+    }
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!(
+                "{}",
+                ContainerError::ReadOnly {
+                    path: self.path.clone()
+                }
+            ));
+        }
+        Ok(())
+    }
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        self.file.read_exact_at(buffer, offset as u64)?;
+        Ok(())
+    }
+    /// Reads the superblock and directory
+    fn load(&mut self) -> Result<()> {
+        let path = self.path.clone();
+        let not_container = || anyhow!("{}", ContainerError::NotContainer { path: path.clone() });
+        let mut superblock = vec![0; Container::SUPERBLOCK_SIZE];
+        if self.end < Container::PAGE_SIZE || self.read(0, &mut superblock).is_err() {
+            return Err(not_container());
+        }
+        match FormatHeader::parse(&superblock, Container::MAGIC) {
+            Some(format) => format.check("container", Container::VERSION, 0)?,
+            None => return Err(not_container()),
+        }
+        let stored = u32::from_le_bytes(superblock[36..40].try_into().unwrap());
+        if hash(&superblock[0..36]) != stored {
+            return Err(not_container());
+        }
+        let read_u64 = |range: Range<usize>| {
+            u64::from_le_bytes(superblock[range].try_into().unwrap()) as usize
+        };
+        let directory = (read_u64(16..24), read_u64(24..32));
+        let damaged = || {
+            anyhow!(
+                "{}",
+                ContainerError::DamagedDirectory { path: path.clone() }
+            )
+        };
+        let mut bytes = vec![0; directory.1];
+        if directory.0 + directory.1 > self.end || self.read(directory.0, &mut bytes).is_err() {
+            return Err(damaged());
+        }
+        if hash(&bytes) != u32::from_le_bytes(superblock[32..36].try_into().unwrap()) {
+            return Err(damaged());
+        }
+        let entries = parse_directory(&bytes).ok_or_else(damaged)?;
+        //every page not used by the directory or an extent is free
+        let mut used: Vec<(usize, usize)> = entries
+            .values()
+            .flat_map(|entry| entry.chunks.iter().cloned())
+            .collect();
+        if directory.1 > 0 {
+            used.push((directory.0, round(directory.1)));
+        }
+        used.sort_unstable();
+        let mut position = Container::PAGE_SIZE;
+        for (offset, size) in used {
+            if offset < position || offset + size > self.end {
+                return Err(damaged());
+            }
+            if offset > position {
+                self.free.insert(position, offset - position);
+            }
+            position = offset + size;
+        }
+        if self.end > position {
+            self.free.insert(position, self.end - position);
+        }
+        self.entries = entries;
+        self.directory = directory;
+        Ok(())
+    }
+    /// Finds room for size bytes, growing the file if there is no free space
+    fn allocate(&mut self, size: usize) -> Result<usize> {
+        let size = round(size);
+        let found = self
+            .free
+            .iter()
+            .find(|(_, free)| **free >= size)
+            .map(|(offset, free)| (*offset, *free));
+        if let Some((offset, free)) = found {
+            self.free.remove(&offset);
+            if free > size {
+                self.free.insert(offset + size, free - size);
+            }
+            return Ok(offset);
+        }
+        //free space at the end is grown instead of starting after it
+        let offset = match self.free.iter().next_back() {
+            Some((offset, free)) if offset + free == self.end => *offset,
+            _ => self.end,
+        };
+        self.free.remove(&offset);
+        self.end = offset + size;
+        self.file.set_len(self.end as u64)?;
+        self.resized = true;
+        Ok(offset)
+    }
+    /// Returns space to the free list, joining it with free space next to it
+    fn release(&mut self, mut offset: usize, mut size: usize) {
+        if let Some((before, before_size)) = self.free.range(..offset).next_back() {
+            if before + before_size == offset {
+                offset = *before;
+                size += before_size;
+            }
+        }
+        self.free.remove(&offset);
+        if let Some(after_size) = self.free.remove(&(offset + size)) {
+            size += after_size;
+        }
+        self.free.insert(offset, size);
+    }
+    /// Gets the offset in the file of byte offset of extent name
+    fn physical(&self, name: &str, offset: usize) -> usize {
+        let mut start = 0;
+        for (chunk, size) in self.entries[name].chunks.iter() {
+            if offset < start + size {
+                return chunk + offset - start;
+            }
+            start += size;
+        }
+        panic!("offset {} is past the chunks of {}", offset, name)
+    }
+    /// Sets the length of extent name, adding or releasing chunks
+    fn resize(&mut self, name: &str, new_size: usize) -> Result<()> {
+        self.check_writable()?;
+        let mut entry = self.entries[name].clone();
+        let capacity = entry.capacity();
+        if new_size > capacity {
+            //doubling the room keeps the number of chunks small
+            let size = round((new_size - capacity).max(capacity));
+            let offset = self.allocate(size)?;
+            match entry.chunks.last_mut() {
+                //joins chunks that end up next to each other
+                Some((last, last_size)) if *last + *last_size == offset => *last_size += size,
+                _ => entry.chunks.push((offset, size)),
+            }
+        } else {
+            //keeps the pages holding the first new_size bytes and releases the rest
+            let mut start = 0;
+            let mut kept = vec![];
+            for (offset, size) in entry.chunks.drain(..) {
+                let needed = round(new_size.saturating_sub(start)).min(size);
+                if needed > 0 {
+                    kept.push((offset, needed));
+                }
+                if needed < size {
+                    self.released.push((offset + needed, size - needed));
+                }
+                start += size;
+            }
+            entry.chunks = kept;
+        }
+        entry.len = new_size;
+        self.entries.insert(name.to_string(), entry);
+        self.changed = true;
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        if !self.changed {
+            self.file.sync_data()?;
+            return Ok(());
+        }
+        let bytes = write_directory(&self.entries);
+        let offset = if bytes.is_empty() {
+            0
+        } else {
+            self.allocate(bytes.len())?
+        };
+        self.file.write_all_at(&bytes, offset as u64)?;
+        //the extents and the directory reach the disk before the superblock points at them
+        if self.resized {
+            sync_metadata(&self.file, &self.path)?;
+            self.resized = false;
+        } else {
+            self.file.sync_data()?;
+        }
+        let mut superblock = vec![0; Container::SUPERBLOCK_SIZE];
+        superblock[0..16].copy_from_slice(
+            &FormatHeader::new(Container::MAGIC, Container::VERSION, 0).to_binary(),
+        );
+        superblock[16..24].copy_from_slice(&(offset as u64).to_le_bytes());
+        superblock[24..32].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+        superblock[32..36].copy_from_slice(&hash(&bytes).to_le_bytes());
+        let crc = hash(&superblock[0..36]);
+        superblock[36..40].copy_from_slice(&crc.to_le_bytes());
+        self.file.write_all_at(&superblock, 0)?;
+        self.file.sync_data()?;
+        let old = self.directory;
+        self.directory = (offset, bytes.len());
+        if old.1 > 0 {
+            self.released.push((old.0, round(old.1)));
+        }
+        for (offset, size) in std::mem::take(&mut self.released) {
+            self.release(offset, size);
+        }
+        self.changed = false;
+        Ok(())
+    }
+}
+impl Drop for State {
+    fn drop(&mut self) {
+        self.commit().ok();
+    }
+}
+/// Rounds size up to a whole number of pages
+fn round(size: usize) -> usize {
+    size.div_ceil(Container::PAGE_SIZE) * Container::PAGE_SIZE
+}
+fn write_directory(entries: &BTreeMap<String, Entry>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (name, entry) in entries.iter() {
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&(entry.len as u64).to_le_bytes());
+        bytes.extend_from_slice(&(entry.chunks.len() as u32).to_le_bytes());
+        for (offset, size) in entry.chunks.iter() {
+            bytes.extend_from_slice(&(*offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&(*size as u64).to_le_bytes());
+        }
+    }
+    bytes
+}
+/// Takes the next len bytes of bytes, None if they are cut off
+fn take<'a>(bytes: &'a [u8], start: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let taken = bytes.get(*start..*start + len)?;
+    *start += len;
+    Some(taken)
+}
+fn take_u64(bytes: &[u8], start: &mut usize) -> Option<usize> {
+    Some(u64::from_le_bytes(take(bytes, start, 8)?.try_into().ok()?) as usize)
+}
+fn take_u32(bytes: &[u8], start: &mut usize) -> Option<usize> {
+    Some(u32::from_le_bytes(take(bytes, start, 4)?.try_into().ok()?) as usize)
+}
+/// Parses a directory, None if it is cut off
+fn parse_directory(bytes: &[u8]) -> Option<BTreeMap<String, Entry>> {
+    let mut start = 0;
+    let mut entries = BTreeMap::new();
+    while start < bytes.len() {
+        let name_len = take_u32(bytes, &mut start)?;
+        let name = String::from_utf8(take(bytes, &mut start, name_len)?.to_vec()).ok()?;
+        let len = take_u64(bytes, &mut start)?;
+        let count = take_u32(bytes, &mut start)?;
+        let mut chunks = vec![];
+        for _ in 0..count {
+            let offset = take_u64(bytes, &mut start)?;
+            chunks.push((offset, take_u64(bytes, &mut start)?));
+        }
+        let entry = Entry { len, chunks };
+        if entry.capacity() < len {
+            return None;
+        }
+        entries.insert(name, entry);
+    }
+    Some(entries)
+}
+/// Extent stored in a `Container`. Pages that have been used are kept in a `CachedExtent`,
+/// changed pages are written back when they are evicted, on `flush` and when the extent is
+/// dropped. Flushing also commits the container
+pub type SubExtent = CachedExtent<RawSubExtent>;
+/// Bytes of an extent in a `Container` read and written a page at a time, see `SubExtent`
+pub struct RawSubExtent {
+    state: Arc<Mutex<State>>,
+    name: String,
+    len: usize,
+    read_only: bool,
+}
+impl PageStore for RawSubExtent {
+    fn byte_len(&self) -> usize {
+        self.len
+    }
+    /// Grown space is zeroed
+    fn set_len(&mut self, new_size: usize) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.resize(&self.name, new_size)?;
+        //chunks may hold bytes of an earlier extent or the bytes cut off by shrinking
+        let zeros = vec![0; Container::PAGE_SIZE];
+        let mut position = self.len;
+        while position < new_size {
+            let count =
+                (Container::PAGE_SIZE - position % Container::PAGE_SIZE).min(new_size - position);
+            let offset = state.physical(&self.name, position);
+            state.file.write_all_at(&zeros[..count], offset as u64)?;
+            position += count;
+        }
+        self.len = new_size;
+        Ok(())
+    }
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let start = page * buffer.len();
+        let end = (start + buffer.len()).min(self.len);
+        if start < end {
+            let offset = state.physical(&self.name, start);
+            state
+                .read(offset, &mut buffer[..end - start])
+                .map_err(|error| anyhow!("failed to read {}: {}", state.path, error))?;
+        }
+        Ok(())
+    }
+    fn write_page(&mut self, page: usize, data: &[u8]) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let start = page * data.len();
+        let end = (start + data.len()).min(self.len);
+        if start < end {
+            let offset = state.physical(&self.name, start);
+            state
+                .file
+                .write_all_at(&data[..end - start], offset as u64)?;
+        }
+        Ok(())
+    }
+    /// Commits the container, the range is ignored
+    fn sync(&mut self, _range: Option<Range<usize>>) -> Result<()> {
+        self.state.lock().unwrap().commit()
+    }
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return self.state.lock().unwrap().check_writable();
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_file};
+    use traits::Extent;
+    fn test(test_name: &str, test: fn(String) -> Result<()>) {
+        create_dir_all("test_folder").unwrap();
+        let p = format!("test_folder/container_{}", test_name);
+        remove_file(&p).ok();
+        if let Err(e) = test(p.clone()) {
+            panic!("{}", e);
+        }
+        remove_file(p).ok();
+    }
+    #[test]
+    fn grow_independently() {
+        test("grow_independently", |p| {
+            let data: Vec<u8> = (0..50_000).map(|i| (i % 251) as u8).collect();
+            {
+                let container = Container::open(p.clone())?;
+                let mut first = container.open_extent("first")?;
+                let mut second = container.open_extent("dir/second")?;
+                //interleaved growth gives each extent several chunks
+                for step in 1..=10 {
+                    first.resize(step * 5000)?;
+                    second.resize(step * 3000)?;
+                    first.write_at((step - 1) * 5000, &data[(step - 1) * 5000..step * 5000]);
+                    second[step * 3000 - 1] = step as u8;
+                }
+                first.flush(None)?;
+                second.flush(None)?;
+            }
+            let container = Container::open(p)?;
+            assert_eq!(container.names(), vec!["dir/second", "first"]);
+            let first = container.open_extent("first")?;
+            let second = container.open_extent("dir/second")?;
+            assert_eq!(first.len(), 50_000);
+            assert_eq!(first.as_slice(0..50_000), &data[..]);
+            assert_eq!(second.len(), 30_000);
+            for step in 1..=10 {
+                assert_eq!(second[step * 3000 - 1], step as u8);
+            }
+            assert_eq!(second[0], 0);
+            Ok(())
+        });
+    }
+    #[test]
+    fn reuse_space() {
+        test("reuse_space", |p| {
+            let container = Container::open(p)?;
+            let mut extent = container.open_extent("a")?;
+            extent.resize(100_000)?;
+            extent.write_at(0, &[1; 100_000]);
+            extent.flush(None)?;
+            let size = container.file_len();
+            extent.resize(10)?;
+            extent.flush(None)?;
+            drop(extent);
+            let mut other = container.open_extent("b")?;
+            other.resize(50_000)?;
+            other.flush(None)?;
+            //the space given up by the first extent is reused and grown space is zeroed. The
+            //directory written by the shrink can only go at the end
+            assert!(container.file_len() <= size + Container::PAGE_SIZE);
+            assert_eq!(other.as_slice(0..50_000), &[0; 50_000][..]);
+            drop(other);
+            container.remove("b")?;
+            assert!(!container.contains("b"));
+            Ok(())
+        });
+    }
+    #[test]
+    fn not_container() {
+        test("not_container", |p| {
+            std::fs::write(&p, vec![1; 10_000])?;
+            assert!(Container::open(p.clone()).is_err());
+            Ok(())
+        });
+    }
+}
@@ -2,16 +2,59 @@
 extern crate anyhow;
 use anyhow::Result;
 use libc::{
-    __errno_location, c_void, close, mmap, mremap, munmap, open, MAP_FAILED, MAP_SHARED,
-    MREMAP_MAYMOVE, O_RDWR, PROT_READ, PROT_WRITE,
+    __errno_location, c_int, c_void, close, mmap, msync, munmap, open, sysconf, _SC_PAGESIZE,
+    MAP_FAILED, MAP_SHARED, MS_SYNC, O_RDONLY, O_RDWR, PROT_READ, PROT_WRITE,
 };
+use std::borrow::Cow;
 use std::cmp::max;
+use std::ffi::CString;
 use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::ops::{Index, IndexMut};
+use std::ops::{Index, IndexMut, Range};
 use std::path::Path;
 use thiserror::Error;
-use traits::Extent;
+use traits::{
+    CachedExtent, ChecksummedExtent, CompressedExtent, CompressedPages, EncryptedExtent,
+    EncryptedPages, Extent,
+};
+mod container;
+mod paged;
+pub use container::{Container, RawSubExtent, SubExtent};
+pub use paged::{PagedFileExtent, PreadExtent};
+/// Extent stored in a file. Table managers that store their tables in files are generic over
+/// this so that the implementation can be chosen
+pub trait FileBacked: Extent + Sized {
+    /// Opens the file at path_string, creating it if it does not exist
+    fn open(path_string: String) -> Result<Self>;
+    /// Opens an existing file without write access
+    fn open_read_only(path_string: String) -> Result<Self>;
+    /// Opens the file with options. Extents ignore the options they have no use for
+    fn open_with(path_string: String, options: &ExtentOptions) -> Result<Self> {
+        if options.read_only {
+            Self::open_read_only(path_string)
+        } else {
+            Self::open(path_string)
+        }
+    }
+}
+/// Options passed to `FileBacked::open_with`, wrappers pass them on to the extent they wrap
+#[derive(Clone, Debug)]
+pub struct ExtentOptions {
+    /// Open an existing file without write access
+    pub read_only: bool,
+    /// Whether a `CompressedExtent` compresses the pages it writes
+    pub compress: bool,
+    /// Key of an `EncryptedExtent`
+    pub key: Option<[u8; 32]>,
+}
+impl Default for ExtentOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            compress: true,
+            key: None,
+        }
+    }
+}
 #[derive(Error, Debug)]
 enum FileExtentError {
     #[error("mmap failed: {errno}")]
@@ -20,155 +63,290 @@ enum FileExtentError {
     OpenFailed { errno: i32 },
     #[error("unmap failed: {errno}")]
     UnMapFailed { errno: i32 },
-    #[error("remap failed: {errno}")]
-    RemapFailed { errno: i32 },
     #[error("close failed for fd: {fd}, errno: {errno}")]
     CloseFailed { errno: i32, fd: i32 },
+    #[error("msync failed: {errno}")]
+    SyncFailed { errno: i32 },
+    #[error("{path} is opened read only")]
+    ReadOnly { path: String },
+    #[error("{path} is encrypted, a key is needed to open it")]
+    MissingKey { path: String },
 }
 //Extent that writes back to mmaped file
 pub struct FileExtent {
     file_map: *mut c_void,
     file_size: usize,
     path_string: String,
+    //set when the file was created or resized since the last flush, the size and directory
+    //entry then have to be synced as well as the contents
+    metadata_changed: bool,
+    //the file is mapped without write access
+    read_only: bool,
 }
 impl FileExtent {
     /// if path exists loads existing file. If it does not then file is created.
     /// Preconditions: path_string points to a regular file, and is readable and writable.
     pub fn new(path_string: String) -> Result<Self> {
         let path = Path::new(&path_string);
+        let metadata_changed = !path.exists();
         let file_size = {
             if !path.exists() {
-                File::create(path.clone())?
+                File::create(path)?
             } else {
-                File::open(path.clone())?
+                File::open(path)?
             }
         }
         .metadata()?
         .len() as usize;
-
-        let fd = unsafe { open((&path).to_str().unwrap().as_ptr() as *const i8, O_RDWR) };
-        if fd == -1 {
-            let errno = unsafe { *__errno_location() };
-            return Err(anyhow!(
-                "open call failed: {}",
-                FileExtentError::OpenFailed { errno }
-            ));
-        }
-
-        let file_map: *mut c_void = unsafe {
-            mmap(
-                0 as *mut c_void,
-                max(file_size, 1),
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED,
-                fd,
-                0,
-            )
-        };
-        if file_map == MAP_FAILED {
-            return Err(anyhow!(
-                "mmap failed: {}",
-                FileExtentError::MmapFailed {
-                    errno: unsafe { *__errno_location() }
-                }
-            ));
-        }
-        unsafe {
-            if close(fd) == -1 {
-                let errno = *__errno_location();
-                return Err(anyhow!(
-                    "close in ctor failed {}",
-                    FileExtentError::CloseFailed { errno, fd },
-                ));
-            }
-        };
+        let file_map = map_file(&path_string, file_size, false)?;
+        Ok(Self {
+            file_map,
+            file_size,
+            path_string,
+            metadata_changed,
+            read_only: false,
+        })
+    }
+    /// Maps an existing file without write access. Writing to the extent panics and resizing
+    /// it fails
+    pub fn open_read_only(path_string: String) -> Result<Self> {
+        let file_size = File::open(&path_string)?.metadata()?.len() as usize;
+        let file_map = map_file(&path_string, file_size, true)?;
         Ok(Self {
             file_map,
             file_size,
             path_string,
+            metadata_changed: false,
+            read_only: true,
         })
     }
+    fn check_writable(&self) {
+        if self.read_only {
+            panic!("{}", self.read_only_error());
+        }
+    }
+    fn read_only_error(&self) -> FileExtentError {
+        FileExtentError::ReadOnly {
+            path: self.path_string.clone(),
+        }
+    }
+    /// Gets the mapped bytes of the file
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.file_map as *const u8, self.file_size) }
+    }
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        self.check_writable();
+        unsafe { std::slice::from_raw_parts_mut(self.file_map as *mut u8, self.file_size) }
+    }
+}
+impl FileBacked for FileExtent {
+    fn open(path_string: String) -> Result<Self> {
+        Self::new(path_string)
+    }
+    fn open_read_only(path_string: String) -> Result<Self> {
+        Self::open_read_only(path_string)
+    }
+}
+/// Caches the pages of the file with the default capacity
+impl<E: FileBacked> FileBacked for CachedExtent<E> {
+    fn open(path_string: String) -> Result<Self> {
+        Ok(Self::new(E::open(path_string)?))
+    }
+    fn open_read_only(path_string: String) -> Result<Self> {
+        Ok(Self::new(E::open_read_only(path_string)?))
+    }
+    fn open_with(path_string: String, options: &ExtentOptions) -> Result<Self> {
+        Ok(Self::new(E::open_with(path_string, options)?))
+    }
+}
+/// Checksums are reported against the path of the file
+impl<E: FileBacked> FileBacked for ChecksummedExtent<E> {
+    fn open(path_string: String) -> Result<Self> {
+        Self::new(E::open(path_string.clone())?, path_string)
+    }
+    fn open_read_only(path_string: String) -> Result<Self> {
+        Self::new(E::open_read_only(path_string.clone())?, path_string)
+    }
+    fn open_with(path_string: String, options: &ExtentOptions) -> Result<Self> {
+        Self::new(E::open_with(path_string.clone(), options)?, path_string)
+    }
+}
+/// Compression is turned on for new files, `open_with` picks it per file
+impl<E: FileBacked> FileBacked for CompressedExtent<E> {
+    fn open(path_string: String) -> Result<Self> {
+        Ok(Self::new(CompressedPages::new(E::open(path_string)?)?))
+    }
+    fn open_read_only(path_string: String) -> Result<Self> {
+        Ok(Self::new(CompressedPages::new(E::open_read_only(
+            path_string,
+        )?)?))
+    }
+    fn open_with(path_string: String, options: &ExtentOptions) -> Result<Self> {
+        let mut extent = Self::new(CompressedPages::new(E::open_with(path_string, options)?)?);
+        if !options.read_only {
+            extent.set_compress(options.compress);
+        }
+        Ok(extent)
+    }
+}
+/// Can only be opened with `open_with` and a key
+impl<E: FileBacked> FileBacked for EncryptedExtent<E> {
+    fn open(path_string: String) -> Result<Self> {
+        Self::open_with(path_string, &ExtentOptions::default())
+    }
+    fn open_read_only(path_string: String) -> Result<Self> {
+        let options = ExtentOptions {
+            read_only: true,
+            ..ExtentOptions::default()
+        };
+        Self::open_with(path_string, &options)
+    }
+    fn open_with(path_string: String, options: &ExtentOptions) -> Result<Self> {
+        match options.key {
+            Some(key) => Ok(Self::new(EncryptedPages::new(
+                E::open_with(path_string, options)?,
+                &key,
+            )?)),
+            None => Err(anyhow!(
+                "{}",
+                FileExtentError::MissingKey { path: path_string }
+            )),
+        }
+    }
 }
+/// Syncs the contents and size of file along with the directory entry of path_string so that
+/// a new or resized file survives a crash
+fn sync_metadata(file: &File, path_string: &str) -> Result<()> {
+    file.sync_all()?;
+    let directory = match Path::new(path_string).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+/// Maps the first size bytes of the file at path_string
+fn map_file(path_string: &str, size: usize, read_only: bool) -> Result<*mut c_void> {
+    let (flags, protection): (c_int, c_int) = if read_only {
+        (O_RDONLY, PROT_READ)
+    } else {
+        (O_RDWR, PROT_READ | PROT_WRITE)
+    };
+    let c_path = CString::new(path_string)?;
+    let fd = unsafe { open(c_path.as_ptr(), flags) };
+    if fd == -1 {
+        let errno = unsafe { *__errno_location() };
+        return Err(anyhow!(
+            "open call failed: {}",
+            FileExtentError::OpenFailed { errno }
+        ));
+    }
+
+    let file_map: *mut c_void = unsafe {
+        mmap(
+            std::ptr::null_mut::<c_void>(),
+            max(size, 1),
+            protection,
+            MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    if file_map == MAP_FAILED {
+        return Err(anyhow!(
+            "mmap failed: {}",
+            FileExtentError::MmapFailed {
+                errno: unsafe { *__errno_location() }
+            }
+        ));
+    }
+    unsafe {
+        if close(fd) == -1 {
+            let errno = *__errno_location();
+            return Err(anyhow!(
+                "close in ctor failed {}",
+                FileExtentError::CloseFailed { errno, fd },
+            ));
+        }
+    };
+    Ok(file_map)
+}
+//the mapping is owned by the extent and is only reached through &self or &mut self
+unsafe impl Send for FileExtent {}
+unsafe impl Sync for FileExtent {}
 impl Drop for FileExtent {
     fn drop(&mut self) {
         unsafe {
-            munmap(self.file_map, self.file_size);
+            munmap(self.file_map, max(self.file_size, 1));
         }
     }
 }
 impl Extent for FileExtent {
+    /// Sets the length of the file then maps it again. Grown space is zeroed
     fn resize(&mut self, new_size: usize) -> Result<()> {
-        if new_size > self.file_size {
-            unsafe {
-                if munmap(self.file_map, max(self.file_size, 1)) == -1 {
-                    let errno = *__errno_location();
-                    return Err(anyhow!(
-                        "unmap failed: {}",
-                        FileExtentError::UnMapFailed { errno }
-                    ));
-                }
-                OpenOptions::new()
-                    .append(true)
-                    .open(self.path_string.clone())?
-                    .write_all(&mut vec![0; new_size - self.file_size])?;
-                let fd = open(self.path_string.as_str().as_ptr() as *const i8, O_RDWR);
-                if fd == -1 {
-                    let errno = *__errno_location();
-                    return Err(anyhow!(
-                        "open call failed: {}",
-                        FileExtentError::OpenFailed { errno }
-                    ));
-                }
-
-                let file_map: *mut c_void = mmap(
-                    0 as *mut c_void,
-                    max(self.file_size, 1),
-                    PROT_READ | PROT_WRITE,
-                    MAP_SHARED,
-                    fd,
-                    0,
-                );
-                if file_map == MAP_FAILED {
-                    return Err(anyhow!(
-                        "mmap failed: {}",
-                        FileExtentError::MmapFailed {
-                            errno: *__errno_location()
-                        }
-                    ));
-                }
-                close(fd);
-                self.file_map = file_map;
-            }
+        if self.read_only {
+            return Err(anyhow!("{}", self.read_only_error()));
         }
-        let new_map = unsafe { mremap(self.file_map, self.file_size, new_size, MREMAP_MAYMOVE) };
-        if new_map == (-1 as i64) as *mut c_void {
-            let errno = unsafe {
-                //horrifically unsafe if two threads errorout
-                //at the same time errno would be accessed at the same time
-                *__errno_location()
-            };
-            return Err(anyhow!(
-                "resize failed: {}",
-                FileExtentError::RemapFailed { errno }
-            ));
+        OpenOptions::new()
+            .write(true)
+            .open(&self.path_string)?
+            .set_len(new_size as u64)?;
+        unsafe {
+            if munmap(self.file_map, max(self.file_size, 1)) == -1 {
+                let errno = *__errno_location();
+                return Err(anyhow!(
+                    "unmap failed: {}",
+                    FileExtentError::UnMapFailed { errno }
+                ));
+            }
         }
+        self.file_map = map_file(&self.path_string, new_size, false)?;
+        self.metadata_changed |= new_size != self.file_size;
         self.file_size = new_size;
-        self.file_map = new_map;
+        Ok(())
+    }
+    /// Writes the mapped pages back with `msync`. If the file was created or resized since the
+    /// last flush the file and the directory holding it are synced so that the new size and
+    /// directory entry also reach the disk
+    fn flush(&mut self, range: Option<Range<usize>>) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let range = range.unwrap_or(0..self.file_size);
+        if range.start < range.end {
+            //msync needs an address aligned to a page
+            let page_size = unsafe { sysconf(_SC_PAGESIZE) } as usize;
+            let start = range.start / page_size * page_size;
+            let address = unsafe { (self.file_map as *mut u8).add(start) as *mut c_void };
+            if unsafe { msync(address, range.end - start, MS_SYNC) } == -1 {
+                let errno = unsafe { *__errno_location() };
+                return Err(anyhow!("{}", FileExtentError::SyncFailed { errno }));
+            }
+        }
+        if self.metadata_changed {
+            sync_metadata(&File::open(&self.path_string)?, &self.path_string)?;
+            self.metadata_changed = false;
+        }
         Ok(())
     }
     fn len(&self) -> usize {
         self.file_size
     }
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self.bytes()[offset..offset + buffer.len()]);
+    }
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        self.bytes_mut()[offset..offset + data.len()].copy_from_slice(data);
+    }
+    fn as_slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.bytes()[range])
+    }
 }
 impl Index<usize> for FileExtent {
     type Output = u8;
     fn index(&self, idx: usize) -> &Self::Output {
         if idx < self.file_size {
-            unsafe {
-                (self.file_map.offset(idx as isize) as *const u8)
-                    .as_ref()
-                    .unwrap() as &u8
-            }
+            unsafe { (self.file_map.add(idx) as *const u8).as_ref().unwrap() as &u8 }
         } else {
             panic!("index out of bounds")
         }
@@ -176,12 +354,9 @@ impl Index<usize> for FileExtent {
 }
 impl IndexMut<usize> for FileExtent {
     fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        self.check_writable();
         if idx < self.file_size {
-            unsafe {
-                (self.file_map.offset(idx as isize) as *mut u8)
-                    .as_mut()
-                    .unwrap() as &mut u8
-            }
+            unsafe { (self.file_map.add(idx) as *mut u8).as_mut().unwrap() as &mut u8 }
         } else {
             panic!("index out of bounds")
         }
@@ -190,18 +365,17 @@ impl IndexMut<usize> for FileExtent {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use anyhow::Error;
-    use std::fs::{read_dir, remove_dir, remove_file, DirBuilder, File};
-    fn create_test(test_name: String) {
+    use std::fs::{read_dir, remove_dir, remove_file, DirBuilder};
+    fn create_test(_test_name: String) {
         if !Path::new("test_folder").exists() {
-            DirBuilder::new().create("test_folder");
+            DirBuilder::new().create("test_folder").ok();
         }
     }
     fn remove_test(test_name: String) {
-        remove_file("test_folder/".to_string() + &test_name);
-        if let Some(iter) = read_dir("test_folder").ok() {
+        remove_file("test_folder/".to_string() + &test_name).ok();
+        if let Ok(iter) = read_dir("test_folder") {
             if iter.count() == 0 {
-                remove_dir("test_folder");
+                remove_dir("test_folder").ok();
             }
         }
     }
@@ -247,6 +421,66 @@ mod tests {
         });
     }
     #[test]
+    fn write_at() {
+        test("write_at".to_string(), |p| {
+            let mut f = FileExtent::new(p)?;
+            f.resize(1000)?;
+            let v: Vec<u8> = (0..100).collect();
+            f.write_at(900, &v);
+            let mut buffer = vec![0; 100];
+            f.read_at(900, &mut buffer);
+            assert_eq!(buffer, v);
+            assert_eq!(f.as_slice(950..1000), &v[50..100]);
+            assert_eq!(f[901], 1);
+            Ok(())
+        });
+    }
+    #[test]
+    fn flush() {
+        test("flush".to_string(), |p| {
+            {
+                let mut f = FileExtent::new(p.clone())?;
+                f.resize(10_000)?;
+                f.write_at(5000, &[1, 2, 3]);
+                f.flush(Some(5000..5003))?;
+                f.flush(None)?;
+            }
+            let f = FileExtent::new(p)?;
+            assert_eq!(f.as_slice(5000..5003), &[1, 2, 3][..]);
+            Ok(())
+        });
+    }
+    #[test]
+    fn read_only() {
+        test("read_only".to_string(), |p| {
+            {
+                let mut f = FileExtent::new(p.clone())?;
+                f.resize(100)?;
+                f.write_at(10, &[4, 5]);
+            }
+            let mut f = FileExtent::open_read_only(p.clone())?;
+            assert_eq!(f.as_slice(10..12), &[4, 5][..]);
+            assert!(f.resize(200).is_err());
+            assert!(FileExtent::open_read_only(p + "_missing").is_err());
+            Ok(())
+        });
+    }
+    #[test]
+    fn shrink() {
+        test("shrink".to_string(), |p| {
+            {
+                let mut f = FileExtent::new(p.clone())?;
+                f.resize(1000)?;
+                f[10] = 7;
+                f.resize(20)?;
+                assert_eq!(f.len(), 20);
+                assert_eq!(f[10], 7);
+            }
+            assert_eq!(FileExtent::new(p)?.len(), 20);
+            Ok(())
+        });
+    }
+    #[test]
     fn reload_from_disk() {
         test("reload_from_disk".to_string(), |p| {
             let v: Vec<u8> = (0..1000).map(|i: i32| i.to_le_bytes()[0]).collect();
@@ -262,7 +496,7 @@ mod tests {
                 }
             }
             {
-                let mut f = FileExtent::new(p)?;
+                let f = FileExtent::new(p)?;
                 assert_eq!(f.len(), 1000);
                 let v: Vec<u8> = (0..1000).map(|i: i32| i.to_le_bytes()[0]).collect();
                 for i in 0..1000 {
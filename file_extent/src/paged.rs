@@ -0,0 +1,199 @@
+use super::{sync_metadata, FileBacked};
+use anyhow::Result;
+use std::fs::{File, OpenOptions};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use thiserror::Error;
+use traits::{CachedExtent, PageStore};
+#[derive(Error, Debug)]
+enum PagedFileExtentError {
+    #[error("{path} is opened read only")]
+    ReadOnly { path: String },
+}
+/// Extent that reads and writes a file with positioned I/O (`pread` and `pwrite`) instead of
+/// mapping it. Pages that have been used are kept in a `CachedExtent`, changed pages are
+/// written back when they are evicted, on `flush` and when the extent is dropped
+pub type PagedFileExtent = CachedExtent<PreadExtent>;
+/// File read and written a page at a time with positioned I/O, see `PagedFileExtent`
+pub struct PreadExtent {
+    file: File,
+    path_string: String,
+    file_size: usize,
+    //set when the file was created or resized since the last flush
+    metadata_changed: bool,
+    read_only: bool,
+}
+impl PreadExtent {
+    /// if path exists loads existing file. If it does not then file is created.
+    pub fn new(path_string: String) -> Result<Self> {
+        let metadata_changed = !Path::new(&path_string).exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path_string)?;
+        Self::from_file(file, path_string, metadata_changed, false)
+    }
+    /// Opens an existing file without write access. Writing to the extent panics and resizing
+    /// it fails
+    pub fn open_read_only(path_string: String) -> Result<Self> {
+        let file = File::open(&path_string)?;
+        Self::from_file(file, path_string, false, true)
+    }
+    fn from_file(
+        file: File,
+        path_string: String,
+        metadata_changed: bool,
+        read_only: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            file_size: file.metadata()?.len() as usize,
+            file,
+            path_string,
+            metadata_changed,
+            read_only,
+        })
+    }
+}
+impl FileBacked for PagedFileExtent {
+    fn open(path_string: String) -> Result<Self> {
+        Ok(Self::new(PreadExtent::new(path_string)?))
+    }
+    fn open_read_only(path_string: String) -> Result<Self> {
+        Ok(Self::new(PreadExtent::open_read_only(path_string)?))
+    }
+}
+impl PageStore for PreadExtent {
+    fn byte_len(&self) -> usize {
+        self.file_size
+    }
+    /// Sets the length of the file. Grown space is zeroed
+    fn set_len(&mut self, new_size: usize) -> Result<()> {
+        self.check_writable()?;
+        self.file.set_len(new_size as u64)?;
+        self.metadata_changed |= new_size != self.file_size;
+        self.file_size = new_size;
+        Ok(())
+    }
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> Result<()> {
+        let start = page * buffer.len();
+        let end = (start + buffer.len()).min(self.file_size);
+        if start < end {
+            self.file
+                .read_exact_at(&mut buffer[..end - start], start as u64)
+                .map_err(|error| anyhow!("failed to read {}: {}", self.path_string, error))?;
+        }
+        Ok(())
+    }
+    fn write_page(&mut self, page: usize, data: &[u8]) -> Result<()> {
+        let start = page * data.len();
+        let end = (start + data.len()).min(self.file_size);
+        if start < end {
+            self.file.write_all_at(&data[..end - start], start as u64)?;
+        }
+        Ok(())
+    }
+    /// Syncs the file. If the file was created or resized since the last flush the directory
+    /// holding it is synced as well
+    fn sync(&mut self, _range: Option<Range<usize>>) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        if self.metadata_changed {
+            sync_metadata(&self.file, &self.path_string)?;
+            self.metadata_changed = false;
+        } else {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!(
+                "{}",
+                PagedFileExtentError::ReadOnly {
+                    path: self.path_string.clone()
+                }
+            ));
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_file};
+    use traits::Extent;
+    fn test(test_name: &str, test: fn(String) -> Result<()>) {
+        create_dir_all("test_folder").unwrap();
+        let p = format!("test_folder/paged_{}", test_name);
+        if let Err(e) = test(p.clone()) {
+            panic!("{}", e);
+        }
+        remove_file(p).ok();
+    }
+    #[test]
+    fn write_at() {
+        test("write_at", |p| {
+            let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+            {
+                let mut f = PagedFileExtent::open(p.clone())?;
+                f.resize(20_000)?;
+                f.write_at(3000, &data);
+                f[19_999] = 7;
+                assert_eq!(f.as_slice(3000..13_000), &data[..]);
+            }
+            let f = PagedFileExtent::open(p)?;
+            assert_eq!(f.len(), 20_000);
+            assert_eq!(f.as_slice(3000..13_000), &data[..]);
+            assert_eq!(f[19_999], 7);
+            assert_eq!(f[0], 0);
+            Ok(())
+        });
+    }
+    #[test]
+    fn evict() {
+        test("evict", |p| {
+            let mut f = PagedFileExtent::with_capacity(PreadExtent::new(p)?, 2);
+            f.resize(PagedFileExtent::PAGE_SIZE * 8)?;
+            for page in 0..8 {
+                f.write_at(page * PagedFileExtent::PAGE_SIZE, &[page as u8 + 1; 10]);
+            }
+            assert_eq!(f.cached_pages(), 2);
+            for page in 0..8 {
+                assert_eq!(f[page * PagedFileExtent::PAGE_SIZE + 9], page as u8 + 1);
+            }
+            f.flush(None)?;
+            Ok(())
+        });
+    }
+    #[test]
+    fn shrink() {
+        test("shrink", |p| {
+            let mut f = PagedFileExtent::open(p)?;
+            f.resize(5000)?;
+            f.write_at(4000, &[1; 1000]);
+            f.resize(4500)?;
+            f.resize(5000)?;
+            assert_eq!(f.as_slice(4000..4500), &[1; 500][..]);
+            assert_eq!(f.as_slice(4500..5000), &[0; 500][..]);
+            Ok(())
+        });
+    }
+    #[test]
+    fn read_only() {
+        test("read_only", |p| {
+            {
+                let mut f = PagedFileExtent::open(p.clone())?;
+                f.resize(100)?;
+                f.write_at(10, &[4, 5]);
+            }
+            let mut f = PagedFileExtent::open_read_only(p)?;
+            assert_eq!(f.as_slice(10..12), &[4, 5][..]);
+            assert!(f.resize(200).is_err());
+            Ok(())
+        });
+    }
+}
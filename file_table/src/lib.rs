@@ -1,8 +1,10 @@
 #[macro_use]
 extern crate anyhow;
 use anyhow::Result;
+use std::borrow::Cow;
+use std::convert::TryInto;
 use thiserror::Error;
-use traits::{Extent, InsertableDyn};
+use traits::{Extent, FormatError, FormatHeader, InsertableDyn};
 #[derive(Error, Debug)]
 enum TableError {
     #[error("Key is out of range")]
@@ -11,6 +13,8 @@ enum TableError {
     KeyUnused { key: Key },
     #[error("Invalid Extent size(#size)")]
     InvalidExtentSize { size: usize },
+    #[error("extent does not hold a table")]
+    InvalidHeader,
 }
 #[derive(Clone, Debug)]
 pub struct Key {
@@ -20,32 +24,31 @@ pub struct Key {
 ///
 ///|Size (bytes) | Datatype| Description|
 ///|-------------|---------|------------|
+///|16|Format header|`FormatHeader` of the table|
 ///|8|Header|Contains size of containing data|
 ///|BlockSize/8|Bitmap| Bitmap containing whether or not item is used (1 if occupied 0 if unused)|
 ///|BlockSize*DataSize|Block Data|Contains data for blocks|
 ///
-///Bitmap and block data repeat
+///Bitmap and block data repeat. Version 0 tables have no format header and are rewritten by
+///`upgrade`
 pub struct SizedTable<E: Extent> {
     extent: E,
     data_size: usize,
 }
 impl<E: Extent> SizedTable<E> {
-    const HEADER_SIZE: usize = 8;
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WSTB";
+    const HEADER_SIZE: usize = FormatHeader::SIZE + 8;
     const BITMAP_SIZE: usize = 0xff;
     const BLOCK_SIZE: usize = Self::BITMAP_SIZE * 8;
-    /// Tries to load table from extent. Fails if extent is in invalid state
+    /// Tries to load table from extent. Fails if extent is in invalid state or is stored in
+    /// another version
     pub fn try_load(extent: E) -> Result<Self> {
-        if extent.len() < 8 {
-            return Err(anyhow!(
-                "{}",
-                TableError::InvalidExtentSize { size: extent.len() }
-            ));
-        }
-        let l: Vec<u8> = (0..8).map(|i| extent[i]).collect();
-        let data_size = usize::from_le_bytes([l[0], l[1], l[2], l[3], l[4], l[5], l[6], l[7]]);
-        if (extent.len() - Self::HEADER_SIZE) % (Self::BITMAP_SIZE + Self::BLOCK_SIZE * data_size)
-            != 0
-        {
+        Self::check_format(&extent)?;
+        let mut l = [0; 8];
+        extent.read_at(FormatHeader::SIZE, &mut l);
+        let data_size = usize::from_le_bytes(l);
+        if !Self::is_whole(extent.len(), Self::HEADER_SIZE, data_size) {
             return Err(anyhow!(
                 "{}",
                 TableError::InvalidExtentSize { size: extent.len() }
@@ -56,101 +59,152 @@ impl<E: Extent> SizedTable<E> {
     /// Creates a fresh table.
     pub fn new(mut extent: E, data_size: usize) -> Result<Self> {
         extent.resize(Self::HEADER_SIZE)?;
-        let data_buffer = data_size.to_le_bytes();
-        for i in 0..8 {
-            extent[i] = data_buffer[i];
-        }
+        extent.write_at(0, &Self::format_header().to_binary());
+        extent.write_at(FormatHeader::SIZE, &data_size.to_le_bytes());
         Ok(Self { extent, data_size })
     }
+    /// Rewrites a table stored in an older version in the current one. Returns whether the
+    /// table was rewritten
+    pub fn upgrade(extent: &mut E) -> Result<bool> {
+        if let Err(error) = Self::check_format(extent) {
+            return match error.downcast_ref::<FormatError>() {
+                Some(FormatError::Outdated { found: 0, .. }) => {
+                    //version 0 is the current layout without the format header
+                    let old = extent.as_slice(0..extent.len()).into_owned();
+                    extent.resize(old.len() + FormatHeader::SIZE)?;
+                    extent.write_at(0, &Self::format_header().to_binary());
+                    extent.write_at(FormatHeader::SIZE, &old);
+                    extent.flush(None)?;
+                    Ok(true)
+                }
+                _ => Err(error),
+            };
+        }
+        Ok(false)
+    }
+    fn format_header() -> FormatHeader {
+        FormatHeader::new(Self::MAGIC, Self::VERSION, 0)
+    }
+    /// Checks extent for damage and that it holds a table stored in the current version
+    fn check_format(extent: &E) -> Result<()> {
+        extent.verify()?;
+        let start = extent.as_slice(0..std::cmp::min(extent.len(), Self::HEADER_SIZE));
+        match FormatHeader::parse(&start, Self::MAGIC) {
+            Some(header) => Ok(header.check("sized table", Self::VERSION, 0)?),
+            //version 0 starts with the size of the data
+            None if start.len() >= 8
+                && Self::is_whole(
+                    extent.len(),
+                    8,
+                    usize::from_le_bytes(start[..8].try_into().unwrap()),
+                ) =>
+            {
+                Err(FormatError::Outdated {
+                    structure: "sized table",
+                    found: 0,
+                    supported: Self::VERSION,
+                }
+                .into())
+            }
+            None if start.len() < Self::HEADER_SIZE => Err(anyhow!(
+                "{}",
+                TableError::InvalidExtentSize { size: extent.len() }
+            )),
+            None => Err(anyhow!("{}", TableError::InvalidHeader)),
+        }
+    }
+    /// Whether len bytes are a header of header_size followed by whole blocks
+    fn is_whole(len: usize, header_size: usize, data_size: usize) -> bool {
+        match data_size
+            .checked_mul(Self::BLOCK_SIZE)
+            .and_then(|size| size.checked_add(Self::BITMAP_SIZE))
+        {
+            Some(block) => len >= header_size && (len - header_size).is_multiple_of(block),
+            None => false,
+        }
+    }
+    /// Writes the table back to storage, see `Extent::flush`
+    pub fn flush(&mut self) -> Result<()> {
+        self.extent.flush(None)
+    }
+    /// Checks the storage of the table for damage, see `Extent::verify`
+    pub fn verify(&self) -> Result<()> {
+        self.extent.verify()
+    }
     /// Inserts data into table.
     /// Linear insertion time
     pub fn insert(&mut self, data: Box<dyn InsertableDyn>) -> Result<Key> {
         //first iterate through bitmaps then if one is unused fill it else append new block
         for i in 0..self.get_number_blocks() {
-            if let Some(index) = get_first_0(self.load_bitmap(i)) {
-                let start = index * self.data_size
-                    + i * (Self::BLOCK_SIZE * self.data_size + Self::BITMAP_SIZE)
-                    + Self::BITMAP_SIZE
-                    + Self::HEADER_SIZE;
-                let buffer = data.to_binary();
-                for i in 0..self.data_size {
-                    self.extent[i + start] = buffer[i];
-                }
-                let bitmap_location = i * (Self::BLOCK_SIZE * self.data_size + Self::BITMAP_SIZE)
-                    + Self::HEADER_SIZE
-                    + index / 8;
-
-                self.extent[bitmap_location] = self.extent[bitmap_location] | 1 << (index % 8);
-                return Ok(Key { index });
+            if let Some(index) = get_first_0(&self.load_bitmap(i)) {
+                let start = self.block_start(i) + Self::BITMAP_SIZE + index * self.data_size;
+                self.extent
+                    .write_at(start, &data.to_binary()[..self.data_size]);
+                let bitmap_location = self.block_start(i) + index / 8;
+                self.extent[bitmap_location] |= 1 << (index % 8);
+                return Ok(Key {
+                    index: i * Self::BLOCK_SIZE + index,
+                });
             }
         }
         let old_len = self.extent.len();
         let new_index = self.get_number_blocks() * Self::BLOCK_SIZE;
         self.extent
             .resize(old_len + Self::BITMAP_SIZE + Self::BLOCK_SIZE * self.data_size)?;
-        for i in old_len..old_len + Self::BITMAP_SIZE {
-            self.extent[i] = 0;
-        }
-        let buffer = data.to_binary();
-        for i in 0..self.data_size {
-            self.extent[i + old_len + Self::BITMAP_SIZE] = buffer[i];
-        }
-        self.extent[old_len] = 0x1;
+        let mut bitmap = vec![0; Self::BITMAP_SIZE];
+        bitmap[0] = 0x1;
+        self.extent.write_at(old_len, &bitmap);
+        self.extent.write_at(
+            old_len + Self::BITMAP_SIZE,
+            &data.to_binary()[..self.data_size],
+        );
         Ok(Key { index: new_index })
     }
     /// Gets data from key
     /// constant retrival time
     pub fn get<Data: InsertableDyn>(&self, key: Key, ctor: fn(Vec<u8>) -> Data) -> Result<Data> {
-        if ((key.index / Self::BLOCK_SIZE) as i64) < self.get_number_blocks() as i64 - 1 {
+        if key.index / Self::BLOCK_SIZE >= self.get_number_blocks() {
             Err(anyhow!("{}", TableError::KeyOutOfRange))
         } else {
             let block_number = key.index / Self::BLOCK_SIZE;
             let bitmap = self.load_bitmap(block_number)[(key.index % Self::BLOCK_SIZE) / 8];
             let index_in_bitmap = (key.index % Self::BLOCK_SIZE) % 8;
-            let bit = bitmap & (0x1 << index_in_bitmap) >> index_in_bitmap;
+            let bit = (bitmap >> index_in_bitmap) & 0x1;
             if bit == 0 {
                 return Err(anyhow!("{}", TableError::KeyUnused { key }));
             }
-            let block = self.load_block(block_number);
-            let start_index = (key.index % (Self::BLOCK_SIZE)) * self.data_size;
-            let data = (start_index..start_index + self.data_size)
-                .map(|i| block[i])
-                .collect();
-            Ok(ctor(data))
+            let start_index = self.block_start(block_number)
+                + Self::BITMAP_SIZE
+                + (key.index % (Self::BLOCK_SIZE)) * self.data_size;
+            let data = self
+                .extent
+                .as_slice(start_index..start_index + self.data_size);
+            Ok(ctor(data.into_owned()))
         }
     }
     fn get_number_blocks(&self) -> usize {
         (self.extent.len() - Self::HEADER_SIZE)
             / (Self::BITMAP_SIZE + Self::BLOCK_SIZE * self.data_size)
     }
-    fn load_bitmap(&self, block_number: usize) -> Vec<u8> {
-        let start = block_number * (Self::BLOCK_SIZE * self.data_size + Self::BITMAP_SIZE)
-            + Self::HEADER_SIZE;
-        (start..start + Self::BITMAP_SIZE)
-            .map(|i| self.extent[i].clone())
-            .collect()
-    }
-    fn load_block(&self, block_number: usize) -> Vec<u8> {
-        let start = block_number * (Self::BLOCK_SIZE * self.data_size + Self::BITMAP_SIZE)
-            + Self::HEADER_SIZE
-            + Self::BITMAP_SIZE;
-        (start..start + Self::BLOCK_SIZE * self.data_size)
-            .map(|i| self.extent[i].clone())
-            .collect()
+    /// Gets the offset of the bitmap of a block in the extent
+    fn block_start(&self, block_number: usize) -> usize {
+        block_number * (Self::BLOCK_SIZE * self.data_size + Self::BITMAP_SIZE) + Self::HEADER_SIZE
+    }
+    fn load_bitmap(&self, block_number: usize) -> Cow<'_, [u8]> {
+        let start = self.block_start(block_number);
+        self.extent.as_slice(start..start + Self::BITMAP_SIZE)
     }
 }
 //gets first 0 in bitmap if it exists
-fn get_first_0(bitmap: Vec<u8>) -> Option<usize> {
-    let mut index = 0;
-    for byte in bitmap.iter() {
-        if byte.clone() != u8::MAX {
+fn get_first_0(bitmap: &[u8]) -> Option<usize> {
+    for (index, byte) in bitmap.iter().enumerate() {
+        if *byte != u8::MAX {
             for i in 0..8 {
                 if byte & (1 << i) != (1 << i) {
                     return Some(index * 8 + i);
                 }
             }
         }
-        index += 1;
     }
     None
 }
@@ -184,12 +238,12 @@ mod tests {
     #[test]
     fn test_bitmap_one() {
         let arr = vec![0];
-        assert_eq!(get_first_0(arr), Some(0));
+        assert_eq!(get_first_0(&arr), Some(0));
     }
     #[test]
     fn test_bitmap_two() {
         let arr = vec![0b01];
-        assert_eq!(get_first_0(arr), Some(1));
+        assert_eq!(get_first_0(&arr), Some(1));
     }
     #[test]
     fn couple_inserts() {
@@ -197,7 +251,7 @@ mod tests {
         let drain = DrianableExtent::new(&mut e);
         let mut t = SizedTable::new(drain, 0usize.size() as usize).ok().unwrap();
         let k_v: Vec<(Key, usize)> = (0..10_000)
-            .map(|i| (t.insert(Box::new(i)).ok().unwrap(), i.clone()))
+            .map(|i| (t.insert(Box::new(i)).ok().unwrap(), i))
             .collect();
         for (key, value) in k_v.iter() {
             assert_eq!(
@@ -210,11 +264,36 @@ mod tests {
     fn recover() {
         let mut e = InMemoryExtent::new();
         let mut drain = DrianableExtent::new(&mut e);
-        let mut drain2 = drain.take();
+        let drain2 = drain.take();
         let mut t = SizedTable::new(drain, 0usize.size() as usize).ok().unwrap();
         let k = t.insert(Box::new(0usize)).ok().unwrap();
         assert_eq!(t.get(k.clone(), usize::from_binary).ok().unwrap(), 0);
-        let mut t2 = SizedTable::try_load(drain2).ok().unwrap();
+        let t2 = SizedTable::try_load(drain2).ok().unwrap();
         assert_eq!(t2.get(k, usize::from_binary).ok().unwrap(), 0);
     }
+    #[test]
+    fn upgrade() {
+        //version 0 table of one block holding 5 at index 0
+        let mut e = InMemoryExtent::new();
+        let data_size = 0usize.size() as usize;
+        let block = SizedTable::<InMemoryExtent>::BITMAP_SIZE
+            + SizedTable::<InMemoryExtent>::BLOCK_SIZE * data_size;
+        let mut old = vec![0; 8 + block];
+        old[..8].copy_from_slice(&data_size.to_le_bytes());
+        old[8] = 1;
+        old[8 + SizedTable::<InMemoryExtent>::BITMAP_SIZE] = 5;
+        e.resize(old.len()).ok().unwrap();
+        e.write_at(0, &old);
+        let error = SizedTable::try_load(DrianableExtent::new(&mut e))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<FormatError>(),
+            Some(FormatError::Outdated { found: 0, .. })
+        ));
+        assert!(SizedTable::upgrade(&mut e).ok().unwrap());
+        assert!(!SizedTable::upgrade(&mut e).ok().unwrap());
+        let t = SizedTable::try_load(e).ok().unwrap();
+        assert_eq!(t.get(Key { index: 0 }, usize::from_binary).ok().unwrap(), 5);
+    }
 }
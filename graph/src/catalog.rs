@@ -0,0 +1,270 @@
+use super::{Database, DatabseError, NodeStorage};
+use crate::table_manager::TableManager;
+use crate::wal::Operation;
+use std::collections::HashMap;
+use traits::{Extent, Insertable, InsertableDyn, Node, NodeElementHash, NodeHash};
+use variable_storage::{Key as VariableKey, VariableExtent};
+/// Layout of a node or edge type
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeEntry {
+    pub name: String,
+    /// Size of the rows of the type in node_contents and edge_contents
+    pub element_size: usize,
+    pub sized: Vec<NodeElementHash>,
+    pub variable: Vec<NodeElementHash>,
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldKind {
+    Sized,
+    Variable,
+}
+/// Layout of a field
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldEntry {
+    pub name: String,
+    pub kind: FieldKind,
+    /// Size of the field, 0 for variable fields
+    pub element_size: usize,
+}
+/// Persistent record of the types and fields stored in a database. Every entry of the
+/// `VariableExtent` is one record, records are never removed.
+///
+///|Size (bytes) | Description|
+///|-------------|------------|
+///|1|0 for a node, 1 for a sized field, 2 for a variable field|
+///|8|Hash|
+///|8|Element size|
+///|8|Length of name|
+///|Length of name|Name|
+///|8 + 8 * sized fields|Number then hashes of sized fields (nodes only)|
+///|8 + 8 * variable fields|Number then hashes of variable fields (nodes only)|
+pub struct Catalog<E: Extent> {
+    records: VariableExtent<E>,
+    nodes: HashMap<NodeHash, NodeEntry>,
+    fields: HashMap<NodeElementHash, FieldEntry>,
+}
+impl<E: Extent> Catalog<E> {
+    const NODE: u8 = 0;
+    const SIZED: u8 = 1;
+    const VARIABLE: u8 = 2;
+    /// Creates an empty catalog
+    pub fn new(records: VariableExtent<E>) -> Self {
+        Self {
+            records,
+            nodes: HashMap::new(),
+            fields: HashMap::new(),
+        }
+    }
+    /// Loads a catalog created by `new` from its extent
+    pub fn load(records: VariableExtent<E>) -> Self {
+        let mut catalog = Self::new(records);
+        let mut index = 0;
+        while catalog.records.contains_key(VariableKey { index }) {
+            catalog.parse(catalog.records.get_entry(VariableKey { index }));
+            index += 1;
+        }
+        catalog
+    }
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.records.flush()
+    }
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.records.verify()
+    }
+    pub fn node(&self, hash: &NodeHash) -> Option<&NodeEntry> {
+        self.nodes.get(hash)
+    }
+    pub fn field(&self, hash: &NodeElementHash) -> Option<&FieldEntry> {
+        self.fields.get(hash)
+    }
+    pub fn nodes(&self) -> impl Iterator<Item = (&NodeHash, &NodeEntry)> {
+        self.nodes.iter()
+    }
+    pub fn fields(&self) -> impl Iterator<Item = (&NodeElementHash, &FieldEntry)> {
+        self.fields.iter()
+    }
+    pub fn add_node(&mut self, hash: NodeHash, entry: NodeEntry) {
+        let mut record = Self::header(Self::NODE, hash.hash, entry.element_size, &entry.name);
+        for hashes in [&entry.sized, &entry.variable].iter() {
+            record.append(&mut hashes.len().to_binary());
+            for field in hashes.iter() {
+                record.append(&mut field.to_binary());
+            }
+        }
+        self.records.add_entry(record);
+        self.nodes.insert(hash, entry);
+    }
+    pub fn add_field(&mut self, hash: NodeElementHash, entry: FieldEntry) {
+        let kind = match entry.kind {
+            FieldKind::Sized => Self::SIZED,
+            FieldKind::Variable => Self::VARIABLE,
+        };
+        self.records.add_entry(Self::header(
+            kind,
+            hash.hash,
+            entry.element_size,
+            &entry.name,
+        ));
+        self.fields.insert(hash, entry);
+    }
+    fn header(kind: u8, hash: usize, element_size: usize, name: &str) -> Vec<u8> {
+        let mut record = vec![kind];
+        record.append(&mut hash.to_binary());
+        record.append(&mut element_size.to_binary());
+        record.append(&mut name.len().to_binary());
+        record.extend_from_slice(name.as_bytes());
+        record
+    }
+    fn parse(&mut self, record: Vec<u8>) {
+        let read_usize = |start: usize| usize::from_binary(record[start..start + 8].to_vec());
+        let hash = read_usize(1);
+        let element_size = read_usize(9);
+        let name_len = read_usize(17);
+        let name = String::from_utf8(record[25..25 + name_len].to_vec()).unwrap();
+        if record[0] == Self::NODE {
+            let mut start = 25 + name_len;
+            let mut read_hashes = || {
+                let len = read_usize(start);
+                let hashes = (0..len)
+                    .map(|i| NodeElementHash {
+                        hash: read_usize(start + 8 + i * 8),
+                    })
+                    .collect::<Vec<_>>();
+                start += 8 + len * 8;
+                hashes
+            };
+            let sized = read_hashes();
+            let variable = read_hashes();
+            self.nodes.insert(
+                NodeHash { hash },
+                NodeEntry {
+                    name,
+                    element_size,
+                    sized,
+                    variable,
+                },
+            );
+        } else {
+            let kind = if record[0] == Self::SIZED {
+                FieldKind::Sized
+            } else {
+                FieldKind::Variable
+            };
+            self.fields.insert(
+                NodeElementHash { hash },
+                FieldEntry {
+                    name,
+                    kind,
+                    element_size,
+                },
+            );
+        }
+    }
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Records the layout of `Data` in the catalog, or checks it against the recorded layout if
+    /// `Data` has been stored before. Types are registered when they are first stored, register
+    /// the types used after opening a database to refuse databases with a different layout.
+    ///
+    /// Types are only known to the calls that use them, so opening a database cannot check
+    /// them. `get` and `iter` find nothing for a type whose layout disagrees with the catalog
+    pub fn register<Data: Node>(&mut self) -> Result<(), DatabseError> {
+        let entry = Self::layout::<Data>();
+        match self.catalog.node(&Data::SELF_HASH) {
+            Some(recorded) if recorded == &entry => Ok(()),
+            Some(_) => Err(DatabseError::SchemaMismatch(Data::SELF_HASH)),
+            None => {
+                self.begin(Operation::Register(Data::SELF_HASH))?;
+                self.catalog.add_node(Data::SELF_HASH, entry);
+                self.commit();
+                Ok(())
+            }
+        }
+    }
+    /// Checks that the layout of `Data` agrees with the catalog. Types that are not in the
+    /// catalog agree
+    pub(crate) fn layout_matches<Data: Node>(&self) -> bool {
+        self.catalog
+            .node(&Data::SELF_HASH)
+            .is_none_or(|recorded| recorded == &Self::layout::<Data>())
+    }
+    fn layout<Data: Node>() -> NodeEntry {
+        NodeEntry {
+            name: Data::NAME.to_string(),
+            element_size: NodeStorage::row_size(
+                Data::get_sized_hashes().len(),
+                Data::get_variable_hashes().len(),
+            ),
+            sized: Data::get_sized_hashes(),
+            variable: Data::get_variable_hashes(),
+        }
+    }
+    /// Records a field of `Data` in the catalog when its table is made
+    pub(crate) fn catalog_field<Data: Node>(
+        &mut self,
+        hash: &NodeElementHash,
+        kind: FieldKind,
+        element_size: usize,
+    ) {
+        let (hashes, names) = match kind {
+            FieldKind::Sized => (Data::get_sized_hashes(), Data::get_sized_names()),
+            FieldKind::Variable => (Data::get_variable_hashes(), Data::get_variable_names()),
+        };
+        let path = hashes
+            .iter()
+            .zip(names)
+            .find(|(field, _)| *field == hash)
+            .map(|(_, name)| name)
+            .unwrap_or_default();
+        let name = if path.is_empty() {
+            Data::NAME.to_string()
+        } else {
+            format!("{}.{}", Data::NAME, path)
+        };
+        self.catalog.add_field(
+            hash.clone(),
+            FieldEntry {
+                name,
+                kind,
+                element_size,
+            },
+        );
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::{DrianableExtent, InMemoryExtent};
+    #[test]
+    fn reload() {
+        let node = NodeEntry {
+            name: "Person".to_string(),
+            element_size: 56,
+            sized: vec![NodeElementHash { hash: 1 }],
+            variable: vec![NodeElementHash { hash: 2 }],
+        };
+        let age = FieldEntry {
+            name: "Person.age".to_string(),
+            kind: FieldKind::Sized,
+            element_size: 8,
+        };
+        let name = FieldEntry {
+            name: "Person.name".to_string(),
+            kind: FieldKind::Variable,
+            element_size: 0,
+        };
+        let mut extent = InMemoryExtent::new();
+        {
+            let mut catalog = Catalog::new(VariableExtent::new(DrianableExtent::new(&mut extent)));
+            catalog.add_node(NodeHash { hash: 5 }, node.clone());
+            catalog.add_field(NodeElementHash { hash: 1 }, age.clone());
+            catalog.add_field(NodeElementHash { hash: 2 }, name.clone());
+        }
+        let catalog = Catalog::load(VariableExtent::load(extent).unwrap());
+        assert_eq!(catalog.node(&NodeHash { hash: 5 }), Some(&node));
+        assert_eq!(catalog.field(&NodeElementHash { hash: 1 }), Some(&age));
+        assert_eq!(catalog.field(&NodeElementHash { hash: 2 }), Some(&name));
+        assert_eq!(catalog.nodes().count(), 1);
+        assert_eq!(catalog.fields().count(), 2);
+    }
+}
@@ -0,0 +1,338 @@
+use super::{field_values, Database, DatabseError, Key, NodeStorage};
+use crate::table_manager::TableManager;
+use crate::wal::Operation;
+use anyhow::anyhow;
+use traits::{Extent, FormatHeader, Insertable, InsertableDyn, Node, NodeElementHash};
+use variable_storage::{Key as VariableKey, VariableExtent};
+/// Persistent hash index from the bytes of a field value to the keys of the nodes holding that
+/// value.
+///
+/// The first entry of the `VariableExtent` is the header and the second lists the keys of the
+/// buckets, every other entry is a bucket. The bucket of a value is picked with FNV-1a so that
+/// it is stable between runs. The number of buckets doubles once they hold more than
+/// `MAX_LOAD` records on average, so that a bucket rewritten on insert stays small.
+///
+/// Header:
+///
+///|Size (bytes) | Description|
+///|-------------|------------|
+///|16|`FormatHeader`|
+///|8|Number of records|
+///
+/// Buckets hold a list of records:
+///
+///|Size (bytes) | Description|
+///|-------------|------------|
+///|8|Length of value|
+///|Length of value|Value|
+///|8|Key of node in node_storage|
+pub struct HashIndex<E: Extent> {
+    buckets: VariableExtent<E>,
+    //keys of the buckets, loaded from the second entry
+    directory: Vec<VariableKey>,
+    records: usize,
+}
+impl<E: Extent> HashIndex<E> {
+    const MAGIC: [u8; 4] = *b"HIDX";
+    const VERSION: u32 = 1;
+    const HEADER: VariableKey = VariableKey { index: 0 };
+    const DIRECTORY: VariableKey = VariableKey { index: 1 };
+    const INITIAL_BUCKETS: usize = 16;
+    /// Average number of records in a bucket before the number of buckets doubles
+    const MAX_LOAD: usize = 8;
+    /// Creates an empty index
+    pub fn new(mut buckets: VariableExtent<E>) -> Self {
+        buckets.add_entry(Self::header(0));
+        buckets.add_entry(vec![]);
+        let directory = (0..Self::INITIAL_BUCKETS)
+            .map(|_| buckets.add_entry(vec![]))
+            .collect();
+        let mut index = Self {
+            buckets,
+            directory,
+            records: 0,
+        };
+        index.write_directory();
+        index
+    }
+    /// Loads an index created by `new` from its extent
+    pub fn load(buckets: VariableExtent<E>) -> anyhow::Result<Self> {
+        let header = buckets.get_entry(Self::HEADER);
+        FormatHeader::parse(&header, Self::MAGIC)
+            .ok_or_else(|| anyhow!("hash index does not start with a header"))?
+            .check("hash index", Self::VERSION, 0)?;
+        let records = usize::from_binary(
+            header[FormatHeader::SIZE..FormatHeader::SIZE + usize::SIZE].to_vec(),
+        );
+        let directory = buckets
+            .get_entry(Self::DIRECTORY)
+            .chunks(VariableKey::SIZE)
+            .map(|key| VariableKey::from_binary(key.to_vec()))
+            .collect();
+        Ok(Self {
+            buckets,
+            directory,
+            records,
+        })
+    }
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.buckets.flush()
+    }
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.buckets.verify()
+    }
+    pub fn insert(&mut self, value: &[u8], key: VariableKey) {
+        if self.records + 1 > self.directory.len() * Self::MAX_LOAD {
+            self.grow();
+        }
+        let bucket = self.bucket(value);
+        let mut records = self.buckets.get_entry(bucket.clone());
+        records.append(&mut encode_record(value, &key));
+        self.buckets.write_entry(bucket, 0, records);
+        self.set_records(self.records + 1);
+    }
+    /// Removes the record of key holding value
+    pub fn remove(&mut self, value: &[u8], key: &VariableKey) {
+        let bucket = self.bucket(value);
+        let records = parse_records(self.buckets.get_entry(bucket.clone()));
+        let before = records.len();
+        let records: Vec<(Vec<u8>, VariableKey)> = records
+            .into_iter()
+            .filter(|(record_value, record_key)| record_value != value || record_key != key)
+            .collect();
+        if records.len() == before {
+            return;
+        }
+        let removed = before - records.len();
+        self.buckets
+            .write_entry(bucket, 0, encode_records(&records));
+        self.set_records(self.records - removed);
+    }
+    /// Gets the keys of every node holding value
+    pub fn get(&self, value: &[u8]) -> Vec<VariableKey> {
+        parse_records(self.buckets.get_entry(self.bucket(value)))
+            .into_iter()
+            .filter(|(record_value, _)| record_value == value)
+            .map(|(_, key)| key)
+            .collect()
+    }
+    /// Moves every record into twice as many buckets
+    fn grow(&mut self) {
+        let records: Vec<(Vec<u8>, VariableKey)> = self
+            .directory
+            .iter()
+            .flat_map(|bucket| parse_records(self.buckets.get_entry(bucket.clone())))
+            .collect();
+        for bucket in self.directory.iter() {
+            self.buckets.remove_entry(bucket.clone());
+        }
+        let number_buckets = self.directory.len() * 2;
+        let mut contents = vec![vec![]; number_buckets];
+        for (value, key) in records.iter() {
+            contents[bucket_index(value, number_buckets)].append(&mut encode_record(value, key));
+        }
+        self.directory = contents
+            .into_iter()
+            .map(|bucket| self.buckets.add_entry(bucket))
+            .collect();
+        self.write_directory();
+        self.set_records(records.len());
+    }
+    fn bucket(&self, value: &[u8]) -> VariableKey {
+        self.directory[bucket_index(value, self.directory.len())].clone()
+    }
+    fn set_records(&mut self, records: usize) {
+        self.records = records;
+        self.buckets
+            .write_entry(Self::HEADER, 0, Self::header(records));
+    }
+    fn write_directory(&mut self) {
+        let directory = self
+            .directory
+            .iter()
+            .flat_map(|bucket| bucket.to_binary())
+            .collect();
+        self.buckets.write_entry(Self::DIRECTORY, 0, directory);
+    }
+    fn header(records: usize) -> Vec<u8> {
+        let mut header = FormatHeader::new(Self::MAGIC, Self::VERSION, 0)
+            .to_binary()
+            .to_vec();
+        header.append(&mut records.to_binary());
+        header
+    }
+}
+/// Picks the bucket of value out of number_buckets with FNV-1a
+fn bucket_index(value: &[u8], number_buckets: usize) -> usize {
+    let hash = value.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3)
+    });
+    hash as usize % number_buckets
+}
+fn encode_record(value: &[u8], key: &VariableKey) -> Vec<u8> {
+    let mut buffer = value.len().to_binary();
+    buffer.extend_from_slice(value);
+    buffer.append(&mut key.to_binary());
+    buffer
+}
+fn encode_records(records: &[(Vec<u8>, VariableKey)]) -> Vec<u8> {
+    records
+        .iter()
+        .flat_map(|(value, key)| encode_record(value, key))
+        .collect()
+}
+fn parse_records(data: Vec<u8>) -> Vec<(Vec<u8>, VariableKey)> {
+    let mut records = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let value_len = usize::from_binary(data[start..start + usize::SIZE].to_vec());
+        let value_start = start + usize::SIZE;
+        let key_start = value_start + value_len;
+        records.push((
+            data[value_start..key_start].to_vec(),
+            VariableKey::from_binary(data[key_start..key_start + VariableKey::SIZE].to_vec()),
+        ));
+        start = key_start + VariableKey::SIZE;
+    }
+    records
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Creates an index on a field of `Data` so that `find_by` does not have to scan every node.
+    /// field is one of the hashes returned by `Data::get_sized_hashes` or
+    /// `Data::get_variable_hashes`
+    pub fn create_index<Data: Node>(&mut self, field: NodeElementHash) -> Result<(), DatabseError> {
+        if !Data::get_sized_hashes().contains(&field)
+            && !Data::get_variable_hashes().contains(&field)
+        {
+            return Err(DatabseError::InvalidField(field));
+        }
+        if self.indexes.contains_key(&field) {
+            return Ok(());
+        }
+        self.begin(Operation::CreateIndex(field.clone()))?;
+        let mut index = HashIndex::new(self.table_manager.get_index(field.clone()));
+        for data_locations in self.rows(&Data::SELF_HASH) {
+            if let Some(value) = self.stored_field(&data_locations, &field) {
+                index.insert(&value, data_locations.owner);
+            }
+        }
+        self.indexes.insert(field, index);
+        self.commit();
+        Ok(())
+    }
+    /// Finds every node of type `Data` whose field holds the value. Uses the index on the field
+    /// if one was made with `create_index`, otherwise every node of type `Data` is scanned
+    pub fn find_by<Data: Node, Value: Node>(
+        &self,
+        field: NodeElementHash,
+        value: Value,
+    ) -> Vec<Key> {
+        let value = match field_values(&value).into_iter().next() {
+            Some((_, value)) => value,
+            None => return vec![],
+        };
+        match self.indexes.get(&field) {
+            Some(index) => index
+                .get(&value)
+                .into_iter()
+                .map(|key| Key { key })
+                .collect(),
+            None => self
+                .rows(&Data::SELF_HASH)
+                .filter(|data_locations| {
+                    self.stored_field(data_locations, &field).as_ref() == Some(&value)
+                })
+                .map(|data_locations| Key {
+                    key: data_locations.owner,
+                })
+                .collect(),
+        }
+    }
+    /// Adds the indexed fields in values to their hash and ordered indexes
+    pub(crate) fn add_to_indexes(&mut self, key: &Key, values: Vec<(NodeElementHash, Vec<u8>)>) {
+        for (field, value) in values {
+            if let Some(index) = self.indexes.get_mut(&field) {
+                index.insert(&value, key.key.clone());
+            }
+            if let Some(index) = self.ordered_indexes.get_mut(&field) {
+                index.insert(&value, &key.key);
+            }
+        }
+    }
+    /// Removes the stored values of the indexed fields of a node from their indexes
+    pub(crate) fn remove_from_indexes(&mut self, key: &Key, data_locations: &NodeStorage) {
+        let values: Vec<(NodeElementHash, Vec<u8>)> = self
+            .indexes
+            .keys()
+            .chain(self.ordered_indexes.keys())
+            .filter_map(|field| {
+                self.stored_field(data_locations, field)
+                    .map(|value| (field.clone(), value))
+            })
+            .collect();
+        for (field, value) in values {
+            if let Some(index) = self.indexes.get_mut(&field) {
+                index.remove(&value, &key.key);
+            }
+            if let Some(index) = self.ordered_indexes.get_mut(&field) {
+                index.remove(&value, &key.key);
+            }
+        }
+    }
+    /// Reads the stored bytes of a field
+    pub(crate) fn stored_field(
+        &self,
+        data_locations: &NodeStorage,
+        field: &NodeElementHash,
+    ) -> Option<Vec<u8>> {
+        if let Some((hash, key)) = data_locations
+            .node_static_sized_keys
+            .iter()
+            .find(|(hash, _)| hash == field)
+        {
+            return self.sized[hash].get(key.clone(), |d| d).ok();
+        }
+        data_locations
+            .node_dynamic_sized_keys
+            .iter()
+            .find(|(hash, _)| hash == field)
+            .map(|(hash, key)| self.variable[hash].get_entry(key.clone()))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::{DrianableExtent, InMemoryExtent};
+    #[test]
+    fn insert_and_remove() {
+        let mut index = HashIndex::new(VariableExtent::new(InMemoryExtent::new()));
+        let values: Vec<Vec<u8>> = (0..1000usize).map(|i| (i % 10).to_binary()).collect();
+        for (i, value) in values.iter().enumerate() {
+            index.insert(value, VariableKey { index: i });
+        }
+        assert_eq!(index.get(&values[3]).len(), 100);
+        assert!(index.get(&values[3]).contains(&VariableKey { index: 13 }));
+        index.remove(&values[3], &VariableKey { index: 13 });
+        assert_eq!(index.get(&values[3]).len(), 99);
+        assert!(!index.get(&values[3]).contains(&VariableKey { index: 13 }));
+        assert_eq!(index.get(&[]).len(), 0);
+    }
+    #[test]
+    fn grow() {
+        let mut extent = InMemoryExtent::new();
+        {
+            let mut index = HashIndex::new(VariableExtent::new(DrianableExtent::new(&mut extent)));
+            for i in 0..1000usize {
+                index.insert(&i.to_binary(), VariableKey { index: i });
+            }
+            assert!(index.directory.len() * HashIndex::<InMemoryExtent>::MAX_LOAD >= 1000);
+            index.remove(&5usize.to_binary(), &VariableKey { index: 5 });
+        }
+        let index = HashIndex::load(VariableExtent::load(extent).unwrap()).unwrap();
+        assert_eq!(index.records, 999);
+        assert_eq!(index.get(&5usize.to_binary()).len(), 0);
+        for i in (0..1000usize).filter(|i| *i != 5) {
+            assert_eq!(index.get(&i.to_binary()), vec![VariableKey { index: i }]);
+        }
+    }
+}
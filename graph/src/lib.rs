@@ -1,93 +1,151 @@
 #[macro_use]
 extern crate anyhow;
 use anyhow::Result;
+mod catalog;
+mod index;
+mod ordered;
+mod path;
+mod shared;
 mod table_manager;
+mod transaction;
+mod traversal;
+mod wal;
+use catalog::{Catalog, FieldKind};
+pub use file_extent::{FileExtent, PagedFileExtent};
+use index::HashIndex;
+use ordered::OrderedIndex;
+pub use shared::{SharedDatabase, Snapshot};
 use std::collections::HashMap;
+use std::path::Path;
 use table::{DatabaseTable, Key as TableKey};
-use table_manager::{InMemoryManager, TableManager};
-use traits::{
-    InMemoryExtent, Insertable, InsertableDyn, Node, NodeElementHash, NodeHash, VariableSizeInsert,
+use table_manager::TableManager;
+pub use table_manager::{BackedManager, ContainerManager, InMemoryManager};
+pub use traits::{
+    CachedExtent, ChecksumError, ChecksummedExtent, CompressedExtent, CompressedExtentError,
+    EncryptedExtent, EncryptedExtentError, FormatError,
 };
+use traits::{Insertable, InsertableDyn, Node, NodeElementHash, NodeHash, VariableSizeInsert};
+pub use transaction::Transaction;
+pub use traversal::Traversal;
+use wal::Operation;
 pub mod prelude {
     pub use traits::{
-        Insertable, InsertableDyn, Node, NodeElementHash, NodeHash, VariableSizeInsert,
+        Insertable, InsertableDyn, Node, NodeElementHash, NodeHash, Ordered, VariableSizeInsert,
     };
 }
 use variable_storage::{Key as VariableKey, VariableExtent};
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Key {
     key: VariableKey,
 }
+/// A link to another node. Links with a label carry an edge payload stored in edge_contents
+#[derive(Clone, PartialEq)]
+struct Link {
+    //node on the other end of the link
+    node: VariableKey,
+    //hash of the edge payload type
+    label: NodeHash,
+    //location of the edge payload members in edge_contents
+    members: TableKey,
+}
+impl Link {
+    //label of links without a payload
+    const NO_LABEL: NodeHash = NodeHash { hash: 0 };
+    const SIZE: usize = VariableKey::SIZE + NodeHash::SIZE + TableKey::SIZE;
+    fn to_binary(&self) -> Vec<u8> {
+        let mut buffer = self.node.to_binary();
+        buffer.append(&mut self.label.to_binary());
+        buffer.append(&mut self.members.to_binary());
+        buffer
+    }
+    fn from_binary(data: &[u8]) -> Self {
+        Self {
+            node: VariableKey::from_binary(data[0..VariableKey::SIZE].to_vec()),
+            label: NodeHash::from_binary(
+                data[VariableKey::SIZE..VariableKey::SIZE + NodeHash::SIZE].to_vec(),
+            ),
+            members: TableKey::from_binary(
+                data[VariableKey::SIZE + NodeHash::SIZE..Self::SIZE].to_vec(),
+            ),
+        }
+    }
+}
 #[derive(Clone)]
 struct NodeKeyStorage {
     //link to self members in node_contents keys
     self_members: TableKey,
     //hash of self
     self_hash: NodeHash,
-    //Links
-    linked_nodes: Vec<VariableKey>,
+    //Links leaving this node
+    outgoing: Vec<Link>,
+    //Links pointing at this node
+    incoming: Vec<Link>,
 }
 impl VariableSizeInsert for NodeKeyStorage {
     fn get_data_variable(&self) -> Vec<u8> {
         let mut buffer = self.self_members.to_binary();
         buffer.append(&mut self.self_hash.to_binary());
-        for key in self.linked_nodes.iter() {
-            buffer.append(&mut key.to_binary());
+        buffer.append(&mut self.outgoing.len().to_binary());
+        for link in self.outgoing.iter().chain(self.incoming.iter()) {
+            buffer.append(&mut link.to_binary());
         }
         buffer
     }
 }
 impl NodeKeyStorage {
+    const HEADER_SIZE: usize = TableKey::SIZE + NodeHash::SIZE + usize::SIZE;
     fn from_binary(data: Vec<u8>) -> Self {
-        let num_keys = (data.len() - TableKey::SIZE - NodeHash::SIZE) / VariableKey::SIZE;
+        let num_links = (data.len() - Self::HEADER_SIZE) / Link::SIZE;
         let self_members = TableKey::from_binary(data.clone());
-        let self_hash = NodeHash::from_binary(
-            data.clone()[TableKey::SIZE..TableKey::SIZE + NodeHash::SIZE].to_vec(),
-        );
-        let linked_nodes = (0..num_keys)
+        let self_hash =
+            NodeHash::from_binary(data[TableKey::SIZE..TableKey::SIZE + NodeHash::SIZE].to_vec());
+        let num_outgoing =
+            usize::from_binary(data[TableKey::SIZE + NodeHash::SIZE..Self::HEADER_SIZE].to_vec());
+        let mut outgoing: Vec<Link> = (0..num_links)
             .map(|i| {
-                VariableKey::from_binary(
-                    data.clone()[i * VariableKey::SIZE + TableKey::SIZE + NodeHash::SIZE
-                        ..i * VariableKey::SIZE
-                            + TableKey::SIZE
-                            + VariableKey::SIZE
-                            + NodeHash::SIZE]
-                        .to_vec(),
+                Link::from_binary(
+                    &data[Self::HEADER_SIZE + i * Link::SIZE
+                        ..Self::HEADER_SIZE + (i + 1) * Link::SIZE],
                 )
             })
             .collect();
+        let incoming = outgoing.split_off(num_outgoing);
         Self {
             self_members,
             self_hash,
-            linked_nodes,
+            outgoing,
+            incoming,
         }
     }
 }
 #[derive(Clone, Debug, PartialEq)]
 struct NodeStorage {
+    //key of the node owning the row in node_storage. For edge payloads this is the node the
+    //edge leaves from
+    owner: VariableKey,
     node_static_sized_keys: Vec<(NodeElementHash, TableKey)>,
     node_dynamic_sized_keys: Vec<(NodeElementHash, VariableKey)>,
 }
 unsafe impl InsertableDyn for NodeStorage {
     fn size(&self) -> u32 {
-        let static_size = if self.node_static_sized_keys.len() > 0 {
+        let static_size = if !self.node_static_sized_keys.is_empty() {
             self.node_static_sized_keys.len() as u32
                 * (self.node_static_sized_keys[0].0.size()
                     + self.node_static_sized_keys[0].1.size())
         } else {
             0
         };
-        let variable_size = if self.node_dynamic_sized_keys.len() > 0 {
+        let variable_size = if !self.node_dynamic_sized_keys.is_empty() {
             self.node_dynamic_sized_keys.len() as u32
                 * (self.node_dynamic_sized_keys[0].0.size()
                     + self.node_dynamic_sized_keys[0].1.size())
         } else {
             0
         };
-        static_size + variable_size + 8 + 8
+        self.owner.size() + static_size + variable_size + 8 + 8
     }
     fn to_binary(&self) -> Vec<u8> {
-        let mut buffer = vec![];
+        let mut buffer = self.owner.to_binary();
         buffer.append(&mut self.node_static_sized_keys.len().to_le_bytes().to_vec());
         for (hash, key) in self.node_static_sized_keys.iter() {
             buffer.append(&mut hash.clone().to_binary());
@@ -102,7 +160,16 @@ unsafe impl InsertableDyn for NodeStorage {
     }
 }
 impl NodeStorage {
+    /// Size of the rows of a type with the given number of sized and variable fields
+    fn row_size(sized: usize, variable: usize) -> usize {
+        VariableKey::SIZE
+            + 2 * usize::SIZE
+            + sized * (NodeElementHash::SIZE + TableKey::SIZE)
+            + variable * (NodeElementHash::SIZE + VariableKey::SIZE)
+    }
     fn from_binary(d: Vec<u8>) -> Self {
+        let owner = VariableKey::from_binary(d[0..VariableKey::SIZE].to_vec());
+        let d = d[VariableKey::SIZE..].to_vec();
         let sized_len = usize::from_le_bytes([d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]]);
         let node_static_size = NodeElementHash::SIZE + TableKey::SIZE;
         let node_static_sized_keys = (0..sized_len)
@@ -128,7 +195,7 @@ impl NodeStorage {
 
         let sized_size = sized_len * (NodeElementHash::SIZE + TableKey::SIZE) + usize::SIZE;
         let unsized_len = usize::from_le_bytes([
-            d[sized_size + 0],
+            d[sized_size],
             d[sized_size + 1],
             d[sized_size + 2],
             d[sized_size + 3],
@@ -160,6 +227,7 @@ impl NodeStorage {
             })
             .collect();
         Self {
+            owner,
             node_static_sized_keys,
             node_dynamic_sized_keys,
         }
@@ -167,6 +235,37 @@ impl NodeStorage {
 }
 pub enum DatabseError {
     InvalidKey(Key),
+    /// The node stored at the key is not of the requested type
+    TypeMismatch(Key),
+    /// The hash is not a field of the requested type
+    InvalidField(NodeElementHash),
+    /// The fields of the type disagree with the fields recorded in the catalog
+    SchemaMismatch(NodeHash),
+    /// The database was opened read only
+    ReadOnly,
+}
+/// When a database flushes its tables to disk. Operations that have not been flushed are rolled
+/// back if the process stops before the next flush. Flushing writes every table and syncs it, so
+/// it costs far more than the operation itself. The default flushes every
+/// `SyncPolicy::DEFAULT_BATCH` operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Flush after every operation. Every operation survives a crash once it returns, at the
+    /// cost of a flush of every table per operation
+    EveryOperation,
+    /// Flush after every n operations
+    EveryN(usize),
+    /// Only flush when `Database::flush` is called or the database is dropped
+    Manual,
+}
+impl SyncPolicy {
+    /// Number of operations between flushes with the default policy
+    pub const DEFAULT_BATCH: usize = 1000;
+}
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        SyncPolicy::EveryN(Self::DEFAULT_BATCH)
+    }
 }
 pub struct Database<Manager: TableManager> {
     table_manager: Manager,
@@ -174,9 +273,22 @@ pub struct Database<Manager: TableManager> {
     node_storage: VariableExtent<Manager::ExtentType>,
     //Listing of location of data members of node
     node_contents: HashMap<NodeHash, DatabaseTable<Manager::ExtentType>>,
+    //Listing of location of data members of edge payloads
+    edge_contents: HashMap<NodeHash, DatabaseTable<Manager::ExtentType>>,
     //For elements with a variable size
     variable: HashMap<NodeElementHash, VariableExtent<Manager::ExtentType>>,
     sized: HashMap<NodeElementHash, DatabaseTable<Manager::ExtentType>>, //For elements with a fixed size
+    //Indexes from field values to the nodes holding them
+    indexes: HashMap<NodeElementHash, HashIndex<Manager::ExtentType>>,
+    //Ordered indexes from sized field values to the nodes holding them
+    ordered_indexes: HashMap<NodeElementHash, OrderedIndex<Manager::ExtentType>>,
+    //Names and layouts of the stored types and fields
+    catalog: Catalog<Manager::ExtentType>,
+    sync_policy: SyncPolicy,
+    //operations finished since the last flush
+    unsynced: usize,
+    //number of operations currently running, operations may nest
+    depth: usize,
 }
 impl<Manager: TableManager> Database<Manager> {
     pub fn new(mut table_manager: Manager) -> Result<Self> {
@@ -185,11 +297,150 @@ impl<Manager: TableManager> Database<Manager> {
             table_manager,
             node_storage: startup.node_storage,
             node_contents: startup.node_contents,
+            edge_contents: startup.edge_contents,
             variable: startup.variable,
             sized: startup.sized,
+            indexes: startup
+                .indexes
+                .into_iter()
+                .map(|(hash, buckets)| Ok((hash, HashIndex::load(buckets)?)))
+                .collect::<Result<_>>()?,
+            ordered_indexes: startup
+                .ordered_indexes
+                .into_iter()
+                .map(|(hash, tree)| (hash, OrderedIndex::load(tree)))
+                .collect(),
+            catalog: startup.catalog,
+            sync_policy: SyncPolicy::default(),
+            unsynced: 0,
+            depth: 0,
         })
     }
-    pub fn insert<Data: Node>(&mut self, data: Data) -> Key {
+    pub fn set_sync_policy(&mut self, sync_policy: SyncPolicy) {
+        self.sync_policy = sync_policy;
+    }
+    /// Writes every table to disk. Once this returns the operations made so far survive a crash
+    pub fn flush(&mut self) -> Result<()> {
+        self.node_storage.flush()?;
+        for table in self
+            .node_contents
+            .values_mut()
+            .chain(self.edge_contents.values_mut())
+            .chain(self.sized.values_mut())
+        {
+            table.flush()?;
+        }
+        for table in self.variable.values_mut() {
+            table.flush()?;
+        }
+        for index in self.indexes.values_mut() {
+            index.flush()?;
+        }
+        for index in self.ordered_indexes.values_mut() {
+            index.flush()?;
+        }
+        self.catalog.flush()?;
+        self.table_manager.checkpoint()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+    /// Checks every table for damage. Only tables stored with checksums, such as tables in a
+    /// `ChecksummedExtent`, can find any. The error names the damaged file and offset
+    pub fn verify(&self) -> Result<()> {
+        self.node_storage.verify()?;
+        for table in self
+            .node_contents
+            .values()
+            .chain(self.edge_contents.values())
+            .chain(self.sized.values())
+        {
+            table.verify()?;
+        }
+        for table in self.variable.values() {
+            table.verify()?;
+        }
+        for index in self.indexes.values() {
+            index.verify()?;
+        }
+        for index in self.ordered_indexes.values() {
+            index.verify()?;
+        }
+        self.catalog.verify()
+    }
+    /// Fails with `SchemaMismatch` if the layout of `Data` disagrees with the catalog, see
+    /// `register`
+    pub fn insert<Data: Node>(&mut self, data: Data) -> Result<Key, DatabseError> {
+        self.insert_noting(data, &mut vec![])
+    }
+    /// Inserts a node and pushes its key to inserted as soon as the key exists, so that the
+    /// node can still be found if the insert is cut off by a panic
+    fn insert_noting<Data: Node>(
+        &mut self,
+        data: Data,
+        inserted: &mut Vec<Key>,
+    ) -> Result<Key, DatabseError> {
+        self.register::<Data>()?;
+        self.begin(Operation::Insert(Data::SELF_HASH))?;
+        //the owner is filled in once the node has a key
+        let mut node = self.insert_members(&data, VariableKey { index: 0 });
+        if !self.node_contents.contains_key(&Data::SELF_HASH) {
+            self.node_contents.insert(
+                Data::SELF_HASH,
+                self.table_manager
+                    .get_node_contents(Data::SELF_HASH, node.size() as usize),
+            );
+        }
+        let contents = self.node_contents.get_mut(&Data::SELF_HASH).unwrap();
+        let self_members = contents.insert(node.clone()).ok().unwrap();
+        let node_keys = NodeKeyStorage {
+            self_members: self_members.clone(),
+            self_hash: Data::SELF_HASH,
+            outgoing: vec![],
+            incoming: vec![],
+        };
+        node.owner = self.node_storage.add_entry(node_keys.get_data_variable());
+        let key = Key {
+            key: node.owner.clone(),
+        };
+        inserted.push(key.clone());
+        contents.write(self_members, node).ok().unwrap();
+        self.add_to_indexes(&key, field_values(&data));
+        self.commit();
+        Ok(key)
+    }
+    /// Starts an operation on the tables, see `TableManager::begin`. Fails with `ReadOnly` if
+    /// the database was opened read only
+    fn begin(&mut self, operation: Operation) -> Result<(), DatabseError> {
+        if self.table_manager.read_only() {
+            return Err(DatabseError::ReadOnly);
+        }
+        if let Err(error) = self.table_manager.begin(operation) {
+            panic!("failed to start an operation: {}", error);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    /// Finishes an operation and flushes the tables if the sync policy asks for it
+    fn commit(&mut self) {
+        if let Err(error) = self.table_manager.commit() {
+            panic!("failed to write to the log: {}", error);
+        }
+        self.depth -= 1;
+        if self.depth > 0 {
+            return;
+        }
+        self.unsynced += 1;
+        let flush = match self.sync_policy {
+            SyncPolicy::EveryOperation => true,
+            SyncPolicy::EveryN(n) => self.unsynced >= n,
+            SyncPolicy::Manual => false,
+        };
+        if flush {
+            self.flush().expect("failed to flush the database");
+        }
+    }
+    /// Inserts the sized and variable members of a node or edge into their tables
+    fn insert_members<Data: Node>(&mut self, data: &Data, owner: VariableKey) -> NodeStorage {
         let (sized_data_vec, unsized_data_vec) = data.get_data();
         let node_static_sized_keys: Vec<(NodeElementHash, TableKey)> = sized_data_vec
             .iter()
@@ -209,6 +460,7 @@ impl<Manager: TableManager> Database<Manager> {
                         self.table_manager
                             .get_sized(hash.clone(), data.size() as usize),
                     );
+                    self.catalog_field::<Data>(hash, FieldKind::Sized, data.size() as usize);
                     (
                         hash.clone(),
                         self.sized.get_mut(hash).unwrap().insert(data).ok().unwrap(),
@@ -230,6 +482,7 @@ impl<Manager: TableManager> Database<Manager> {
                 } else {
                     self.variable
                         .insert(hash.clone(), self.table_manager.get_variable(hash.clone()));
+                    self.catalog_field::<Data>(hash, FieldKind::Variable, 0);
                     (
                         hash.clone(),
                         self.variable
@@ -240,62 +493,14 @@ impl<Manager: TableManager> Database<Manager> {
                 }
             })
             .collect();
-        let node = NodeStorage {
+        NodeStorage {
+            owner,
             node_static_sized_keys,
             node_dynamic_sized_keys,
-        };
-        if !self.node_contents.contains_key(&Data::SELF_HASH) {
-            self.node_contents.insert(
-                Data::SELF_HASH,
-                self.table_manager
-                    .get_node_contents(Data::SELF_HASH, node.size() as usize),
-            );
-        }
-        let key = self
-            .node_contents
-            .get_mut(&Data::SELF_HASH)
-            .unwrap()
-            .insert(node);
-        let node_keys = NodeKeyStorage {
-            self_members: key.ok().unwrap(),
-            self_hash: Data::SELF_HASH,
-            //Links
-            linked_nodes: vec![],
-        };
-        Key {
-            key: self.node_storage.add_entry(node_keys.get_data_variable()),
         }
     }
-    pub fn connect(&mut self, key1: Key, key2: Key) -> Result<(), DatabseError> {
-        if !self.node_storage.contains_key(key1.clone().key) {
-            return Err(DatabseError::InvalidKey(key1));
-        }
-        if !self.node_storage.contains_key(key2.clone().key) {
-            return Err(DatabseError::InvalidKey(key2));
-        }
-        let mut k1_data = self.node_storage.get_entry(key1.clone().key);
-        k1_data.append(&mut key2.key.to_binary());
-        self.node_storage.write_entry(key1.clone().key, 0, k1_data);
-        let mut k2_data = self.node_storage.get_entry(key2.clone().key);
-        k2_data.append(&mut key1.key.to_binary());
-        self.node_storage.write_entry(key2.key, 0, k2_data);
-        Ok(())
-    }
-    pub fn get_connected(&self, key: Key) -> Vec<Key> {
-        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.key));
-        return data
-            .linked_nodes
-            .iter()
-            .map(|key| Key { key: key.clone() })
-            .collect();
-    }
-    pub fn get<Data: Node>(&self, key: Key) -> Option<Data> {
-        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.key));
-        let data_locations = self.node_contents[&data.self_hash]
-            .get(data.self_members, NodeStorage::from_binary)
-            .ok()
-            .unwrap();
-
+    /// Loads the members of a node or edge from their tables
+    fn load_members<Data: Node>(&self, data_locations: NodeStorage) -> Data {
         let variable = data_locations
             .node_dynamic_sized_keys
             .iter()
@@ -311,19 +516,404 @@ impl<Manager: TableManager> Database<Manager> {
                 )
             })
             .collect();
-        Some(Data::from_data(sized, variable))
+        Data::from_data(sized, variable)
+    }
+    /// Frees the members of a node or edge in their tables
+    fn remove_members(&mut self, data_locations: NodeStorage) {
+        for (hash, table_key) in data_locations.node_static_sized_keys {
+            self.sized
+                .get_mut(&hash)
+                .unwrap()
+                .remove(table_key)
+                .ok()
+                .unwrap();
+        }
+        for (hash, variable_key) in data_locations.node_dynamic_sized_keys {
+            self.variable
+                .get_mut(&hash)
+                .unwrap()
+                .remove_entry(variable_key);
+        }
+    }
+    /// Links two nodes in both directions
+    pub fn connect(&mut self, key1: Key, key2: Key) -> Result<(), DatabseError> {
+        self.begin(Operation::Connect(key1.clone(), key2.clone()))?;
+        let result = self
+            .connect_directed(key1.clone(), key2.clone())
+            .and_then(|_| self.connect_directed(key2, key1));
+        self.commit();
+        result
+    }
+    /// Adds a link going from `from` to `to`
+    pub fn connect_directed(&mut self, from: Key, to: Key) -> Result<(), DatabseError> {
+        if !self.node_storage.contains_key(from.clone().key) {
+            return Err(DatabseError::InvalidKey(from));
+        }
+        if !self.node_storage.contains_key(to.clone().key) {
+            return Err(DatabseError::InvalidKey(to));
+        }
+        self.begin(Operation::Connect(from.clone(), to.clone()))?;
+        self.add_link(from, to, Link::NO_LABEL, TableKey { index: 0 });
+        self.commit();
+        Ok(())
+    }
+    /// Adds a link going from `from` to `to` that carries edge as its payload. The link is
+    /// labelled with `Edge::SELF_HASH`
+    pub fn connect_with<Edge: Node>(
+        &mut self,
+        from: Key,
+        to: Key,
+        edge: Edge,
+    ) -> Result<(), DatabseError> {
+        if !self.node_storage.contains_key(from.clone().key) {
+            return Err(DatabseError::InvalidKey(from));
+        }
+        if !self.node_storage.contains_key(to.clone().key) {
+            return Err(DatabseError::InvalidKey(to));
+        }
+        self.register::<Edge>()?;
+        self.begin(Operation::Connect(from.clone(), to.clone()))?;
+        let members = self.insert_members(&edge, from.clone().key);
+        if !self.edge_contents.contains_key(&Edge::SELF_HASH) {
+            self.edge_contents.insert(
+                Edge::SELF_HASH,
+                self.table_manager
+                    .get_edge_contents(Edge::SELF_HASH, members.size() as usize),
+            );
+        }
+        let members_key = self
+            .edge_contents
+            .get_mut(&Edge::SELF_HASH)
+            .unwrap()
+            .insert(members)
+            .ok()
+            .unwrap();
+        self.add_link(from, to, Edge::SELF_HASH, members_key);
+        self.commit();
+        Ok(())
+    }
+    fn add_link(&mut self, from: Key, to: Key, label: NodeHash, members: TableKey) {
+        let outgoing = Link {
+            node: to.clone().key,
+            label: label.clone(),
+            members: members.clone(),
+        };
+        self.edit_node_keys(from.clone().key, |data| data.outgoing.push(outgoing));
+        let incoming = Link {
+            node: from.key,
+            label,
+            members,
+        };
+        self.edit_node_keys(to.key, |data| data.incoming.push(incoming));
+    }
+    /// Frees the payload carried by a link if it has one
+    fn remove_edge_payload(&mut self, link: &Link) {
+        if link.label == Link::NO_LABEL {
+            return;
+        }
+        let edge_table = self.edge_contents.get_mut(&link.label).unwrap();
+        let data_locations = edge_table
+            .get(link.members.clone(), NodeStorage::from_binary)
+            .ok()
+            .unwrap();
+        edge_table.remove(link.members.clone()).ok().unwrap();
+        self.remove_members(data_locations);
+    }
+    /// Removes every link between two nodes in both directions
+    pub fn disconnect(&mut self, key1: Key, key2: Key) -> Result<(), DatabseError> {
+        self.begin(Operation::Disconnect(key1.clone(), key2.clone()))?;
+        let result = self
+            .disconnect_directed(key1.clone(), key2.clone())
+            .and_then(|_| self.disconnect_directed(key2, key1));
+        self.commit();
+        result
+    }
+    /// Removes every link going from `from` to `to`
+    pub fn disconnect_directed(&mut self, from: Key, to: Key) -> Result<(), DatabseError> {
+        if !self.node_storage.contains_key(from.clone().key) {
+            return Err(DatabseError::InvalidKey(from));
+        }
+        if !self.node_storage.contains_key(to.clone().key) {
+            return Err(DatabseError::InvalidKey(to));
+        }
+        self.begin(Operation::Disconnect(from.clone(), to.clone()))?;
+        let removed: Vec<Link> = self.edit_node_keys(from.clone().key, |data| {
+            let (removed, kept) = data.outgoing.drain(..).partition(|l| l.node == to.key);
+            data.outgoing = kept;
+            removed
+        });
+        self.edit_node_keys(to.key, |data| data.incoming.retain(|l| l.node != from.key));
+        for link in removed.iter() {
+            self.remove_edge_payload(link);
+        }
+        self.commit();
+        Ok(())
+    }
+    /// Removes every link to and from a node
+    pub fn disconnect_all(&mut self, key: Key) -> Result<(), DatabseError> {
+        if !self.node_storage.contains_key(key.clone().key) {
+            return Err(DatabseError::InvalidKey(key));
+        }
+        self.begin(Operation::DisconnectAll(key.clone()))?;
+        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.clone().key));
+        self.unlink_neighbours(&key.key, &data);
+        self.edit_node_keys(key.key, |data| {
+            data.outgoing.clear();
+            data.incoming.clear();
+        });
+        self.commit();
+        Ok(())
+    }
+    /// Removes the links pointing back at key from all of its neighbours and frees the payloads
+    /// of every link of key
+    fn unlink_neighbours(&mut self, key: &VariableKey, data: &NodeKeyStorage) {
+        for link in data.outgoing.iter() {
+            if &link.node != key {
+                self.edit_node_keys(link.node.clone(), |d| d.incoming.retain(|l| &l.node != key));
+            }
+            self.remove_edge_payload(link);
+        }
+        //links to self are in both lists so their payload has already been freed
+        for link in data.incoming.iter() {
+            if &link.node != key {
+                self.edit_node_keys(link.node.clone(), |d| d.outgoing.retain(|l| &l.node != key));
+                self.remove_edge_payload(link);
+            }
+        }
+    }
+    /// Loads the stored links of a node, edits them and writes them back, resizing the entry
+    fn edit_node_keys<T, F: FnOnce(&mut NodeKeyStorage) -> T>(
+        &mut self,
+        key: VariableKey,
+        edit: F,
+    ) -> T {
+        let mut data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.clone()));
+        let out = edit(&mut data);
+        self.node_storage
+            .write_entry(key, 0, data.get_data_variable());
+        out
+    }
+    /// Gets the nodes that key links to. For nodes joined with `connect` this is every neighbour.
+    /// Empty if key was removed
+    pub fn get_connected(&self, key: Key) -> Vec<Key> {
+        self.get_outgoing(key)
+    }
+    /// Gets the nodes that key has a link to. Empty if key was removed
+    pub fn get_outgoing(&self, key: Key) -> Vec<Key> {
+        self.node_keys(key)
+            .map(|data| data.outgoing)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|link| Key { key: link.node })
+            .collect()
+    }
+    /// Gets the nodes that have a link to key. Empty if key was removed
+    pub fn get_incoming(&self, key: Key) -> Vec<Key> {
+        self.node_keys(key)
+            .map(|data| data.incoming)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|link| Key { key: link.node })
+            .collect()
+    }
+    /// Loads the stored links of a node, `None` if key was removed
+    fn node_keys(&self, key: Key) -> Option<NodeKeyStorage> {
+        if !self.node_storage.contains_key(key.key.clone()) {
+            return None;
+        }
+        Some(NodeKeyStorage::from_binary(
+            self.node_storage.get_entry(key.key),
+        ))
+    }
+    /// Gets the links leaving key that are labelled with `Edge`, along with their payloads.
+    /// Empty if key was removed
+    pub fn get_edges<Edge: Node>(&self, key: Key) -> Vec<(Key, Edge)> {
+        self.node_keys(key)
+            .map(|data| data.outgoing)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|link| link.label == Edge::SELF_HASH)
+            .map(|link| {
+                let data_locations = self.edge_contents[&link.label]
+                    .get(link.members, NodeStorage::from_binary)
+                    .ok()
+                    .unwrap();
+                (Key { key: link.node }, self.load_members(data_locations))
+            })
+            .collect()
+    }
+    /// Overwrites the data of a node in place. The key and links of the node are kept
+    pub fn update<Data: Node>(&mut self, key: Key, data: Data) -> Result<(), DatabseError> {
+        if !self.node_storage.contains_key(key.clone().key) {
+            return Err(DatabseError::InvalidKey(key));
+        }
+        let node_keys = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.clone().key));
+        if node_keys.self_hash != Data::SELF_HASH {
+            return Err(DatabseError::TypeMismatch(key));
+        }
+        self.begin(Operation::Update(key.clone()))?;
+        let data_locations = self.node_contents[&node_keys.self_hash]
+            .get(node_keys.self_members, NodeStorage::from_binary)
+            .ok()
+            .unwrap();
+        self.remove_from_indexes(&key, &data_locations);
+        let (sized_data_vec, unsized_data_vec) = data.get_data();
+        for ((hash, table_key), (_, data)) in data_locations
+            .node_static_sized_keys
+            .into_iter()
+            .zip(sized_data_vec.iter())
+        {
+            self.sized
+                .get_mut(&hash)
+                .unwrap()
+                .write::<Box<dyn InsertableDyn>>(table_key, data.clone())
+                .ok()
+                .unwrap();
+        }
+        for ((hash, variable_key), (_, data)) in data_locations
+            .node_dynamic_sized_keys
+            .into_iter()
+            .zip(unsized_data_vec.iter())
+        {
+            self.variable.get_mut(&hash).unwrap().write_entry(
+                variable_key,
+                0,
+                data.get_data_variable(),
+            );
+        }
+        self.add_to_indexes(&key, field_values(&data));
+        self.commit();
+        Ok(())
+    }
+    /// Iterates over every node of type `Data`. Nothing is found if the layout of `Data`
+    /// disagrees with the catalog
+    pub fn iter<Data: Node>(&self) -> impl Iterator<Item = (Key, Data)> + '_ {
+        let rows = if self.layout_matches::<Data>() {
+            Some(self.rows(&Data::SELF_HASH))
+        } else {
+            None
+        };
+        rows.into_iter().flatten().map(move |data_locations| {
+            (
+                Key {
+                    key: data_locations.owner.clone(),
+                },
+                self.load_members(data_locations),
+            )
+        })
+    }
+    /// Iterates over the node_contents rows of every node with the hash
+    fn rows<'a>(&'a self, hash: &NodeHash) -> impl Iterator<Item = NodeStorage> + 'a {
+        self.node_contents
+            .get(hash)
+            .into_iter()
+            .flat_map(|contents| {
+                contents.keys().map(move |self_members| {
+                    contents
+                        .get(self_members, NodeStorage::from_binary)
+                        .ok()
+                        .unwrap()
+                })
+            })
+    }
+    /// Removes a node, its data and every link to it
+    pub fn remove(&mut self, key: Key) -> Result<(), DatabseError> {
+        if !self.node_storage.contains_key(key.clone().key) {
+            return Err(DatabseError::InvalidKey(key));
+        }
+        self.begin(Operation::Remove(key.clone()))?;
+        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.clone().key));
+        let contents = self.node_contents.get_mut(&data.self_hash).unwrap();
+        let data_locations = contents
+            .get(data.self_members.clone(), NodeStorage::from_binary)
+            .ok()
+            .unwrap();
+        contents.remove(data.self_members.clone()).ok().unwrap();
+        self.remove_from_indexes(&key, &data_locations);
+        self.remove_members(data_locations);
+        self.unlink_neighbours(&key.key, &data);
+        self.node_storage.remove_entry(key.key);
+        self.commit();
+        Ok(())
+    }
+    /// Returns `None` if the key was removed or the layout of `Data` disagrees with the catalog
+    pub fn get<Data: Node>(&self, key: Key) -> Option<Data> {
+        if !self.node_storage.contains_key(key.clone().key) || !self.layout_matches::<Data>() {
+            return None;
+        }
+        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.key));
+        let data_locations = self.node_contents[&data.self_hash]
+            .get(data.self_members, NodeStorage::from_binary)
+            .ok()
+            .unwrap();
+        Some(self.load_members(data_locations))
+    }
+}
+/// Gets the bytes of every field of data
+fn field_values<Data: Node>(data: &Data) -> Vec<(NodeElementHash, Vec<u8>)> {
+    let (sized_data_vec, unsized_data_vec) = data.get_data();
+    sized_data_vec
+        .into_iter()
+        .map(|(hash, data)| (hash, data.to_binary()))
+        .chain(
+            unsized_data_vec
+                .into_iter()
+                .map(|(hash, data)| (hash, data.get_data_variable())),
+        )
+        .collect()
+}
+impl<Manager: TableManager> Drop for Database<Manager> {
+    /// Flushes the tables. Nothing is flushed if an operation was cut off by a panic, the log
+    /// rolls it back when the database is next opened
+    fn drop(&mut self) {
+        if self.depth == 0 {
+            self.flush().ok();
+        }
     }
 }
 /// Should never fail
 pub fn in_memory_db() -> Database<InMemoryManager> {
     Database::new(InMemoryManager::new()).ok().unwrap()
 }
+/// Opens the database stored in the directory at path. The directory is created if it does
+/// not exist. Changes are flushed in batches, see `SyncPolicy` for making every operation
+/// durable. Register the types used after opening to refuse a database with a different
+/// layout, see `Database::register`
+pub fn on_disk_db<P: AsRef<Path>>(path: P) -> Result<Database<BackedManager>> {
+    Database::new(BackedManager::new(path)?)
+}
+/// Opens the database stored in the directory at path for reading, see
+/// `BackedManager::open_read_only`. Changes fail with `DatabseError::ReadOnly`
+pub fn on_disk_db_read_only<P: AsRef<Path>>(path: P) -> Result<Database<BackedManager>> {
+    Database::new(BackedManager::open_read_only(path)?)
+}
+/// Rewrites the tables of the database in the directory at path that are stored in an older
+/// format, see `BackedManager::upgrade`. The database must not be open. Returns the number of
+/// tables rewritten
+pub fn upgrade<P: AsRef<Path>>(path: P) -> Result<usize> {
+    BackedManager::<FileExtent>::new(path)?.upgrade()
+}
+/// Opens the database stored in the single file at path, see `ContainerManager`. The file is
+/// created if it does not exist
+pub fn single_file_db<P: AsRef<Path>>(path: P) -> Result<Database<ContainerManager>> {
+    Database::new(ContainerManager::new(path)?)
+}
+/// Opens the database stored in the directory at path with every table encrypted with key, see
+/// `EncryptedExtent`. The directory is created if it does not exist. Fails if the database was
+/// encrypted with a different key
+pub fn on_disk_db_encrypted<P: AsRef<Path>>(
+    path: P,
+    key: [u8; 32],
+) -> Result<Database<BackedManager<EncryptedExtent<FileExtent>>>> {
+    Database::new(BackedManager::new_encrypted(path, key)?)
+}
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
     fn node_storage() {
         let s = NodeStorage {
+            owner: VariableKey { index: 0 },
             node_static_sized_keys: vec![(NodeElementHash { hash: 0 }, TableKey { index: 0 })],
             node_dynamic_sized_keys: vec![(NodeElementHash { hash: 0 }, VariableKey { index: 0 })],
         };
@@ -333,10 +923,12 @@ mod test {
     #[test]
     fn testing_eq() {
         let s = NodeStorage {
+            owner: VariableKey { index: 0 },
             node_static_sized_keys: vec![(NodeElementHash { hash: 0 }, TableKey { index: 0 })],
             node_dynamic_sized_keys: vec![(NodeElementHash { hash: 0 }, VariableKey { index: 0 })],
         };
         let s2 = NodeStorage {
+            owner: VariableKey { index: 0 },
             node_static_sized_keys: vec![(NodeElementHash { hash: 1 }, TableKey { index: 0 })],
             node_dynamic_sized_keys: vec![(NodeElementHash { hash: 1 }, VariableKey { index: 0 })],
         };
@@ -345,6 +937,7 @@ mod test {
     #[test]
     fn big_hashes() {
         let s = NodeStorage {
+            owner: VariableKey { index: 0 },
             node_static_sized_keys: vec![(
                 NodeElementHash {
                     hash: 0x12_21_12_21_12_98_67_58,
@@ -368,6 +961,7 @@ mod test {
     #[test]
     fn big_hashes_static_only() {
         let s = NodeStorage {
+            owner: VariableKey { index: 0 },
             node_static_sized_keys: vec![(
                 NodeElementHash {
                     hash: 0x12_21_12_21_12_98_67_58,
@@ -381,4 +975,16 @@ mod test {
         let bin = s.to_binary();
         assert_eq!(s, NodeStorage::from_binary(bin));
     }
+    #[test]
+    fn disconnect_shrinks_entry() {
+        let mut db = in_memory_db();
+        let a = db.insert(1u64).ok().unwrap();
+        let b = db.insert(2u64).ok().unwrap();
+        db.connect(a.clone(), b.clone()).ok().unwrap();
+        db.disconnect(a.clone(), b).ok().unwrap();
+        assert_eq!(
+            db.node_storage.get_entry(a.key).len(),
+            NodeKeyStorage::HEADER_SIZE
+        );
+    }
 }
@@ -0,0 +1,114 @@
+use super::{Database, DatabseError, Key};
+use crate::table_manager::TableManager;
+use crate::wal::Operation;
+use btree::BTree;
+use std::ops::{Bound, RangeBounds};
+use traits::{Extent, Node, NodeElementHash, OrderKind, Ordered};
+use variable_storage::Key as VariableKey;
+/// Persistent ordered index from the value of a sized field to the keys of the nodes holding
+/// that value. Values are mapped to u64 with `OrderKind::to_ordered`, the kind is kept in the
+/// tag of the tree so that the index can be reloaded.
+pub struct OrderedIndex<E: Extent> {
+    tree: BTree<E>,
+    kind: OrderKind,
+}
+impl<E: Extent> OrderedIndex<E> {
+    /// Loads an index from a tree created with an `OrderKind` as its tag
+    pub fn load(tree: BTree<E>) -> Self {
+        let kind = OrderKind::from_u64(tree.tag()).expect("invalid order kind");
+        Self { tree, kind }
+    }
+    pub fn insert(&mut self, value: &[u8], key: &VariableKey) {
+        self.tree
+            .insert(self.kind.to_ordered(value), key.index as u64)
+            .ok()
+            .unwrap();
+    }
+    pub fn remove(&mut self, value: &[u8], key: &VariableKey) {
+        self.tree
+            .remove(self.kind.to_ordered(value), key.index as u64);
+    }
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.tree.flush()
+    }
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.tree.verify()
+    }
+    /// Gets the keys of nodes with values in range sorted by value
+    pub fn range(&self, range: (Bound<u64>, Bound<u64>)) -> impl Iterator<Item = VariableKey> + '_ {
+        self.tree.range(range).map(|(_, index)| VariableKey {
+            index: index as usize,
+        })
+    }
+}
+/// Maps a bound on a field value to a bound on its ordered value
+fn ordered_bound<Field: Ordered>(bound: Bound<&Field>) -> Bound<u64> {
+    match bound {
+        Bound::Included(value) => Bound::Included(Field::ORDER.to_ordered(&value.to_binary())),
+        Bound::Excluded(value) => Bound::Excluded(Field::ORDER.to_ordered(&value.to_binary())),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Creates an ordered index on a sized field of `Data` so that `range` does not have to scan
+    /// and sort every node. field is one of the hashes returned by `Data::get_sized_hashes` and
+    /// `Field` is its type
+    pub fn create_ordered_index<Data: Node, Field: Ordered>(
+        &mut self,
+        field: NodeElementHash,
+    ) -> Result<(), DatabseError> {
+        if !Data::get_sized_hashes().contains(&field) {
+            return Err(DatabseError::InvalidField(field));
+        }
+        if self.ordered_indexes.contains_key(&field) {
+            return Ok(());
+        }
+        self.begin(Operation::CreateIndex(field.clone()))?;
+        let mut index = OrderedIndex::load(
+            self.table_manager
+                .get_ordered_index(field.clone(), Field::ORDER),
+        );
+        for data_locations in self.rows(&Data::SELF_HASH) {
+            if let Some(value) = self.stored_field(&data_locations, &field) {
+                index.insert(&value, &data_locations.owner);
+            }
+        }
+        self.ordered_indexes.insert(field, index);
+        self.commit();
+        Ok(())
+    }
+    /// Gets every node of type `Data` whose field is in range, sorted by the field. Uses the
+    /// index on the field if one was made with `create_ordered_index`, otherwise every node of
+    /// type `Data` is scanned
+    pub fn range<Data: Node, Field: Ordered>(
+        &self,
+        field: NodeElementHash,
+        range: impl RangeBounds<Field>,
+    ) -> Box<dyn Iterator<Item = (Key, Data)> + '_> {
+        let bounds = (
+            ordered_bound(range.start_bound()),
+            ordered_bound(range.end_bound()),
+        );
+        let keys: Box<dyn Iterator<Item = VariableKey> + '_> =
+            match self.ordered_indexes.get(&field) {
+                Some(index) => Box::new(index.range(bounds)),
+                None => {
+                    let mut found: Vec<(u64, VariableKey)> = self
+                        .rows(&Data::SELF_HASH)
+                        .filter_map(|data_locations| {
+                            self.stored_field(&data_locations, &field).map(|value| {
+                                (Field::ORDER.to_ordered(&value), data_locations.owner)
+                            })
+                        })
+                        .filter(|(value, _)| bounds.contains(value))
+                        .collect();
+                    found.sort_by_key(|(value, key)| (*value, key.index));
+                    Box::new(found.into_iter().map(|(_, key)| key))
+                }
+            };
+        Box::new(keys.filter_map(move |key| {
+            let key = Key { key };
+            self.get::<Data>(key.clone()).map(|data| (key, data))
+        }))
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Database, Key};
+use crate::table_manager::TableManager;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use traits::Node;
+/// Entry in the priority queue of the weighted search. Ordered so that the `BinaryHeap` pops the
+/// lowest estimate first
+struct Candidate {
+    //cost of the path found so far
+    cost: f64,
+    //cost plus the heuristic of the node
+    estimate: f64,
+    key: Key,
+}
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+/// Walks the previous links back from `to` to build the path
+fn build_path(previous: &HashMap<Key, Key>, to: Key) -> Vec<Key> {
+    let mut path = vec![to];
+    while let Some(key) = previous.get(path.last().unwrap()) {
+        path.push(key.clone());
+    }
+    path.reverse();
+    path
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Finds the path from `from` to `to` that follows the fewest links. Returns `None` if `to`
+    /// can not be reached
+    pub fn shortest_path(&self, from: Key, to: Key) -> Option<Vec<Key>> {
+        if !self.node_storage.contains_key(from.clone().key)
+            || !self.node_storage.contains_key(to.clone().key)
+        {
+            return None;
+        }
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(from.clone());
+        while let Some(key) = queue.pop_front() {
+            if key == to {
+                return Some(build_path(&previous, to));
+            }
+            for linked in self.get_outgoing(key.clone()) {
+                if linked != from && !previous.contains_key(&linked) {
+                    previous.insert(linked.clone(), key.clone());
+                    queue.push_back(linked);
+                }
+            }
+        }
+        None
+    }
+    /// Finds the cheapest path from `from` to `to` using Dijkstra's algorithm. Only links labelled
+    /// with `Edge` are followed and the cost of each link is computed from its payload by
+    /// `weight`, which must not be negative. Returns the total cost along with the path
+    pub fn weighted_shortest_path<Edge: Node, W: Fn(&Edge) -> f64>(
+        &self,
+        from: Key,
+        to: Key,
+        weight: W,
+    ) -> Option<(f64, Vec<Key>)> {
+        self.a_star(from, to, weight, |_: &Key| 0.0)
+    }
+    /// Finds the cheapest path like `weighted_shortest_path`, using `heuristic` to estimate the
+    /// remaining cost from a node to `to`. The heuristic must never overestimate the cost for the
+    /// path returned to be the cheapest
+    pub fn a_star<Edge: Node, W: Fn(&Edge) -> f64, H: Fn(&Key) -> f64>(
+        &self,
+        from: Key,
+        to: Key,
+        weight: W,
+        heuristic: H,
+    ) -> Option<(f64, Vec<Key>)> {
+        if !self.node_storage.contains_key(from.clone().key)
+            || !self.node_storage.contains_key(to.clone().key)
+        {
+            return None;
+        }
+        let mut previous: HashMap<Key, Key> = HashMap::new();
+        let mut costs: HashMap<Key, f64> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        costs.insert(from.clone(), 0.0);
+        queue.push(Candidate {
+            cost: 0.0,
+            estimate: heuristic(&from),
+            key: from,
+        });
+        while let Some(Candidate { cost, key, .. }) = queue.pop() {
+            if key == to {
+                return Some((cost, build_path(&previous, to)));
+            }
+            //skip entries made stale by a cheaper path found later
+            if cost > costs[&key] {
+                continue;
+            }
+            for (linked, edge) in self.get_edges::<Edge>(key.clone()) {
+                let linked_cost = cost + weight(&edge);
+                let is_cheaper = match costs.get(&linked) {
+                    Some(old_cost) => linked_cost < *old_cost,
+                    None => true,
+                };
+                if is_cheaper {
+                    costs.insert(linked.clone(), linked_cost);
+                    previous.insert(linked.clone(), key.clone());
+                    queue.push(Candidate {
+                        cost: linked_cost,
+                        estimate: linked_cost + heuristic(&linked),
+                        key: linked,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,323 @@
+use super::{Database, DatabseError, Key, Link, NodeKeyStorage, NodeStorage};
+use crate::table_manager::TableManager;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use traits::{Node, NodeElementHash, NodeHash};
+/// Copy of a node as it was before a change
+#[derive(Clone)]
+struct NodeImage {
+    hash: NodeHash,
+    sized: Vec<(NodeElementHash, Vec<u8>)>,
+    variable: Vec<(NodeElementHash, Vec<u8>)>,
+    outgoing: Vec<Key>,
+    incoming: Vec<Key>,
+}
+impl NodeImage {
+    fn load<Data: Node>(&self) -> Option<Data> {
+        if self.hash != Data::SELF_HASH {
+            return None;
+        }
+        Some(Data::from_data(self.sized.clone(), self.variable.clone()))
+    }
+}
+/// A database along with the old versions of nodes still needed by snapshots
+struct Versioned<Manager: TableManager> {
+    db: Database<Manager>,
+    //number of changes made to the database
+    version: u64,
+    //for every changed key the version made by each change and the node before the change,
+    //oldest first. None if the node did not exist
+    history: HashMap<Key, Vec<(u64, Option<NodeImage>)>>,
+    //number of live snapshots taken at each version
+    snapshots: BTreeMap<u64, usize>,
+}
+impl<Manager: TableManager> Versioned<Manager> {
+    /// Saves the nodes at keys as they are before the next change. Nothing is saved if there
+    /// are no snapshots to read it
+    fn record(&mut self, keys: Vec<Key>) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+        let next = self.version + 1;
+        for key in keys {
+            let image = self.db.image(&key);
+            let versions = self.history.entry(key).or_default();
+            if versions.last().map(|(version, _)| *version) != Some(next) {
+                versions.push((next, image));
+            }
+        }
+    }
+    /// Gets the node at key as seen by a snapshot taken at version. Returns None if the node
+    /// has not changed since the snapshot so the database itself can be read
+    fn at(&self, version: u64, key: &Key) -> Option<Option<&NodeImage>> {
+        self.history
+            .get(key)?
+            .iter()
+            .find(|(changed, _)| *changed > version)
+            .map(|(_, image)| image.as_ref())
+    }
+    /// Drops the history no snapshot can read
+    fn collect(&mut self) {
+        match self.snapshots.keys().next().cloned() {
+            Some(oldest) => {
+                for versions in self.history.values_mut() {
+                    versions.retain(|(changed, _)| *changed > oldest);
+                }
+                self.history.retain(|_, versions| !versions.is_empty());
+            }
+            None => self.history.clear(),
+        }
+    }
+}
+/// Handle to a database that can be shared between threads. One writer at a time changes the
+/// database while readers take snapshots that keep seeing the database as it was when they
+/// were taken. Clones of the handle refer to the same database.
+pub struct SharedDatabase<Manager: TableManager> {
+    inner: Arc<RwLock<Versioned<Manager>>>,
+}
+impl<Manager: TableManager> Clone for SharedDatabase<Manager> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+impl<Manager: TableManager> SharedDatabase<Manager> {
+    pub fn new(db: Database<Manager>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Versioned {
+                db,
+                version: 0,
+                history: HashMap::new(),
+                snapshots: BTreeMap::new(),
+            })),
+        }
+    }
+    /// Takes a point in time view of the database
+    pub fn snapshot(&self) -> Snapshot<Manager> {
+        let mut inner = self.inner.write().unwrap();
+        let version = inner.version;
+        *inner.snapshots.entry(version).or_insert(0) += 1;
+        Snapshot {
+            inner: self.inner.clone(),
+            version,
+        }
+    }
+    /// Makes a change to the database. The nodes found by affected are saved for snapshots
+    /// before the change is made
+    fn write<T, A, F>(&self, affected: A, change: F) -> T
+    where
+        A: FnOnce(&Database<Manager>) -> Vec<Key>,
+        F: FnOnce(&mut Database<Manager>) -> T,
+    {
+        let mut inner = self.inner.write().unwrap();
+        let keys = affected(&inner.db);
+        inner.record(keys);
+        let out = change(&mut inner.db);
+        inner.version += 1;
+        out
+    }
+    pub fn insert<Data: Node>(&self, data: Data) -> Result<Key, DatabseError> {
+        let mut inner = self.inner.write().unwrap();
+        let key = inner.db.insert(data)?;
+        //the node did not exist before the change so earlier snapshots must not see it
+        if !inner.snapshots.is_empty() {
+            let next = inner.version + 1;
+            inner
+                .history
+                .entry(key.clone())
+                .or_default()
+                .push((next, None));
+        }
+        inner.version += 1;
+        Ok(key)
+    }
+    pub fn update<Data: Node>(&self, key: Key, data: Data) -> Result<(), DatabseError> {
+        let affected = vec![key.clone()];
+        self.write(move |_| affected, |db| db.update(key, data))
+    }
+    pub fn remove(&self, key: Key) -> Result<(), DatabseError> {
+        let target = key.clone();
+        self.write(move |db| db.neighbourhood(&target), |db| db.remove(key))
+    }
+    pub fn connect(&self, key1: Key, key2: Key) -> Result<(), DatabseError> {
+        let affected = vec![key1.clone(), key2.clone()];
+        self.write(move |_| affected, |db| db.connect(key1, key2))
+    }
+    pub fn connect_directed(&self, from: Key, to: Key) -> Result<(), DatabseError> {
+        let affected = vec![from.clone(), to.clone()];
+        self.write(move |_| affected, |db| db.connect_directed(from, to))
+    }
+    pub fn connect_with<Edge: Node>(
+        &self,
+        from: Key,
+        to: Key,
+        edge: Edge,
+    ) -> Result<(), DatabseError> {
+        let affected = vec![from.clone(), to.clone()];
+        self.write(move |_| affected, |db| db.connect_with(from, to, edge))
+    }
+    pub fn disconnect(&self, key1: Key, key2: Key) -> Result<(), DatabseError> {
+        let affected = vec![key1.clone(), key2.clone()];
+        self.write(move |_| affected, |db| db.disconnect(key1, key2))
+    }
+    pub fn disconnect_all(&self, key: Key) -> Result<(), DatabseError> {
+        let target = key.clone();
+        self.write(
+            move |db| db.neighbourhood(&target),
+            |db| db.disconnect_all(key),
+        )
+    }
+    /// Writes the database to disk, see `Database::flush`
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.inner.write().unwrap().db.flush()
+    }
+    /// Checks the database for damage, see `Database::verify`
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.inner.read().unwrap().db.verify()
+    }
+    /// Gets the latest version of a node
+    pub fn get<Data: Node>(&self, key: Key) -> Option<Data> {
+        self.inner.read().unwrap().db.get(key)
+    }
+}
+/// Point in time view of a `SharedDatabase`. Changes made after the snapshot was taken are
+/// not visible to it. Old versions of changed nodes are kept until every snapshot that can see
+/// them is dropped
+pub struct Snapshot<Manager: TableManager> {
+    inner: Arc<RwLock<Versioned<Manager>>>,
+    version: u64,
+}
+impl<Manager: TableManager> Snapshot<Manager> {
+    /// Gets a node as it was when the snapshot was taken, None if it did not exist or was not
+    /// of type `Data`
+    pub fn get<Data: Node>(&self, key: Key) -> Option<Data> {
+        let inner = self.inner.read().unwrap();
+        match inner.at(self.version, &key) {
+            Some(image) => image.and_then(|image| image.load()),
+            None => inner.db.get_typed(&key),
+        }
+    }
+    /// Gets every node of type `Data`, sorted by key
+    pub fn iter<Data: Node>(&self) -> Vec<(Key, Data)> {
+        let inner = self.inner.read().unwrap();
+        let mut keys: Vec<Key> = inner
+            .db
+            .iter::<Data>()
+            .map(|(key, _)| key)
+            .chain(inner.history.keys().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        keys.sort_by_key(|key| key.key.index);
+        keys.into_iter()
+            .filter_map(|key| {
+                let data = match inner.at(self.version, &key) {
+                    Some(image) => image.and_then(|image| image.load()),
+                    None => inner.db.get_typed::<Data>(&key),
+                };
+                data.map(|data| (key, data))
+            })
+            .collect()
+    }
+    /// Gets the nodes that key has a link to
+    pub fn get_outgoing(&self, key: Key) -> Vec<Key> {
+        self.links(key, |image| &image.outgoing, Database::get_outgoing)
+    }
+    /// Gets the nodes that have a link to key
+    pub fn get_incoming(&self, key: Key) -> Vec<Key> {
+        self.links(key, |image| &image.incoming, Database::get_incoming)
+    }
+    /// Same as `Database::get_connected`
+    pub fn get_connected(&self, key: Key) -> Vec<Key> {
+        self.get_outgoing(key)
+    }
+    fn links(
+        &self,
+        key: Key,
+        saved: fn(&NodeImage) -> &Vec<Key>,
+        current: fn(&Database<Manager>, Key) -> Vec<Key>,
+    ) -> Vec<Key> {
+        let inner = self.inner.read().unwrap();
+        match inner.at(self.version, &key) {
+            Some(image) => image.map(|image| saved(image).clone()).unwrap_or_default(),
+            None if inner.db.node_storage.contains_key(key.key.clone()) => current(&inner.db, key),
+            None => vec![],
+        }
+    }
+}
+impl<Manager: TableManager> Drop for Snapshot<Manager> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.write().unwrap();
+        let count = inner.snapshots.get_mut(&self.version).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            inner.snapshots.remove(&self.version);
+            inner.collect();
+        }
+    }
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Copies the node at key, None if there is no node at key
+    fn image(&self, key: &Key) -> Option<NodeImage> {
+        if !self.node_storage.contains_key(key.key.clone()) {
+            return None;
+        }
+        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.key.clone()));
+        let data_locations = self.node_contents[&data.self_hash]
+            .get(data.self_members.clone(), NodeStorage::from_binary)
+            .ok()
+            .unwrap();
+        let sized = data_locations
+            .node_static_sized_keys
+            .iter()
+            .map(|(hash, key)| {
+                (
+                    hash.clone(),
+                    self.sized[hash].get(key.clone(), |d| d).ok().unwrap(),
+                )
+            })
+            .collect();
+        let variable = data_locations
+            .node_dynamic_sized_keys
+            .iter()
+            .map(|(hash, key)| (hash.clone(), self.variable[hash].get_entry(key.clone())))
+            .collect();
+        let keys = |links: &Vec<Link>| {
+            links
+                .iter()
+                .map(|link| Key {
+                    key: link.node.clone(),
+                })
+                .collect()
+        };
+        Some(NodeImage {
+            hash: data.self_hash.clone(),
+            sized,
+            variable,
+            outgoing: keys(&data.outgoing),
+            incoming: keys(&data.incoming),
+        })
+    }
+    /// Gets key and every node linked to or from it
+    fn neighbourhood(&self, key: &Key) -> Vec<Key> {
+        if !self.node_storage.contains_key(key.key.clone()) {
+            return vec![];
+        }
+        let mut keys = vec![key.clone()];
+        keys.append(&mut self.get_outgoing(key.clone()));
+        keys.append(&mut self.get_incoming(key.clone()));
+        keys
+    }
+    /// Gets the node at key if it is of type `Data`
+    fn get_typed<Data: Node>(&self, key: &Key) -> Option<Data> {
+        if !self.node_storage.contains_key(key.key.clone()) {
+            return None;
+        }
+        let data = NodeKeyStorage::from_binary(self.node_storage.get_entry(key.key.clone()));
+        if data.self_hash != Data::SELF_HASH {
+            return None;
+        }
+        self.get(key.clone())
+    }
+}
@@ -1,14 +1,25 @@
+use crate::catalog::Catalog;
+use crate::wal::Operation;
+use btree::BTree;
 use std::collections::HashMap;
 use table::DatabaseTable;
-use traits::{Extent, InMemoryExtent, NodeElementHash, NodeHash};
+use traits::{Extent, InMemoryExtent, NodeElementHash, NodeHash, OrderKind};
 mod backed;
+mod container;
+mod named;
 use anyhow::Result;
+pub use backed::BackedManager;
+pub use container::ContainerManager;
 use variable_storage::VariableExtent;
 pub struct TableStartup<E: Extent> {
     pub node_storage: VariableExtent<E>,
     pub node_contents: HashMap<NodeHash, DatabaseTable<E>>,
+    pub edge_contents: HashMap<NodeHash, DatabaseTable<E>>,
     pub variable: HashMap<NodeElementHash, VariableExtent<E>>,
     pub sized: HashMap<NodeElementHash, DatabaseTable<E>>,
+    pub indexes: HashMap<NodeElementHash, VariableExtent<E>>,
+    pub ordered_indexes: HashMap<NodeElementHash, BTree<E>>,
+    pub catalog: Catalog<E>,
 }
 pub trait TableManager {
     type ExtentType: Extent;
@@ -18,12 +29,35 @@ pub trait TableManager {
         hash: NodeHash,
         data_size: usize,
     ) -> DatabaseTable<Self::ExtentType>;
+    fn get_edge_contents(
+        &mut self,
+        hash: NodeHash,
+        data_size: usize,
+    ) -> DatabaseTable<Self::ExtentType>;
     fn get_sized(
         &mut self,
         hash: NodeElementHash,
         data_size: usize,
     ) -> DatabaseTable<Self::ExtentType>;
     fn get_variable(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType>;
+    fn get_index(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType>;
+    /// Creates the tree of an ordered index, kind is stored as the tag of the tree
+    fn get_ordered_index(
+        &mut self,
+        hash: NodeElementHash,
+        kind: OrderKind,
+    ) -> BTree<Self::ExtentType>;
+    /// Whether the tables were opened without write access, `Database` then refuses changes
+    fn read_only(&self) -> bool {
+        false
+    }
+    /// Starts an operation, changes made to the tables are undone if the operation is cut off
+    /// or if the tables are not flushed before a crash. Operations may nest
+    fn begin(&mut self, operation: Operation) -> Result<()>;
+    fn commit(&mut self) -> Result<()>;
+    /// Called once every table has been flushed, the changes made before it no longer have to
+    /// be undone
+    fn checkpoint(&mut self) -> Result<()>;
 }
 //manages extent in memory
 pub struct InMemoryManager {}
@@ -32,31 +66,73 @@ impl InMemoryManager {
         Self {}
     }
 }
+impl Default for InMemoryManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl TableManager for InMemoryManager {
     type ExtentType = InMemoryExtent;
     fn get(&mut self) -> Result<TableStartup<Self::ExtentType>> {
         Ok(TableStartup {
             node_storage: VariableExtent::new(InMemoryExtent::new()),
             node_contents: HashMap::new(),
+            edge_contents: HashMap::new(),
             variable: HashMap::new(),
             sized: HashMap::new(),
+            indexes: HashMap::new(),
+            ordered_indexes: HashMap::new(),
+            catalog: Catalog::new(VariableExtent::new(InMemoryExtent::new())),
         })
     }
     fn get_node_contents(
         &mut self,
-        hash: NodeHash,
+        _hash: NodeHash,
         data_size: usize,
     ) -> DatabaseTable<Self::ExtentType> {
         DatabaseTable::new(InMemoryExtent::new(), data_size)
+            .ok()
+            .unwrap()
+    }
+    fn get_edge_contents(
+        &mut self,
+        _hash: NodeHash,
+        data_size: usize,
+    ) -> DatabaseTable<Self::ExtentType> {
+        DatabaseTable::new(InMemoryExtent::new(), data_size)
+            .ok()
+            .unwrap()
     }
     fn get_sized(
         &mut self,
-        hash: NodeElementHash,
+        _hash: NodeElementHash,
         data_size: usize,
     ) -> DatabaseTable<Self::ExtentType> {
         DatabaseTable::new(InMemoryExtent::new(), data_size)
+            .ok()
+            .unwrap()
+    }
+    fn get_variable(&mut self, _hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
+        VariableExtent::new(InMemoryExtent::new())
     }
-    fn get_variable(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
+    fn get_index(&mut self, _hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
         VariableExtent::new(InMemoryExtent::new())
     }
+    fn get_ordered_index(
+        &mut self,
+        _hash: NodeElementHash,
+        kind: OrderKind,
+    ) -> BTree<Self::ExtentType> {
+        BTree::new(InMemoryExtent::new(), kind as u64).ok().unwrap()
+    }
+    //nothing survives a crash so nothing has to be logged
+    fn begin(&mut self, _operation: Operation) -> Result<()> {
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn checkpoint(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
@@ -1,12 +1,17 @@
+use super::named::{lock, NamedTables, StorageError};
 use super::{TableManager, TableStartup};
+use crate::wal::{wal_path, LoggedExtent, Operation, Wal};
 use anyhow::Result;
-use file_extent::FileExtent;
-use std::collections::HashMap;
-use std::fs::create_dir;
-use std::path::Path;
+use btree::BTree;
+use file_extent::{ExtentOptions, FileBacked, FileExtent};
+use libc::{LOCK_EX, LOCK_SH};
+use std::fs::{create_dir, remove_file, File, OpenOptions};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use table::DatabaseTable;
 use thiserror::Error;
-use traits::{Extent, InMemoryExtent, NodeElementHash, NodeHash};
+use traits::{NodeElementHash, NodeHash, OrderKind};
 use variable_storage::VariableExtent;
 #[derive(Error, Debug)]
 enum BackedManagerError {
@@ -14,52 +19,202 @@ enum BackedManagerError {
     RootNotDirectory,
     #[error("{path} is not a directory")]
     NotDirectory { path: String },
-    #[error("contents file not a directory")]
-    ContentsNotDirectory,
 }
-pub struct BackedManager {
-    root_path: String,
+/// Picks the tables to compress by their file name relative to the root
+type CompressFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+/// Table manager that keeps each table in its own file. The files are opened as `E`, by
+/// default they are mapped into memory with `FileExtent`
+pub struct BackedManager<E: FileBacked = FileExtent> {
+    root_path: PathBuf,
+    wal: Arc<Mutex<Wal>>,
+    compress: CompressFilter,
+    //key of `EncryptedExtent` tables
+    key: Option<[u8; 32]>,
+    //holds the lock on the database until the manager is dropped
+    _lock: File,
+    read_only: bool,
+    extent: PhantomData<E>,
 }
-impl BackedManager {
-    pub fn new(root_path_string: String) -> Result<Self> {
-        let root_path = Path::new(&root_path_string);
+impl<E: FileBacked> BackedManager<E> {
+    const DIRECTORIES: [&'static str; 6] = [
+        "contents", "edges", "variable", "static", "indexes", "ordered",
+    ];
+    /// Opens the database directory at root_path, creating it and its sub directories if they
+    /// do not exist. An operation left unfinished in the log is rolled back. Fails if the
+    /// database is already open
+    pub fn new<P: AsRef<Path>>(root_path: P) -> Result<Self> {
+        Self::open_root(root_path.as_ref(), None)
+    }
+    /// Same as `new` for databases whose tables are stored in an `EncryptedExtent`. Fails if
+    /// the tables were encrypted with a different key
+    pub fn new_encrypted<P: AsRef<Path>>(root_path: P, key: [u8; 32]) -> Result<Self> {
+        Self::open_root(root_path.as_ref(), Some(key))
+    }
+    /// Same as `open_read_only` for databases whose tables are stored in an `EncryptedExtent`
+    pub fn open_read_only_encrypted<P: AsRef<Path>>(root_path: P, key: [u8; 32]) -> Result<Self> {
+        Self::open_root_read_only(root_path.as_ref(), Some(key))
+    }
+    fn open_root(root_path: &Path, key: Option<[u8; 32]>) -> Result<Self> {
         if !root_path.exists() {
             create_dir(root_path)?;
         } else if !root_path.is_dir() {
             return Err(anyhow!("{}", BackedManagerError::RootNotDirectory));
         }
-        if !root_path.join("contents").exists() {
-            create_dir(root_path.join("contents"))?
-        } else if !root_path.join("contents").is_dir() {
-            return Err(anyhow!("{}", BackedManagerError::ContentsNotDirectory));
+        let lock = Self::lock(root_path, LOCK_EX)?;
+        for directory in Self::DIRECTORIES.iter() {
+            let path = root_path.join(directory);
+            if !path.exists() {
+                create_dir(&path)?
+            } else if !path.is_dir() {
+                return Err(anyhow!(
+                    "{}",
+                    BackedManagerError::NotDirectory {
+                        path: path.to_str().unwrap().to_string()
+                    }
+                ));
+            }
+        }
+        let log = wal_path(root_path);
+        if log.exists() && log.metadata()?.len() > 0 {
+            let options = ExtentOptions {
+                key,
+                ..ExtentOptions::default()
+            };
+            let open = |name: &str| {
+                let path = root_path.join(name).to_str().unwrap().to_string();
+                E::open_with(path, &options)
+            };
+            let remove = |name: &str| {
+                let path = root_path.join(name);
+                if path.exists() {
+                    remove_file(path)?;
+                }
+                Ok(())
+            };
+            Wal::recover(&log, key, open, remove)?;
+        }
+        Ok(Self {
+            root_path: root_path.to_path_buf(),
+            wal: Arc::new(Mutex::new(Wal::open(log, key)?)),
+            compress: Arc::new(|_| true),
+            key,
+            _lock: lock,
+            read_only: false,
+            extent: PhantomData,
+        })
+    }
+    /// Opens an existing database without write access. Any number of read only opens may
+    /// share a database but it can not be open for writing at the same time
+    pub fn open_read_only<P: AsRef<Path>>(root_path: P) -> Result<Self> {
+        Self::open_root_read_only(root_path.as_ref(), None)
+    }
+    fn open_root_read_only(root_path: &Path, key: Option<[u8; 32]>) -> Result<Self> {
+        if !root_path.is_dir() {
+            return Err(anyhow!("{}", BackedManagerError::RootNotDirectory));
         }
-        if !root_path.join("variable").exists() {
-            create_dir(root_path.join("variable"))?
-        } else if !root_path.join("variable").is_dir() {
+        let lock = Self::lock(root_path, LOCK_SH)?;
+        let log = wal_path(root_path);
+        if log.exists() && log.metadata()?.len() > 0 {
             return Err(anyhow!(
                 "{}",
-                BackedManagerError::NotDirectory {
-                    path: root_path.join("variable").to_str().unwrap().to_string()
+                StorageError::NeedsRecovery {
+                    path: root_path.display().to_string()
                 }
             ));
         }
-        if !root_path.join("static").exists() {
-            create_dir(root_path.join("static"))?
-        } else if !root_path.join("static").is_dir() {
+        Ok(Self {
+            root_path: root_path.to_path_buf(),
+            wal: Arc::new(Mutex::new(Wal::open(log, key)?)),
+            compress: Arc::new(|_| true),
+            key,
+            _lock: lock,
+            read_only: true,
+            extent: PhantomData,
+        })
+    }
+    /// Picks which tables are compressed when `E` is a `CompressedExtent`, other extents ignore
+    /// it. filter is given the name of the table file relative to the root such as
+    /// `variable/{hash}.var`. Every table is compressed by default. Tables written before keep
+    /// their old pages readable either way
+    pub fn with_compression<F: Fn(&str) -> bool + Send + Sync + 'static>(
+        mut self,
+        filter: F,
+    ) -> Self {
+        self.compress = Arc::new(filter);
+        self
+    }
+    /// Rewrites every table stored in an older format in the current one so that the database
+    /// can be opened with `Database::new`. Returns the number of tables rewritten
+    pub fn upgrade(&self) -> Result<usize> {
+        if self.read_only {
             return Err(anyhow!(
                 "{}",
-                BackedManagerError::NotDirectory {
-                    path: root_path.join("static").to_str().unwrap().to_string()
+                StorageError::ReadOnly {
+                    path: self.root_path.display().to_string()
                 }
             ));
         }
-        Ok(Self {
-            root_path: root_path_string,
-        })
+        self.upgrade_tables()
+    }
+    /// Takes an advisory lock on the lock file in the root. The lock is released when the file
+    /// is closed
+    fn lock(root_path: &Path, operation: i32) -> Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(root_path.join("lock"))?;
+        lock(file, operation, root_path)
+    }
+    fn path(&self, name: &str) -> String {
+        self.root_path.join(name).to_str().unwrap().to_string()
+    }
+    /// Options used to open the file of a table
+    fn options(&self, name: &str) -> ExtentOptions {
+        ExtentOptions {
+            read_only: self.read_only,
+            compress: (self.compress)(name),
+            key: self.key,
+        }
+    }
+}
+impl<E: FileBacked> NamedTables for BackedManager<E> {
+    type Extent = E;
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+    fn exists(&self, name: &str) -> bool {
+        Path::new(&self.path(name)).exists()
+    }
+    /// Gets the names of the files in directory
+    fn names(&self, directory: &str) -> Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in self.root_path.join(directory).read_dir()? {
+            let file_name = entry?.file_name();
+            names.push(format!("{}/{}", directory, file_name.to_str().unwrap()));
+        }
+        Ok(names)
+    }
+    fn open(&self, name: String) -> Result<LoggedExtent<E>> {
+        let file = E::open_with(self.path(&name), &self.options(&name))?;
+        Ok(LoggedExtent::open(file, name, self.wal.clone()))
+    }
+    fn create(&self, name: String) -> Result<LoggedExtent<E>> {
+        let path = self.path(&name);
+        let options = self.options(&name);
+        LoggedExtent::create(name, self.wal.clone(), || E::open_with(path, &options))
+    }
+    /// Tables are named by their path
+    fn describe(&self, name: &str) -> String {
+        self.path(name)
     }
 }
 ///File Structure:
 ///{DB NAME}
+///|-lock
+///|-wal.log
+///|-catalog.var
 ///|-node_storage.var
 ///|-contents
 ///||-{hash0}.static
@@ -69,6 +224,12 @@ impl BackedManager {
 ///     .
 ///     .
 ///||-{hashn}.static
+///|-edges
+///||-{hash0}.static
+///     .
+///     .
+///     .
+///||-{hashn}.static
 ///|-variable
 ///||-{hash0}.var
 ///||-{hash1}.var
@@ -85,53 +246,92 @@ impl BackedManager {
 ///     .
 ///     .
 ///||-{hashn}.static
-impl TableManager for BackedManager {
-    type ExtentType = FileExtent;
+///|-indexes
+///||-{hash0}.var
+///     .
+///     .
+///     .
+///||-{hashn}.var
+///|-ordered
+///||-{hash0}.btree
+///     .
+///     .
+///     .
+///||-{hashn}.btree
+impl<E: FileBacked> TableManager for BackedManager<E> {
+    type ExtentType = LoggedExtent<E>;
 
+    /// Rebuilds the tables of every type and field recorded in the catalog
     fn get(&mut self) -> Result<TableStartup<Self::ExtentType>> {
-        todo!()
-        /*
-        let mut node_contents = HashMap::new();
-        for entry in Path::new(&self.root_path).join("contents").read_dir()? {
-            let entry_hash = entry?.path().file_stem().unwrap().to_str().unwrap();
-            let storage = DatabaseTable::new(
-                FileExtent::new(entry?.path().to_str().unwrap().to_string())?,
-                node_contents_size,
-            );
-            node_contents.insert(
-                NodeHash {
-                    hash: entry_hash.parse()?,
-                },
-                storage,
-            );
-        }
-
-        Ok(TableStartup {
-            node_storage: VariableExtent::new(FileExtent::new(
-                (self.root_path.clone() + "/node_storage.var").to_string(),
-            )?),
-
-            node_contents: node_contents,
-            variable: HashMap::new(),
-            sized: HashMap::new(),
-        })
-        */
+        self.startup()
     }
     fn get_node_contents(
         &mut self,
         hash: NodeHash,
         data_size: usize,
     ) -> DatabaseTable<Self::ExtentType> {
-        todo!()
+        self.create_table("contents", hash.hash, "static", |extent| {
+            DatabaseTable::new(extent, data_size)
+        })
+    }
+    fn get_edge_contents(
+        &mut self,
+        hash: NodeHash,
+        data_size: usize,
+    ) -> DatabaseTable<Self::ExtentType> {
+        self.create_table("edges", hash.hash, "static", |extent| {
+            DatabaseTable::new(extent, data_size)
+        })
     }
     fn get_sized(
         &mut self,
         hash: NodeElementHash,
         data_size: usize,
     ) -> DatabaseTable<Self::ExtentType> {
-        todo!()
+        self.create_table("static", hash.hash, "static", |extent| {
+            DatabaseTable::new(extent, data_size)
+        })
     }
     fn get_variable(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
-        todo!()
+        self.create_table("variable", hash.hash, "var", |extent| {
+            Ok(VariableExtent::new(extent))
+        })
+    }
+    fn get_index(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
+        self.create_table("indexes", hash.hash, "var", |extent| {
+            Ok(VariableExtent::new(extent))
+        })
+    }
+    fn get_ordered_index(
+        &mut self,
+        hash: NodeElementHash,
+        kind: OrderKind,
+    ) -> BTree<Self::ExtentType> {
+        self.create_table("ordered", hash.hash, "btree", |extent| {
+            BTree::new(extent, kind as u64)
+        })
+    }
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+    fn begin(&mut self, operation: Operation) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!(
+                "{}",
+                StorageError::ReadOnly {
+                    path: self.root_path.display().to_string()
+                }
+            ));
+        }
+        self.wal.lock().unwrap().begin(operation)
+    }
+    fn commit(&mut self) -> Result<()> {
+        self.wal.lock().unwrap().commit()
+    }
+    fn checkpoint(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.wal.lock().unwrap().checkpoint()
     }
 }
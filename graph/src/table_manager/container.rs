@@ -0,0 +1,207 @@
+use super::named::{lock, NamedTables, StorageError};
+use super::{TableManager, TableStartup};
+use crate::wal::{LoggedExtent, Operation, Wal};
+use anyhow::Result;
+use btree::BTree;
+use file_extent::{Container, SubExtent};
+use libc::{LOCK_EX, LOCK_SH};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use table::DatabaseTable;
+use traits::{NodeElementHash, NodeHash, OrderKind};
+use variable_storage::VariableExtent;
+/// Table manager that keeps every table in a single file, see `Container`. Tables are named
+/// the same way as the files of a `BackedManager`. The log is kept next to the file in
+/// `{path}-wal`
+pub struct ContainerManager {
+    path: PathBuf,
+    container: Container,
+    wal: Arc<Mutex<Wal>>,
+    //holds the lock on the database until the manager is dropped
+    _lock: File,
+    read_only: bool,
+}
+impl ContainerManager {
+    /// Opens the database in the file at path, creating it if it does not exist. An operation
+    /// left unfinished in the log is rolled back. Fails if the database is already open
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let lock = lock(file, LOCK_EX, path)?;
+        let container = Container::open(path.to_str().unwrap().to_string())?;
+        let log = Self::wal_path(path);
+        if log.exists() && log.metadata()?.len() > 0 {
+            let remove = |name: &str| {
+                if container.contains(name) {
+                    container.remove(name)?;
+                }
+                Ok(())
+            };
+            Wal::recover(&log, None, |name: &str| container.open_extent(name), remove)?;
+            container.commit()?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            container,
+            wal: Arc::new(Mutex::new(Wal::open(log, None)?)),
+            _lock: lock,
+            read_only: false,
+        })
+    }
+    /// Opens an existing database without write access. Any number of read only opens may
+    /// share a database but it can not be open for writing at the same time
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let lock = lock(File::open(path)?, LOCK_SH, path)?;
+        let log = Self::wal_path(path);
+        if log.exists() && log.metadata()?.len() > 0 {
+            return Err(anyhow!(
+                "{}",
+                StorageError::NeedsRecovery {
+                    path: path.display().to_string()
+                }
+            ));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            container: Container::open_read_only(path.to_str().unwrap().to_string())?,
+            wal: Arc::new(Mutex::new(Wal::open(log, None)?)),
+            _lock: lock,
+            read_only: true,
+        })
+    }
+    /// Rewrites every table stored in an older format in the current one so that the database
+    /// can be opened with `Database::new`. Returns the number of tables rewritten
+    pub fn upgrade(&self) -> Result<usize> {
+        if self.read_only {
+            return Err(anyhow!(
+                "{}",
+                StorageError::ReadOnly {
+                    path: self.path.display().to_string()
+                }
+            ));
+        }
+        self.upgrade_tables()
+    }
+    /// Gets the path of the log of the database in the file at path
+    fn wal_path(path: &Path) -> PathBuf {
+        let mut log = path.as_os_str().to_owned();
+        log.push("-wal");
+        PathBuf::from(log)
+    }
+}
+impl NamedTables for ContainerManager {
+    type Extent = SubExtent;
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+    fn exists(&self, name: &str) -> bool {
+        self.container.contains(name)
+    }
+    fn names(&self, directory: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}/", directory);
+        Ok(self
+            .container
+            .names()
+            .into_iter()
+            .filter(|name| name.starts_with(&prefix))
+            .collect())
+    }
+    fn open(&self, name: String) -> Result<LoggedExtent<SubExtent>> {
+        let extent = self.container.open_extent(&name)?;
+        Ok(LoggedExtent::open(extent, name, self.wal.clone()))
+    }
+    fn create(&self, name: String) -> Result<LoggedExtent<SubExtent>> {
+        let container = self.container.clone();
+        let extent_name = name.clone();
+        LoggedExtent::create(name, self.wal.clone(), move || {
+            container.open_extent(&extent_name)
+        })
+    }
+    fn describe(&self, name: &str) -> String {
+        format!("{} in {}", name, self.path.display())
+    }
+}
+impl TableManager for ContainerManager {
+    type ExtentType = LoggedExtent<SubExtent>;
+
+    /// Rebuilds the tables of every type and field recorded in the catalog
+    fn get(&mut self) -> Result<TableStartup<Self::ExtentType>> {
+        self.startup()
+    }
+    fn get_node_contents(
+        &mut self,
+        hash: NodeHash,
+        data_size: usize,
+    ) -> DatabaseTable<Self::ExtentType> {
+        self.create_table("contents", hash.hash, "static", |extent| {
+            DatabaseTable::new(extent, data_size)
+        })
+    }
+    fn get_edge_contents(
+        &mut self,
+        hash: NodeHash,
+        data_size: usize,
+    ) -> DatabaseTable<Self::ExtentType> {
+        self.create_table("edges", hash.hash, "static", |extent| {
+            DatabaseTable::new(extent, data_size)
+        })
+    }
+    fn get_sized(
+        &mut self,
+        hash: NodeElementHash,
+        data_size: usize,
+    ) -> DatabaseTable<Self::ExtentType> {
+        self.create_table("static", hash.hash, "static", |extent| {
+            DatabaseTable::new(extent, data_size)
+        })
+    }
+    fn get_variable(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
+        self.create_table("variable", hash.hash, "var", |extent| {
+            Ok(VariableExtent::new(extent))
+        })
+    }
+    fn get_index(&mut self, hash: NodeElementHash) -> VariableExtent<Self::ExtentType> {
+        self.create_table("indexes", hash.hash, "var", |extent| {
+            Ok(VariableExtent::new(extent))
+        })
+    }
+    fn get_ordered_index(
+        &mut self,
+        hash: NodeElementHash,
+        kind: OrderKind,
+    ) -> BTree<Self::ExtentType> {
+        self.create_table("ordered", hash.hash, "btree", |extent| {
+            BTree::new(extent, kind as u64)
+        })
+    }
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+    fn begin(&mut self, operation: Operation) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!(
+                "{}",
+                StorageError::ReadOnly {
+                    path: self.path.display().to_string()
+                }
+            ));
+        }
+        self.wal.lock().unwrap().begin(operation)
+    }
+    fn commit(&mut self) -> Result<()> {
+        self.wal.lock().unwrap().commit()
+    }
+    fn checkpoint(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.wal.lock().unwrap().checkpoint()
+    }
+}
@@ -0,0 +1,268 @@
+use super::TableStartup;
+use crate::catalog::{Catalog, FieldKind};
+use crate::wal::LoggedExtent;
+use anyhow::Result;
+use btree::BTree;
+use libc::{flock, EWOULDBLOCK, LOCK_NB};
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use table::{DatabaseTable, TableError};
+use thiserror::Error;
+use traits::{
+    ChecksumError, CompressedExtentError, EncryptedExtentError, Extent, FormatError,
+    NodeElementHash,
+};
+use variable_storage::VariableExtent;
+#[derive(Error, Debug)]
+pub(super) enum StorageError {
+    #[error("{path} is not named after a hash")]
+    InvalidFileName { path: String },
+    #[error("{path} does not hold a valid table")]
+    InvalidTable { path: String },
+    #[error("{path}: {error}")]
+    Format { path: String, error: FormatError },
+    #[error("{path} holds elements of size {found} but the catalog records {expected}")]
+    SizeMismatch {
+        path: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("database at {path} is already open")]
+    AlreadyOpen { path: String },
+    #[error("database at {path} is open read only")]
+    ReadOnly { path: String },
+    #[error("log of the database at {path} holds unfinished operations, open it for writing to roll them back")]
+    NeedsRecovery { path: String },
+    #[error("{path} is damaged")]
+    Damaged { path: String },
+}
+/// Gets the name of a table, tables are named like this in the log
+pub(super) fn table_name(directory: &str, hash: usize, extension: &str) -> String {
+    format!("{}/{}.{}", directory, hash, extension)
+}
+/// Takes an advisory lock on file, which is released when the file is closed. path names the
+/// database in errors
+pub(super) fn lock(file: File, operation: i32, path: &Path) -> Result<File> {
+    if unsafe { flock(file.as_raw_fd(), operation | LOCK_NB) } == -1 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() == Some(EWOULDBLOCK) {
+            return Err(anyhow!(
+                "{}",
+                StorageError::AlreadyOpen {
+                    path: path.display().to_string()
+                }
+            ));
+        }
+        return Err(error.into());
+    }
+    Ok(file)
+}
+/// Turns an error from loading the table at path into a `StorageError`. A `ChecksumError`
+/// already names the file and offset and is returned as it is, other damage keeps its error
+/// under `StorageError::Damaged`
+fn load_error(error: anyhow::Error, path: String) -> anyhow::Error {
+    if error.is::<ChecksumError>() {
+        return error;
+    }
+    if error.is::<CompressedExtentError>() || error.is::<EncryptedExtentError>() {
+        return error.context(StorageError::Damaged { path });
+    }
+    match error.downcast::<FormatError>() {
+        Ok(error) => anyhow!("{}", StorageError::Format { path, error }),
+        Err(_) => anyhow!("{}", StorageError::InvalidTable { path }),
+    }
+}
+/// Rewrites a table stored in an older format, see `NamedTables::upgrade_tables`
+type Upgrade<E> = fn(&mut LoggedExtent<E>) -> Result<bool>;
+fn element_hash(hash: usize) -> NodeElementHash {
+    NodeElementHash { hash }
+}
+///# Named tables
+///
+///Storage that keeps every table under a name such as `variable/{hash}.var`. The table
+///managers that keep their tables on disk implement this and load their tables with the
+///provided methods. The names used are:
+///
+///|-catalog.var
+///|-node_storage.var
+///|-contents/{hash}.static
+///|-edges/{hash}.static
+///|-variable/{hash}.var
+///|-static/{hash}.static
+///|-indexes/{hash}.var
+///|-ordered/{hash}.btree
+pub(super) trait NamedTables {
+    type Extent: Extent;
+    fn read_only(&self) -> bool;
+    /// Whether the table called name exists
+    fn exists(&self, name: &str) -> bool;
+    /// Gets the names of the tables in directory
+    fn names(&self, directory: &str) -> Result<Vec<String>>;
+    /// Opens the table called name, creating it if it does not exist
+    fn open(&self, name: String) -> Result<LoggedExtent<Self::Extent>>;
+    /// Creates the table called name. If an operation is running rolling it back removes the
+    /// table
+    fn create(&self, name: String) -> Result<LoggedExtent<Self::Extent>>;
+    /// Names the table called name in errors
+    fn describe(&self, name: &str) -> String;
+    /// Creates the table of a hash and builds the structure stored in it with make. Tables are
+    /// created part way through an operation that can not fail, so an error panics with the
+    /// message of `load_error`
+    fn create_table<T, F: FnOnce(LoggedExtent<Self::Extent>) -> Result<T>>(
+        &self,
+        directory: &str,
+        hash: usize,
+        extension: &str,
+        make: F,
+    ) -> T {
+        let name = table_name(directory, hash, extension);
+        let path = self.describe(&name);
+        match self.create(name).and_then(make) {
+            Ok(table) => table,
+            Err(error) => panic!("failed to create a table: {}", load_error(error, path)),
+        }
+    }
+    /// Loads a `VariableExtent` made by `VariableExtent::new`, creating it if it does not exist
+    fn load_variable(&self, name: String) -> Result<VariableExtent<LoggedExtent<Self::Extent>>> {
+        if !self.exists(&name) {
+            //made outside of an operation so it is written straight away, the log can not
+            //remove it if it is cut off
+            let mut extent = VariableExtent::new(self.open(name)?);
+            if !self.read_only() {
+                extent.flush()?;
+            }
+            return Ok(extent);
+        }
+        let path = self.describe(&name);
+        VariableExtent::load(self.open(name)?).map_err(|error| load_error(error, path))
+    }
+    /// Loads the table of a hash recorded in the catalog if it has been made. Fails if the
+    /// element size of the table disagrees with the catalog
+    fn load_table(
+        &self,
+        directory: &str,
+        hash: usize,
+        element_size: usize,
+    ) -> Result<Option<DatabaseTable<LoggedExtent<Self::Extent>>>> {
+        let name = table_name(directory, hash, "static");
+        if !self.exists(&name) {
+            return Ok(None);
+        }
+        let path = self.describe(&name);
+        let table = match DatabaseTable::load(self.open(name)?) {
+            Ok(table) => table,
+            Err(TableError::Format(error)) => {
+                return Err(anyhow!("{}", StorageError::Format { path, error }))
+            }
+            Err(TableError::Damaged(error)) => return Err(load_error(error, path)),
+            Err(_) => return Err(anyhow!("{}", StorageError::InvalidTable { path })),
+        };
+        if table.element_size() != element_size {
+            return Err(anyhow!(
+                "{}",
+                StorageError::SizeMismatch {
+                    path,
+                    expected: element_size,
+                    found: table.element_size(),
+                }
+            ));
+        }
+        Ok(Some(table))
+    }
+    /// Loads every table in directory. Tables are named after their hash
+    fn load_directory<K: Eq + Hash, T>(
+        &self,
+        directory: &str,
+        to_hash: fn(usize) -> K,
+        load: fn(LoggedExtent<Self::Extent>) -> Result<T>,
+    ) -> Result<HashMap<K, T>> {
+        let mut tables = HashMap::new();
+        for name in self.names(directory)? {
+            let path = self.describe(&name);
+            let hash = match Path::new(&name)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                Some(hash) => hash,
+                None => return Err(anyhow!("{}", StorageError::InvalidFileName { path })),
+            };
+            let table = load(self.open(name)?).map_err(|error| load_error(error, path))?;
+            tables.insert(to_hash(hash), table);
+        }
+        Ok(tables)
+    }
+    /// Rebuilds the tables of every type and field recorded in the catalog
+    fn startup(&self) -> Result<TableStartup<LoggedExtent<Self::Extent>>> {
+        let catalog = Catalog::load(self.load_variable("catalog.var".to_string())?);
+        let node_storage = self.load_variable("node_storage.var".to_string())?;
+        let mut node_contents = HashMap::new();
+        let mut edge_contents = HashMap::new();
+        for (hash, entry) in catalog.nodes() {
+            if let Some(table) = self.load_table("contents", hash.hash, entry.element_size)? {
+                node_contents.insert(hash.clone(), table);
+            }
+            if let Some(table) = self.load_table("edges", hash.hash, entry.element_size)? {
+                edge_contents.insert(hash.clone(), table);
+            }
+        }
+        let mut variable = HashMap::new();
+        let mut sized = HashMap::new();
+        for (hash, entry) in catalog.fields() {
+            match entry.kind {
+                FieldKind::Sized => {
+                    if let Some(table) = self.load_table("static", hash.hash, entry.element_size)? {
+                        sized.insert(hash.clone(), table);
+                    }
+                }
+                FieldKind::Variable => {
+                    let name = table_name("variable", hash.hash, "var");
+                    if self.exists(&name) {
+                        variable.insert(hash.clone(), self.load_variable(name)?);
+                    }
+                }
+            }
+        }
+        Ok(TableStartup {
+            node_storage,
+            node_contents,
+            edge_contents,
+            variable,
+            sized,
+            indexes: self.load_directory("indexes", element_hash, VariableExtent::load)?,
+            ordered_indexes: self.load_directory("ordered", element_hash, BTree::load)?,
+            catalog,
+        })
+    }
+    /// Rewrites every table stored in an older format in the current one. Returns the number
+    /// of tables rewritten
+    fn upgrade_tables(&self) -> Result<usize> {
+        let groups: Vec<(Vec<String>, Upgrade<Self::Extent>)> = vec![
+            (
+                vec!["catalog.var".to_string(), "node_storage.var".to_string()],
+                VariableExtent::upgrade,
+            ),
+            (self.names("variable")?, VariableExtent::upgrade),
+            (self.names("indexes")?, VariableExtent::upgrade),
+            (self.names("contents")?, DatabaseTable::upgrade),
+            (self.names("edges")?, DatabaseTable::upgrade),
+            (self.names("static")?, DatabaseTable::upgrade),
+            (self.names("ordered")?, BTree::upgrade),
+        ];
+        let mut upgraded = 0;
+        for (names, upgrade) in groups {
+            for name in names.into_iter().filter(|name| self.exists(name)) {
+                let path = self.describe(&name);
+                let mut extent = self.open(name)?;
+                if upgrade(&mut extent).map_err(|error| load_error(error, path))? {
+                    upgraded += 1;
+                }
+            }
+        }
+        Ok(upgraded)
+    }
+}
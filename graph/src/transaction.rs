@@ -0,0 +1,121 @@
+use super::{Database, DatabseError, Key, Link};
+use crate::table_manager::TableManager;
+use crate::wal::Operation;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use traits::Node;
+use variable_storage::Key as VariableKey;
+/// Changes made inside `Database::transaction`. Changes are made to the database as they
+/// happen so that they are visible to `get`, the transaction keeps track of them so that they
+/// can be undone if the transaction fails
+pub struct Transaction<'a, Manager: TableManager> {
+    db: &'a mut Database<Manager>,
+    //nodes inserted by the transaction
+    inserted: Vec<Key>,
+    //links added by the transaction, one entry per direction
+    linked: Vec<(Key, Key)>,
+    //operations running on the database when the transaction started, including its own
+    depth: usize,
+}
+impl<'a, Manager: TableManager> Transaction<'a, Manager> {
+    /// Inserts a node, see `Database::insert`
+    pub fn insert<Data: Node>(&mut self, data: Data) -> Result<Key, DatabseError> {
+        self.db.insert_noting(data, &mut self.inserted)
+    }
+    /// Links two nodes in both directions, see `Database::connect`
+    pub fn connect(&mut self, key1: Key, key2: Key) -> Result<(), DatabseError> {
+        for key in [&key1, &key2] {
+            if !self.db.node_storage.contains_key(key.key.clone()) {
+                return Err(DatabseError::InvalidKey(key.clone()));
+            }
+        }
+        //the links are noted first so that a panic part way through still removes them,
+        //removing a link that was never added does nothing
+        let len = self.linked.len();
+        self.linked.push((key1.clone(), key2.clone()));
+        self.linked.push((key2.clone(), key1.clone()));
+        let result = self.db.connect(key1, key2);
+        if result.is_err() {
+            self.linked.truncate(len);
+        }
+        result
+    }
+    /// Gets a node, nodes inserted by the transaction are included
+    pub fn get<Data: Node>(&self, key: Key) -> Option<Data> {
+        self.db.get(key)
+    }
+    /// Undoes every change made by the transaction, newest first
+    fn roll_back(&mut self) {
+        for (from, to) in std::mem::take(&mut self.linked).into_iter().rev() {
+            self.db.remove_link(from.key, to.key);
+        }
+        for key in std::mem::take(&mut self.inserted).into_iter().rev() {
+            if self.db.remove(key.clone()).is_err() {
+                eprintln!("failed to roll back the insert of {:?}", key);
+            }
+        }
+    }
+}
+impl<'a, Manager: TableManager> Drop for Transaction<'a, Manager> {
+    /// Ends the operations that a panic inside f cut off, so that the depth of the database
+    /// and of its log are back to where they were when the transaction started
+    fn drop(&mut self) {
+        self.db.end_operations(self.depth);
+    }
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Runs f as one unit. The changes made through the `Transaction` are kept if f returns
+    /// `Ok` and discarded if it returns `Err` or panics. On disk the transaction is a single
+    /// operation in the log, so a crash part way through rolls back all of it
+    pub fn transaction<T, E, F: FnOnce(&mut Transaction<'_, Manager>) -> Result<T, E>>(
+        &mut self,
+        f: F,
+    ) -> Result<T, E> {
+        //a read only database refuses every change made by f, so there is nothing to log
+        let began = self.begin(Operation::Transaction).is_ok();
+        let depth = self.depth;
+        let mut transaction = Transaction {
+            db: self,
+            inserted: vec![],
+            linked: vec![],
+            depth,
+        };
+        let result = catch_unwind(AssertUnwindSafe(|| f(&mut transaction)));
+        if !matches!(result, Ok(Ok(_))) {
+            transaction.roll_back();
+        }
+        drop(transaction);
+        if began {
+            self.commit();
+        }
+        match result {
+            Ok(result) => result,
+            Err(panic) => resume_unwind(panic),
+        }
+    }
+    /// Ends the operations started after depth, used once a panic cut them off. Nested
+    /// operations write nothing to the log, so only the depths need to be brought back
+    fn end_operations(&mut self, depth: usize) {
+        while self.depth > depth {
+            if let Err(error) = self.table_manager.commit() {
+                panic!("failed to write to the log: {}", error);
+            }
+            self.depth -= 1;
+        }
+    }
+    /// Removes the newest unlabelled link going from `from` to `to`
+    fn remove_link(&mut self, from: VariableKey, to: VariableKey) {
+        fn is_link<'b>(node: &'b VariableKey) -> impl Fn(&Link) -> bool + 'b {
+            move |link| &link.node == node && link.label == Link::NO_LABEL
+        }
+        self.edit_node_keys(from.clone(), |data| {
+            if let Some(index) = data.outgoing.iter().rposition(is_link(&to)) {
+                data.outgoing.remove(index);
+            }
+        });
+        self.edit_node_keys(to.clone(), |data| {
+            if let Some(index) = data.incoming.iter().rposition(is_link(&from)) {
+                data.incoming.remove(index);
+            }
+        });
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Database, Key, NodeKeyStorage};
+use crate::table_manager::TableManager;
+use std::collections::{HashSet, VecDeque};
+use traits::{Node, NodeHash};
+enum Order {
+    BreadthFirst,
+    DepthFirst,
+}
+/// Iterator over the nodes reachable from a start node. Yields each node once along with its
+/// depth from the start node. Created with `Database::bfs` or `Database::dfs`
+pub struct Traversal<'a, Manager: TableManager> {
+    database: &'a Database<Manager>,
+    order: Order,
+    //nodes waiting to be visited along with their depth
+    frontier: VecDeque<(Key, usize)>,
+    visited: HashSet<Key>,
+    max_depth: Option<usize>,
+    filter: Option<NodeHash>,
+}
+impl<'a, Manager: TableManager> Traversal<'a, Manager> {
+    fn new(database: &'a Database<Manager>, start: Key, order: Order) -> Self {
+        let mut frontier = VecDeque::new();
+        let mut visited = HashSet::new();
+        if database.node_storage.contains_key(start.key.clone()) {
+            if let Order::BreadthFirst = order {
+                visited.insert(start.clone());
+            }
+            frontier.push_back((start, 0));
+        }
+        Self {
+            database,
+            order,
+            frontier,
+            visited,
+            max_depth: None,
+            filter: None,
+        }
+    }
+    /// Stops the traversal from going further than depth links away from the start node
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+    /// Only yields nodes of type `Data`. Nodes of other types are still walked through
+    pub fn filter<Data: Node>(mut self) -> Self {
+        self.filter = Some(Data::SELF_HASH);
+        self
+    }
+    /// Nodes that have been reached so far
+    pub fn visited(&self) -> &HashSet<Key> {
+        &self.visited
+    }
+    /// Takes the next node to visit, skipping nodes already visited by the depth first search
+    fn next_node(&mut self) -> Option<(Key, usize)> {
+        match self.order {
+            Order::BreadthFirst => self.frontier.pop_front(),
+            Order::DepthFirst => loop {
+                let (key, depth) = self.frontier.pop_back()?;
+                if self.visited.insert(key.clone()) {
+                    return Some((key, depth));
+                }
+            },
+        }
+    }
+}
+impl<'a, Manager: TableManager> Iterator for Traversal<'a, Manager> {
+    type Item = (Key, usize);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, depth) = self.next_node()?;
+            let data =
+                NodeKeyStorage::from_binary(self.database.node_storage.get_entry(key.key.clone()));
+            if !matches!(self.max_depth, Some(max_depth) if depth >= max_depth) {
+                match self.order {
+                    Order::BreadthFirst => {
+                        for link in data.outgoing.iter() {
+                            let linked = Key {
+                                key: link.node.clone(),
+                            };
+                            if self.visited.insert(linked.clone()) {
+                                self.frontier.push_back((linked, depth + 1));
+                            }
+                        }
+                    }
+                    //pushed in reverse so that links are walked in the order they were added
+                    Order::DepthFirst => {
+                        for link in data.outgoing.iter().rev() {
+                            let linked = Key {
+                                key: link.node.clone(),
+                            };
+                            if !self.visited.contains(&linked) {
+                                self.frontier.push_back((linked, depth + 1));
+                            }
+                        }
+                    }
+                }
+            }
+            if !matches!(&self.filter, Some(hash) if hash != &data.self_hash) {
+                return Some((key, depth));
+            }
+        }
+    }
+}
+impl<Manager: TableManager> Database<Manager> {
+    /// Walks the nodes reachable from start breadth first
+    pub fn bfs(&self, start: Key) -> Traversal<'_, Manager> {
+        Traversal::new(self, start, Order::BreadthFirst)
+    }
+    /// Walks the nodes reachable from start depth first
+    pub fn dfs(&self, start: Key) -> Traversal<'_, Manager> {
+        Traversal::new(self, start, Order::DepthFirst)
+    }
+}
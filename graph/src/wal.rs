@@ -0,0 +1,576 @@
+use super::Key;
+use anyhow::Result;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::{Index, IndexMut, Range};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use traits::{seal, unseal, Extent, FormatHeader, Insertable, NodeElementHash, NodeHash};
+use variable_storage::Key as VariableKey;
+#[derive(Error, Debug)]
+enum WalError {
+    #[error("log is encrypted, open the database with its key")]
+    MissingKey,
+    #[error("record at {offset} of the log could not be decrypted")]
+    InvalidRecord { offset: usize },
+}
+/// Logical operation on a database. Every change made by an operation is logged so that an
+/// operation cut off by a crash can be rolled back
+#[derive(Clone)]
+pub enum Operation {
+    Insert(NodeHash),
+    Update(Key),
+    Remove(Key),
+    Connect(Key, Key),
+    Disconnect(Key, Key),
+    DisconnectAll(Key),
+    CreateIndex(NodeElementHash),
+    Register(NodeHash),
+    /// Changes made inside `Database::transaction`
+    Transaction,
+}
+impl Operation {
+    fn to_binary(&self) -> Vec<u8> {
+        let (kind, first, second) = match self {
+            Self::Insert(hash) => (0u8, hash.hash, 0),
+            Self::Update(key) => (1, key.key.index, 0),
+            Self::Remove(key) => (2, key.key.index, 0),
+            Self::Connect(from, to) => (3, from.key.index, to.key.index),
+            Self::Disconnect(from, to) => (4, from.key.index, to.key.index),
+            Self::DisconnectAll(key) => (5, key.key.index, 0),
+            Self::CreateIndex(hash) => (6, hash.hash, 0),
+            Self::Register(hash) => (7, hash.hash, 0),
+            Self::Transaction => (8, 0, 0),
+        };
+        let mut buffer = vec![kind];
+        buffer.extend_from_slice(&first.to_le_bytes());
+        buffer.extend_from_slice(&second.to_le_bytes());
+        buffer
+    }
+    fn from_binary(data: &[u8]) -> Self {
+        let first = usize::from_binary(data[1..9].to_vec());
+        let second = usize::from_binary(data[9..17].to_vec());
+        let key = |index| Key {
+            key: VariableKey { index },
+        };
+        match data[0] {
+            0 => Self::Insert(NodeHash { hash: first }),
+            1 => Self::Update(key(first)),
+            2 => Self::Remove(key(first)),
+            3 => Self::Connect(key(first), key(second)),
+            4 => Self::Disconnect(key(first), key(second)),
+            5 => Self::DisconnectAll(key(first)),
+            6 => Self::CreateIndex(NodeElementHash { hash: first }),
+            7 => Self::Register(NodeHash { hash: first }),
+            _ => Self::Transaction,
+        }
+    }
+}
+/// Appends the length of bytes followed by bytes
+fn push_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&bytes.len().to_le_bytes());
+    buffer.extend_from_slice(bytes);
+}
+/// Undo information for a change to an extent
+enum Record {
+    /// Start of a logical operation
+    Begin(Operation),
+    /// Bytes at offset before they were overwritten
+    Write {
+        extent: String,
+        offset: usize,
+        before: Vec<u8>,
+    },
+    /// Length of the extent before it was resized and the bytes cut off if it shrunk
+    Resize {
+        extent: String,
+        len: usize,
+        tail: Vec<u8>,
+    },
+    /// The extent was created by the operation
+    Create { extent: String },
+}
+impl Record {
+    fn to_binary(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        match self {
+            Self::Begin(operation) => {
+                buffer.push(0);
+                push_bytes(&mut buffer, &operation.to_binary());
+            }
+            Self::Write {
+                extent,
+                offset,
+                before,
+            } => {
+                buffer.push(1);
+                push_bytes(&mut buffer, extent.as_bytes());
+                buffer.extend_from_slice(&offset.to_le_bytes());
+                push_bytes(&mut buffer, before);
+            }
+            Self::Resize { extent, len, tail } => {
+                buffer.push(2);
+                push_bytes(&mut buffer, extent.as_bytes());
+                buffer.extend_from_slice(&len.to_le_bytes());
+                push_bytes(&mut buffer, tail);
+            }
+            Self::Create { extent } => {
+                buffer.push(3);
+                push_bytes(&mut buffer, extent.as_bytes());
+            }
+        }
+        buffer
+    }
+    /// Parses a record, returns None if data was cut off
+    fn from_binary(data: &[u8]) -> Option<Self> {
+        let mut start = 1;
+        let read_usize = |start: &mut usize| -> Option<usize> {
+            let bytes = data.get(*start..*start + 8)?;
+            *start += 8;
+            Some(usize::from_binary(bytes.to_vec()))
+        };
+        let read_bytes = |start: &mut usize| -> Option<Vec<u8>> {
+            let len = read_usize(start)?;
+            let bytes = data.get(*start..*start + len)?.to_vec();
+            *start += len;
+            Some(bytes)
+        };
+        let read_string = |bytes: Vec<u8>| -> Option<String> { String::from_utf8(bytes).ok() };
+        match data.first()? {
+            0 => Some(Self::Begin(Operation::from_binary(&read_bytes(
+                &mut start,
+            )?))),
+            1 => {
+                let extent = read_string(read_bytes(&mut start)?)?;
+                let offset = read_usize(&mut start)?;
+                Some(Self::Write {
+                    extent,
+                    offset,
+                    before: read_bytes(&mut start)?,
+                })
+            }
+            2 => {
+                let extent = read_string(read_bytes(&mut start)?)?;
+                let len = read_usize(&mut start)?;
+                Some(Self::Resize {
+                    extent,
+                    len,
+                    tail: read_bytes(&mut start)?,
+                })
+            }
+            _ => Some(Self::Create {
+                extent: read_string(read_bytes(&mut start)?)?,
+            }),
+        }
+    }
+}
+///# Write-ahead undo log
+///
+///While an operation is running every extent change is logged before it is made, the log is
+///synced to disk so that the undo information always reaches the disk before the change. The
+///log is emptied by `checkpoint` once the extents have been flushed, so it holds every operation
+///since the last flush. A log that is not empty when the database is opened belongs to
+///operations that may not have reached the disk, `recover` rolls all of them back.
+///
+///A log that is not empty starts with a `FormatHeader`, records are stored after it one after
+///another as the length of the record (8 bytes) followed by the record. Version 0 logs have no
+///format header and are read from the start, their records are the same.
+///
+///The log holds the bytes of the extents, so the log of an encrypted database is encrypted with
+///the same key. Flag 1 of the format header is set and every record is stored sealed with
+///`traits::seal`, the offset of the record in the log is authenticated along with it.
+pub struct Wal {
+    file: File,
+    //records are encrypted with the key if there is one
+    key: Option<[u8; 32]>,
+    //length of the log file
+    len: usize,
+    //nothing has been written since the log was emptied, the format header comes first
+    empty: bool,
+    //number of nested operations running
+    depth: usize,
+    //incremented at every checkpoint so that extents know to log pages again
+    generation: u64,
+}
+impl Wal {
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WLOG";
+    //flag set if the records are encrypted
+    const ENCRYPTED: u32 = 1;
+    /// Opens the log at path, creating it if it does not exist. Records are encrypted with key
+    /// if it is given
+    pub fn open<P: AsRef<Path>>(path: P, key: Option<[u8; 32]>) -> Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        Ok(Self {
+            key,
+            len,
+            empty: len == 0,
+            file,
+            depth: 0,
+            generation: 0,
+        })
+    }
+    pub fn begin(&mut self, operation: Operation) -> Result<()> {
+        if self.depth == 0 {
+            self.append(Record::Begin(operation))?;
+        }
+        self.depth += 1;
+        Ok(())
+    }
+    pub fn commit(&mut self) -> Result<()> {
+        self.depth -= 1;
+        Ok(())
+    }
+    /// Empties the log. Must only be called once every extent has been flushed, does nothing
+    /// while an operation is running
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if self.depth == 0 {
+            self.file.set_len(0)?;
+            self.file.sync_data()?;
+            self.len = 0;
+            self.empty = true;
+            self.generation += 1;
+        }
+        Ok(())
+    }
+    fn is_active(&self) -> bool {
+        self.depth > 0
+    }
+    fn append(&mut self, record: Record) -> Result<()> {
+        let mut buffer = vec![];
+        if self.empty {
+            let flags = if self.key.is_some() {
+                Self::ENCRYPTED
+            } else {
+                0
+            };
+            buffer.extend_from_slice(
+                &FormatHeader::new(Self::MAGIC, Self::VERSION, flags).to_binary(),
+            );
+        }
+        let mut bytes = record.to_binary();
+        if let Some(key) = &self.key {
+            let offset = self.len + buffer.len();
+            bytes = seal(key, &bytes, &(offset as u64).to_le_bytes())?;
+        }
+        buffer.extend_from_slice(&bytes.len().to_le_bytes());
+        buffer.extend_from_slice(&bytes);
+        self.file.write_all(&buffer)?;
+        self.file.sync_data()?;
+        self.len += buffer.len();
+        self.empty = false;
+        Ok(())
+    }
+    /// Records that an extent was created by the running operation
+    fn log_create(&mut self, extent: &str) -> Result<()> {
+        self.append(Record::Create {
+            extent: extent.to_string(),
+        })
+    }
+    /// Rolls back the operations left in the log at path. Extents are opened by name with open
+    /// and extents made by a rolled back operation are deleted with remove. key decrypts the
+    /// records of an encrypted log
+    pub fn recover<E, P, O, R>(
+        path: P,
+        key: Option<[u8; 32]>,
+        mut open: O,
+        mut remove: R,
+    ) -> Result<()>
+    where
+        E: Extent,
+        P: AsRef<Path>,
+        O: FnMut(&str) -> Result<E>,
+        R: FnMut(&str) -> Result<()>,
+    {
+        let mut data = vec![];
+        File::open(&path)?.read_to_end(&mut data)?;
+        let mut records = vec![];
+        let mut encrypted = false;
+        let mut start = match FormatHeader::parse(&data, Self::MAGIC) {
+            Some(header) => {
+                header.check("log", Self::VERSION, Self::ENCRYPTED)?;
+                encrypted = header.flags & Self::ENCRYPTED != 0;
+                FormatHeader::SIZE
+            }
+            None => 0,
+        };
+        let key = match key {
+            Some(key) if encrypted => Some(key),
+            None if encrypted => return Err(anyhow!("{}", WalError::MissingKey)),
+            _ => None,
+        };
+        while start + 8 <= data.len() {
+            let len = usize::from_binary(data[start..start + 8].to_vec());
+            let end = start + 8 + len;
+            let bytes = match (data.get(start + 8..end), &key) {
+                (Some(bytes), Some(key)) => {
+                    match unseal(key, bytes, &(start as u64).to_le_bytes()) {
+                        Some(bytes) => Some(bytes),
+                        //a record that was being written may be torn, any other one is damaged
+                        None if end == data.len() => None,
+                        None => {
+                            return Err(anyhow!("{}", WalError::InvalidRecord { offset: start }))
+                        }
+                    }
+                }
+                (bytes, _) => bytes.map(|bytes| bytes.to_vec()),
+            };
+            match bytes.as_deref().and_then(Record::from_binary) {
+                Some(record) => records.push(record),
+                //the record was being written when the crash happened so its change was never made
+                None => break,
+            }
+            start = end;
+        }
+        let mut extents: HashMap<String, E> = HashMap::new();
+        for record in records.into_iter().rev() {
+            match record {
+                Record::Begin(_) => {}
+                Record::Write {
+                    extent,
+                    offset,
+                    before,
+                } => {
+                    //extents that only write flushed changes, such as `CompressedExtent`,
+                    //may not have grown to hold the page yet. Undoing the resize that grew
+                    //them drops the page anyway
+                    let file = open_extent(&mut extents, &extent, &mut open)?;
+                    let end = std::cmp::min(offset + before.len(), file.len());
+                    if offset < end {
+                        file.write_at(offset, &before[..end - offset]);
+                    }
+                }
+                Record::Resize { extent, len, tail } => {
+                    let file = open_extent(&mut extents, &extent, &mut open)?;
+                    file.resize(len)?;
+                    //the tail is empty if the extent grew
+                    if !tail.is_empty() {
+                        file.write_at(len - tail.len(), &tail);
+                    }
+                }
+                Record::Create { extent } => {
+                    extents.remove(&extent);
+                    remove(&extent)?;
+                }
+            }
+        }
+        drop(extents);
+        OpenOptions::new().write(true).open(&path)?.set_len(0)?;
+        Ok(())
+    }
+}
+/// Gets an extent opened during recovery, opening it if it has not been opened yet
+fn open_extent<'a, E: Extent, O: FnMut(&str) -> Result<E>>(
+    extents: &'a mut HashMap<String, E>,
+    extent: &str,
+    open: &mut O,
+) -> Result<&'a mut E> {
+    if !extents.contains_key(extent) {
+        extents.insert(extent.to_string(), open(extent)?);
+    }
+    Ok(extents.get_mut(extent).unwrap())
+}
+/// Extent that logs the bytes it is about to change to a `Wal` while an operation is running
+pub struct LoggedExtent<E: Extent> {
+    inner: E,
+    //name of the extent in the log
+    name: String,
+    wal: Arc<Mutex<Wal>>,
+    //pages logged since the last checkpoint
+    logged: HashSet<usize>,
+    generation: u64,
+    //generation the extent was created in. Rolling back the log removes the extent so its
+    //changes are not logged until the next checkpoint
+    created: Option<u64>,
+}
+impl<E: Extent> LoggedExtent<E> {
+    const PAGE_SIZE: usize = 4096;
+    /// Wraps an extent that already exists
+    pub fn open(inner: E, name: String, wal: Arc<Mutex<Wal>>) -> Self {
+        let generation = wal.lock().unwrap().generation;
+        Self {
+            inner,
+            name,
+            wal,
+            logged: HashSet::new(),
+            generation,
+            created: None,
+        }
+    }
+    /// Creates a new extent with make. If an operation is running the creation is logged before
+    /// the extent is made so that rolling back the operation removes it
+    pub fn create<F: FnOnce() -> Result<E>>(
+        name: String,
+        wal: Arc<Mutex<Wal>>,
+        make: F,
+    ) -> Result<Self> {
+        let created = {
+            let mut log = wal.lock().unwrap();
+            if log.is_active() {
+                log.log_create(&name)?;
+                Some(log.generation)
+            } else {
+                None
+            }
+        };
+        let mut extent = Self::open(make()?, name, wal);
+        extent.created = created;
+        Ok(extent)
+    }
+    /// Whether changes to the extent have to be logged
+    fn needs_log(&self, wal: &Wal) -> bool {
+        wal.is_active() && self.created != Some(wal.generation)
+    }
+    /// Logs the pages holding the bytes in range that have not been logged by the running
+    /// operation
+    fn log_pages(&mut self, range: Range<usize>) {
+        let mut wal = self.wal.lock().unwrap();
+        if !self.needs_log(&wal) || range.start == range.end {
+            return;
+        }
+        if wal.generation != self.generation {
+            self.logged.clear();
+            self.generation = wal.generation;
+        }
+        for page in range.start / Self::PAGE_SIZE..=(range.end - 1) / Self::PAGE_SIZE {
+            if self.logged.insert(page) {
+                let start = page * Self::PAGE_SIZE;
+                let end = std::cmp::min(start + Self::PAGE_SIZE, self.inner.len());
+                wal.append(Record::Write {
+                    extent: self.name.clone(),
+                    offset: start,
+                    before: self.inner.as_slice(start..end).into_owned(),
+                })
+                .ok()
+                .unwrap();
+            }
+        }
+    }
+}
+impl<E: Extent> Index<usize> for LoggedExtent<E> {
+    type Output = u8;
+    fn index(&self, idx: usize) -> &Self::Output {
+        &self.inner[idx]
+    }
+}
+impl<E: Extent> IndexMut<usize> for LoggedExtent<E> {
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        self.log_pages(idx..idx + 1);
+        &mut self.inner[idx]
+    }
+}
+impl<E: Extent> Extent for LoggedExtent<E> {
+    fn resize(&mut self, new_size: usize) -> Result<()> {
+        {
+            let mut wal = self.wal.lock().unwrap();
+            if self.needs_log(&wal) {
+                let len = self.inner.len();
+                let tail = self
+                    .inner
+                    .as_slice(std::cmp::min(new_size, len)..len)
+                    .into_owned();
+                wal.append(Record::Resize {
+                    extent: self.name.clone(),
+                    len,
+                    tail,
+                })?;
+            }
+        }
+        self.inner.resize(new_size)
+    }
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        self.inner.read_at(offset, buffer)
+    }
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        self.log_pages(offset..offset + data.len());
+        self.inner.write_at(offset, data)
+    }
+    fn as_slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        self.inner.as_slice(range)
+    }
+    fn flush(&mut self, range: Option<Range<usize>>) -> Result<()> {
+        self.inner.flush(range)
+    }
+    fn verify(&self) -> Result<()> {
+        self.inner.verify()
+    }
+}
+/// Gets the path of the log of the database at root
+pub fn wal_path(root: &Path) -> PathBuf {
+    root.join("wal.log")
+}
+#[cfg(test)]
+mod tests {
+    use super::{wal_path, Wal};
+    use crate::{on_disk_db, on_disk_db_encrypted, Operation, SyncPolicy};
+    use std::fs::remove_dir_all;
+    use traits::{InMemoryExtent, Node};
+    #[test]
+    fn roll_back() {
+        let root = std::env::temp_dir().join(format!("graph_wal_{}", std::process::id()));
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        let key = {
+            let mut db = on_disk_db(&root).ok().unwrap();
+            db.set_sync_policy(SyncPolicy::EveryOperation);
+            let key = db.insert(5u64).ok().unwrap();
+            //cut off before commit
+            db.begin(Operation::Insert(u64::SELF_HASH)).ok().unwrap();
+            db.insert(6u64).ok().unwrap();
+            db.update(key.clone(), 7u64).ok().unwrap();
+            db.insert("new type".to_string()).ok().unwrap();
+            key
+        };
+        let string_table = root
+            .join("contents")
+            .join(format!("{}.static", String::SELF_HASH.hash));
+        assert!(string_table.exists());
+        let mut db = on_disk_db(&root).ok().unwrap();
+        assert!(!string_table.exists());
+        assert_eq!(
+            db.iter::<u64>().map(|(_, data)| data).collect::<Vec<_>>(),
+            vec![5]
+        );
+        assert_eq!(db.get::<u64>(key), Some(5));
+        assert_eq!(db.iter::<String>().count(), 0);
+        let key = db.insert("new type".to_string()).ok().unwrap();
+        drop(db);
+        let db = on_disk_db(&root).ok().unwrap();
+        assert_eq!(db.get::<String>(key), Some("new type".to_string()));
+        drop(db);
+        remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn encrypted_roll_back() {
+        const KEY: [u8; 32] = [5; 32];
+        let root = std::env::temp_dir().join(format!("graph_wal_encrypted_{}", std::process::id()));
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        {
+            let mut db = on_disk_db_encrypted(&root, KEY).ok().unwrap();
+            db.set_sync_policy(SyncPolicy::EveryOperation);
+            db.insert(5u64).ok().unwrap();
+            //cut off before commit
+            db.begin(Operation::Insert(u64::SELF_HASH)).ok().unwrap();
+            db.insert(6u64).ok().unwrap();
+        }
+        let no_extent = |_: &str| -> anyhow::Result<InMemoryExtent> { unreachable!() };
+        assert!(Wal::recover(wal_path(&root), None, no_extent, |_| Ok(())).is_err());
+        let db = on_disk_db_encrypted(&root, KEY).ok().unwrap();
+        assert_eq!(
+            db.iter::<u64>().map(|(_, data)| data).collect::<Vec<_>>(),
+            vec![5]
+        );
+        drop(db);
+        remove_dir_all(&root).unwrap();
+    }
+}
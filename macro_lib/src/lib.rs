@@ -56,8 +56,11 @@ pub fn node(input: TokenStream) -> TokenStream {
     ]);
 
     let name = &ast.ident;
+    let name_string = name.to_string();
+    let field_names: Vec<String> = sized.iter().map(|field| field.to_string()).collect();
     let tree = quote! {impl Node for #name{
         const SELF_HASH: NodeHash=NodeHash{hash:#hash};
+        const NAME: &'static str=#name_string;
         fn get_sized_hashes() -> Vec<NodeElementHash> {
             let mut out = vec![];
             #({
@@ -83,6 +86,32 @@ pub fn node(input: TokenStream) -> TokenStream {
             })*
             return out;
         }
+        fn get_sized_names() -> Vec<String> {
+            let mut out = vec![];
+            #({
+                for name in #types::get_sized_names(){
+                    if name.is_empty(){
+                        out.push(#field_names.to_string());
+                    }else{
+                        out.push(format!("{}.{}",#field_names,name));
+                    }
+                }
+            })*
+            return out;
+        }
+        fn get_variable_names() -> Vec<String> {
+            let mut out = vec![];
+            #({
+                for name in #types::get_variable_names(){
+                    if name.is_empty(){
+                        out.push(#field_names.to_string());
+                    }else{
+                        out.push(format!("{}.{}",#field_names,name));
+                    }
+                }
+            })*
+            return out;
+        }
         fn get_data(&self)->(
             Vec<(NodeElementHash, Box<dyn InsertableDyn>)>,
             Vec<(NodeElementHash, Box<dyn VariableSizeInsert>)>,
@@ -156,7 +185,7 @@ pub fn node(input: TokenStream) -> TokenStream {
     }
     .into();
 
-    return tree;
+    tree
 }
 // Hashes identifier, panics if input is not identifier
 #[proc_macro]
@@ -6,6 +6,7 @@ struct Bar {}
 #[derive(GraphInsertable)]
 struct Foo {}
 #[derive(GraphInsertable)]
+#[allow(dead_code)]
 struct Person {
     age: f32,
 }
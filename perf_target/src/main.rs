@@ -1,10 +1,10 @@
 use table::DatabaseTable;
 use traits::InMemoryExtent;
 fn main() {
-    let mut t = DatabaseTable::new(InMemoryExtent::new(), 4);
+    let mut t = DatabaseTable::new(InMemoryExtent::new(), 4).ok().unwrap();
     let mut v = vec![];
     for i in 0..1_000_000 {
-        v.push((t.insert(i as u32), i));
+        v.push((t.insert(i).ok().unwrap(), i));
     }
     for (key, value) in v.iter() {
         assert_eq!(
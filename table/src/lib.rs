@@ -1,5 +1,16 @@
 use anyhow::Result;
-use traits::{Extent, Insertable, InsertableDyn};
+use traits::{Extent, FormatError, FormatHeader, Insertable, InsertableDyn};
+///DatabaseTable Layout
+///
+///|Size (bytes) | Description|
+///|-------------|------------|
+///|16|`FormatHeader`|
+///|8|Element size|
+///|1|1 if slot is used 0 if free|
+///|Element size|Slot data|
+///
+///Used flag and slot data repeat for every slot, the bitmap is rebuilt from the flags by `load`.
+///Version 0 tables have no format header and are rewritten by `upgrade`
 pub struct DatabaseTable<Store: Extent> {
     bitmap: Bitmap,
     data: Store,
@@ -22,54 +33,180 @@ pub enum TableError {
     InvalidKey,
     InvalidLock,
     KeyNotUsed,
+    /// The extent does not hold a table
+    InvalidExtent,
+    /// The table is stored in a format that can not be read
+    Format(FormatError),
+    /// The extent failed `Extent::verify`
+    Damaged(anyhow::Error),
 }
 impl<Store: Extent> DatabaseTable<Store> {
-    pub fn new(data: Store, element_size: usize) -> Self {
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WTBL";
+    const HEADER_SIZE: usize = FormatHeader::SIZE + 8;
+    /// Creates an empty table
+    pub fn new(mut data: Store, element_size: usize) -> Result<Self> {
+        data.resize(Self::HEADER_SIZE)?;
+        data.write_at(0, &Self::format_header().to_binary());
+        data.write_at(FormatHeader::SIZE, &element_size.to_le_bytes());
         let bitmap = Bitmap::new(0);
-        Self {
+        Ok(Self {
+            bitmap,
+            data,
+            element_size,
+        })
+    }
+    /// Loads a table created by `new` from its extent. Fails with `TableError::Format` if the
+    /// table is stored in another version
+    pub fn load(data: Store) -> Result<Self, TableError> {
+        Self::check_format(&data)?;
+        let element_size = usize::from_binary(
+            data.as_slice(FormatHeader::SIZE..Self::HEADER_SIZE)
+                .into_owned(),
+        );
+        if !Self::is_whole(data.len(), Self::HEADER_SIZE, element_size) {
+            return Err(TableError::InvalidExtent);
+        }
+        let len = (data.len() - Self::HEADER_SIZE) / (element_size + 1);
+        let mut bitmap = Bitmap::new(len);
+        let slots = Self::HEADER_SIZE..data.len();
+        for (index, slot) in data.as_slice(slots).chunks(element_size + 1).enumerate() {
+            bitmap.set(index, slot[0] == 1);
+        }
+        Ok(Self {
             bitmap,
             data,
             element_size,
+        })
+    }
+    /// Rewrites a table stored in an older version in the current one. Returns whether the
+    /// table was rewritten
+    pub fn upgrade(data: &mut Store) -> Result<bool> {
+        match Self::check_format(data) {
+            Ok(()) => Ok(false),
+            Err(TableError::Format(FormatError::Outdated { found: 0, .. })) => {
+                //version 0 is the current layout without the format header
+                let old = data.as_slice(0..data.len()).into_owned();
+                data.resize(old.len() + FormatHeader::SIZE)?;
+                data.write_at(0, &Self::format_header().to_binary());
+                data.write_at(FormatHeader::SIZE, &old);
+                data.flush(None)?;
+                Ok(true)
+            }
+            Err(TableError::Format(error)) => Err(error.into()),
+            Err(TableError::Damaged(error)) => Err(error),
+            Err(_) => Err(anyhow::anyhow!("extent does not hold a table")),
+        }
+    }
+    fn format_header() -> FormatHeader {
+        FormatHeader::new(Self::MAGIC, Self::VERSION, 0)
+    }
+    /// Checks data for damage and that it holds a table stored in the current version
+    fn check_format(data: &Store) -> Result<(), TableError> {
+        data.verify().map_err(TableError::Damaged)?;
+        let start = data.as_slice(0..std::cmp::min(data.len(), Self::HEADER_SIZE));
+        match FormatHeader::parse(&start, Self::MAGIC) {
+            Some(header) => header
+                .check("table", Self::VERSION, 0)
+                .map_err(TableError::Format),
+            //version 0 starts with the element size
+            None if start.len() >= 8
+                && Self::is_whole(data.len(), 8, usize::from_binary(start[..8].to_vec())) =>
+            {
+                Err(TableError::Format(FormatError::Outdated {
+                    structure: "table",
+                    found: 0,
+                    supported: Self::VERSION,
+                }))
+            }
+            None => Err(TableError::InvalidExtent),
+        }
+    }
+    /// Whether len bytes are a header of header_size followed by whole slots
+    fn is_whole(len: usize, header_size: usize, element_size: usize) -> bool {
+        match element_size.checked_add(1) {
+            Some(slot) => len >= header_size && (len - header_size).is_multiple_of(slot),
+            None => false,
         }
     }
+    pub fn element_size(&self) -> usize {
+        self.element_size
+    }
+    /// Writes the table back to storage, see `Extent::flush`
+    pub fn flush(&mut self) -> Result<()> {
+        self.data.flush(None)
+    }
+    /// Checks the storage of the table for damage, see `Extent::verify`
+    pub fn verify(&self) -> Result<()> {
+        self.data.verify()
+    }
     pub fn get<Data: InsertableDyn>(
         &self,
         key: Key,
         ctor: fn(Vec<u8>) -> Data,
     ) -> Result<Data, TableError> {
-        if key.index > self.data.len() * self.element_size {
+        if key.index >= self.bitmap.len() {
             return Err(TableError::InvalidKey);
         }
-        if self.bitmap.get(key.index) == false {
+        if !self.bitmap.get(key.index) {
             return Err(TableError::InvalidKey);
         }
-        let data = (0..self.element_size)
-            .map(|i| self.data[key.index * self.element_size + i])
-            .collect();
-        Ok(ctor(data))
+        let start = self.slot_start(key.index) + 1;
+        let data = self.data.as_slice(start..start + self.element_size);
+        Ok(ctor(data.into_owned()))
     }
     pub fn insert<Data: InsertableDyn>(&mut self, data: Data) -> Result<Key> {
-        if let Some(index) = self.bitmap.get_first_free() {
-            self.bitmap.set(index, true);
-            let bytes = data.to_binary();
-            for i in 0..self.element_size {
-                self.data[index * self.element_size + i] = bytes[i];
+        let index = match self.bitmap.get_first_free() {
+            Some(index) => index,
+            None => {
+                self.data.resize(self.data.len() + self.element_size + 1)?;
+                self.bitmap.resize(self.bitmap.len() + 1);
+                self.bitmap.len() - 1
             }
-            return Ok(Key { index });
-        }
-        let bytes = data.to_binary();
-
-        self.data.resize(self.data.len() + self.element_size)?;
-        self.bitmap.resize(self.bitmap.len() + 1);
-
-        let len = self.bitmap.len();
-        for i in 0..self.element_size {
-            self.data[(len - 1) * self.element_size + i] = bytes[i];
-        }
-        let index = len - 1;
+        };
         self.bitmap.set(index, true);
+        let start = self.slot_start(index);
+        let mut slot = vec![1];
+        slot.extend_from_slice(&data.to_binary()[..self.element_size]);
+        self.data.write_at(start, &slot);
         Ok(Key { index })
     }
+    /// Gets the index of the used flag of a slot in the extent
+    fn slot_start(&self, index: usize) -> usize {
+        Self::HEADER_SIZE + index * (self.element_size + 1)
+    }
+    /// Overwrites the data stored at an occupied key
+    pub fn write<Data: InsertableDyn>(&mut self, key: Key, data: Data) -> Result<(), TableError> {
+        if key.index >= self.bitmap.len() {
+            return Err(TableError::InvalidKey);
+        }
+        if !self.bitmap.get(key.index) {
+            return Err(TableError::KeyNotUsed);
+        }
+        let start = self.slot_start(key.index) + 1;
+        self.data
+            .write_at(start, &data.to_binary()[..self.element_size]);
+        Ok(())
+    }
+    /// Iterates over the keys of every occupied slot
+    pub fn keys(&self) -> impl Iterator<Item = Key> + '_ {
+        (0..self.bitmap.len())
+            .filter(move |index| self.bitmap.get(*index))
+            .map(|index| Key { index })
+    }
+    /// Frees the slot used by key so that it can be reused by a later insert
+    pub fn remove(&mut self, key: Key) -> Result<(), TableError> {
+        if key.index >= self.bitmap.len() {
+            return Err(TableError::InvalidKey);
+        }
+        if !self.bitmap.get(key.index) {
+            return Err(TableError::KeyNotUsed);
+        }
+        self.bitmap.set(key.index, false);
+        let start = self.slot_start(key.index);
+        self.data.write_at(start, &[0]);
+        Ok(())
+    }
 }
 struct Bitmap {
     data: Vec<u64>,
@@ -84,7 +221,7 @@ impl Bitmap {
             alloc_size += 1;
         }
         Bitmap {
-            data: vec![0; alloc_size as usize],
+            data: vec![0; alloc_size],
             len,
         }
     }
@@ -92,17 +229,12 @@ impl Bitmap {
         if index >= self.len {
             panic!("out of bounds")
         }
-        let byte = self.data[index / Self::INT_SIZE as usize];
+        let byte = self.data[index / Self::INT_SIZE];
         let bit = (byte >> (index % Self::INT_SIZE)) & 0x1;
-        if bit == 0 {
-            return false;
-        } else {
-            return true;
-        }
+        bit != 0
     }
     pub fn get_first_free(&self) -> Option<usize> {
-        let mut index = 0;
-        for i in self.data.iter() {
+        for (index, i) in self.data.iter().enumerate() {
             if i != &u64::MAX {
                 for j in 0..Self::INT_SIZE {
                     if !i & (1 << j as u64) == (1 << j as u64) {
@@ -115,19 +247,16 @@ impl Bitmap {
                     }
                 }
             }
-            index += 1;
         }
-        return None;
+        None
     }
     pub fn set(&mut self, index: usize, state: bool) {
-        if state == true {
-            let set = 1 << index % Self::INT_SIZE;
-            self.data[index as usize / Self::INT_SIZE as usize] =
-                self.data[index as usize / Self::INT_SIZE as usize] | set;
+        if state {
+            let set = 1 << (index % Self::INT_SIZE);
+            self.data[index / Self::INT_SIZE] |= set;
         } else {
-            let set = (1 << index % Self::INT_SIZE) ^ u64::MAX;
-            self.data[index as usize / Self::INT_SIZE as usize] =
-                self.data[index as usize / Self::INT_SIZE as usize] & set;
+            let set = (1 << (index % Self::INT_SIZE)) ^ u64::MAX;
+            self.data[index / Self::INT_SIZE] &= set;
         }
     }
     pub fn len(&self) -> usize {
@@ -160,14 +289,14 @@ pub fn from_binary(data: Vec<u8>) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use traits::InMemoryExtent;
+    use traits::{DrianableExtent, InMemoryExtent};
     #[test]
     fn test_bitmap() {
         let mut b = Bitmap::new(64);
         assert_eq!(b.get_first_free().unwrap(), 0);
         b.set(0, true);
         assert_eq!(b.get_first_free().unwrap(), 1);
-        assert_eq!(b.get(5), false);
+        assert!(!b.get(5));
     }
     #[test]
     fn get_first_free() {
@@ -180,25 +309,127 @@ mod tests {
 
     #[test]
     fn make_db() {
-        let _ = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>());
+        let _ = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
     }
     #[test]
     fn insert_and_get_single() {
-        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>());
+        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
         let k1 = db.insert::<u32>(1).ok().unwrap();
         assert_eq!(db.get::<u32>(k1, from_binary).ok().unwrap(), 1);
     }
     #[test]
     fn insert_and_get() {
-        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>());
+        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
         let k1 = db.insert::<u32>(1).ok().unwrap();
         let k2 = db.insert::<u32>(2).ok().unwrap();
         assert_eq!(db.get::<u32>(k1, from_binary).ok().unwrap(), 1);
         assert_eq!(db.get::<u32>(k2, from_binary).ok().unwrap(), 2);
     }
     #[test]
+    fn remove_and_reuse() {
+        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
+        let k1 = db.insert::<u32>(1).ok().unwrap();
+        let k2 = db.insert::<u32>(2).ok().unwrap();
+        assert!(db.remove(k1.clone()).is_ok());
+        assert!(db.get::<u32>(k1.clone(), from_binary).is_err());
+        assert!(db.remove(k1.clone()).is_err());
+        let k3 = db.insert::<u32>(3).ok().unwrap();
+        assert_eq!(k3, k1);
+        assert_eq!(db.get::<u32>(k3, from_binary).ok().unwrap(), 3);
+        assert_eq!(db.get::<u32>(k2, from_binary).ok().unwrap(), 2);
+    }
+    #[test]
+    fn write() {
+        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
+        let k1 = db.insert::<u32>(1).ok().unwrap();
+        let k2 = db.insert::<u32>(2).ok().unwrap();
+        assert!(db.write::<u32>(k1.clone(), 3).is_ok());
+        assert_eq!(db.get::<u32>(k1, from_binary).ok().unwrap(), 3);
+        assert_eq!(db.get::<u32>(k2, from_binary).ok().unwrap(), 2);
+        assert!(db.write::<u32>(Key { index: 5 }, 3).is_err());
+    }
+    #[test]
+    fn keys() {
+        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
+        let keys: Vec<Key> = (0..5).map(|i| db.insert::<u32>(i).ok().unwrap()).collect();
+        db.remove(keys[1].clone()).ok().unwrap();
+        db.remove(keys[3].clone()).ok().unwrap();
+        assert_eq!(
+            db.keys().collect::<Vec<_>>(),
+            vec![keys[0].clone(), keys[2].clone(), keys[4].clone()]
+        );
+    }
+    #[test]
+    fn load() {
+        let mut extent = InMemoryExtent::new();
+        let (k1, k2) = {
+            let mut db = DatabaseTable::new(
+                DrianableExtent::new(&mut extent),
+                std::mem::size_of::<u32>(),
+            )
+            .ok()
+            .unwrap();
+            let k1 = db.insert::<u32>(1).ok().unwrap();
+            let k2 = db.insert::<u32>(2).ok().unwrap();
+            let k3 = db.insert::<u32>(3).ok().unwrap();
+            db.remove(k3).ok().unwrap();
+            (k1, k2)
+        };
+        let mut db = DatabaseTable::load(extent).ok().unwrap();
+        assert_eq!(db.element_size(), 4);
+        assert_eq!(db.keys().collect::<Vec<_>>(), vec![k1.clone(), k2.clone()]);
+        assert_eq!(db.get::<u32>(k1, from_binary).ok().unwrap(), 1);
+        assert_eq!(db.get::<u32>(k2, from_binary).ok().unwrap(), 2);
+        assert_eq!(db.insert::<u32>(4).ok().unwrap(), Key { index: 2 });
+        assert!(DatabaseTable::load(InMemoryExtent::new()).is_err());
+    }
+    #[test]
+    fn upgrade() {
+        //version 0 table holding 7 then a free slot
+        let mut extent = InMemoryExtent::new();
+        let mut old = 4usize.to_le_bytes().to_vec();
+        old.extend_from_slice(&[1, 7, 0, 0, 0, 0, 0, 0, 0, 0]);
+        extent.resize(old.len()).ok().unwrap();
+        extent.write_at(0, &old);
+        assert!(matches!(
+            DatabaseTable::load(DrianableExtent::new(&mut extent)),
+            Err(TableError::Format(FormatError::Outdated { found: 0, .. }))
+        ));
+        assert!(DatabaseTable::upgrade(&mut extent).ok().unwrap());
+        assert!(!DatabaseTable::upgrade(&mut extent).ok().unwrap());
+        let db = DatabaseTable::load(extent).ok().unwrap();
+        assert_eq!(db.element_size(), 4);
+        assert_eq!(db.keys().collect::<Vec<_>>(), vec![Key { index: 0 }]);
+        assert_eq!(
+            db.get::<u32>(Key { index: 0 }, from_binary).ok().unwrap(),
+            7
+        );
+
+        let mut extent = InMemoryExtent::new();
+        extent.resize(FormatHeader::SIZE + 8).ok().unwrap();
+        extent.write_at(0, &FormatHeader::new(*b"WTBL", 2, 0).to_binary());
+        assert!(matches!(
+            DatabaseTable::load(extent),
+            Err(TableError::Format(FormatError::Newer { found: 2, .. }))
+        ));
+    }
+    #[test]
     fn mass_insert() {
-        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>());
+        let mut db = DatabaseTable::new(InMemoryExtent::new(), std::mem::size_of::<u32>())
+            .ok()
+            .unwrap();
         let mut keys = vec![];
         for i in 0..100 {
             keys.push((db.insert::<u32>(i).ok().unwrap(), i));
@@ -1,3 +1,4 @@
+#![cfg(test)]
 use graph::prelude::*;
 #[macro_use]
 extern crate macro_lib;
@@ -17,30 +18,125 @@ struct Person {
     age: u64,
     name: String,
 }
+#[derive(GraphInsertable, Debug, PartialEq, Clone)]
+struct Purchased {
+    price: u64,
+    note: String,
+}
+#[derive(GraphInsertable, Debug, PartialEq, Clone)]
+struct Road {
+    length: u64,
+}
+#[derive(GraphInsertable, Debug, PartialEq, Clone)]
+struct Reading {
+    celsius: f32,
+}
+/// Node whose fields can only be read once. Inserting it reads them a second time after its
+/// key is handed out, so the insert panics part way through
+struct Fragile {
+    age: u64,
+    reads: std::cell::Cell<usize>,
+}
+impl Fragile {
+    fn element(hash: NodeElementHash) -> NodeElementHash {
+        NodeElementHash {
+            hash: hash.hash ^ Self::SELF_HASH.hash,
+        }
+    }
+}
+impl Node for Fragile {
+    const SELF_HASH: NodeHash = NodeHash {
+        hash: 0x0046_7261_6769_6c65,
+    };
+    const NAME: &'static str = "Fragile";
+    fn get_sized_hashes() -> Vec<NodeElementHash> {
+        u64::get_sized_hashes()
+            .into_iter()
+            .map(Self::element)
+            .collect()
+    }
+    fn get_variable_hashes() -> Vec<NodeElementHash> {
+        vec![]
+    }
+    fn get_sized_names() -> Vec<String> {
+        vec!["age".to_string()]
+    }
+    fn get_variable_names() -> Vec<String> {
+        vec![]
+    }
+    fn get_data(
+        &self,
+    ) -> (
+        Vec<(NodeElementHash, Box<dyn InsertableDyn>)>,
+        Vec<(NodeElementHash, Box<dyn VariableSizeInsert>)>,
+    ) {
+        self.reads.set(self.reads.get() + 1);
+        assert_eq!(self.reads.get(), 1, "fields read twice");
+        let (sized, variable) = self.age.get_data();
+        let sized = sized
+            .into_iter()
+            .map(|(hash, data)| (Self::element(hash), data))
+            .collect();
+        (sized, variable)
+    }
+    fn from_data(
+        sized: Vec<(NodeElementHash, Vec<u8>)>,
+        variable: Vec<(NodeElementHash, Vec<u8>)>,
+    ) -> Self {
+        let sized = sized
+            .into_iter()
+            .map(|(hash, data)| (Self::element(hash), data))
+            .collect();
+        Self {
+            age: u64::from_data(sized, variable),
+            reads: Default::default(),
+        }
+    }
+}
+/// `Person` with a field of a different type, used to check that a changed layout is refused
+mod changed {
+    use graph::prelude::*;
+    #[derive(GraphInsertable, Debug, PartialEq, Clone)]
+    pub struct Person {
+        pub age: f32,
+        pub name: String,
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use graph::{in_memory_db, Database};
+    use graph::{
+        in_memory_db, on_disk_db, on_disk_db_encrypted, on_disk_db_read_only, single_file_db,
+        upgrade, BackedManager, CachedExtent, ChecksumError, ChecksummedExtent, CompressedExtent,
+        CompressedExtentError, ContainerManager, Database, DatabseError, EncryptedExtent,
+        EncryptedExtentError, FileExtent, InMemoryManager, Key, PagedFileExtent, SharedDatabase,
+        SyncPolicy,
+    };
+    use std::convert::TryInto;
+    use std::path::{Path, PathBuf};
     #[test]
     fn it_works() {
         let mut db = in_memory_db();
-        let e = db.insert(Empty {});
+        let e = db.insert(Empty {}).ok().unwrap();
         assert_eq!(db.get::<Empty>(e).unwrap(), Empty {});
     }
     #[test]
     fn sized_only() {
         let mut db = in_memory_db();
         let s = SizedOnly { age: 1 };
-        let k = db.insert(s.clone());
+        let k = db.insert(s.clone()).ok().unwrap();
         assert_eq!(db.get::<SizedOnly>(k).unwrap(), s);
     }
     #[test]
     fn insert_string() {
         let mut db = in_memory_db();
-        let e = db.insert(S {
-            name: "bar".to_string(),
-        });
+        let e = db
+            .insert(S {
+                name: "bar".to_string(),
+            })
+            .ok()
+            .unwrap();
         assert_eq!(
             db.get::<S>(e).unwrap(),
             S {
@@ -55,7 +151,1289 @@ mod tests {
             name: "Bill".to_string(),
             age: 5,
         };
-        let p_key = db.insert(p_obj.clone());
+        let p_key = db.insert(p_obj.clone()).ok().unwrap();
         assert_eq!(db.get::<Person>(p_key).unwrap(), p_obj);
     }
+    #[test]
+    fn remove() {
+        let mut db = in_memory_db();
+        let bill = Person {
+            name: "Bill".to_string(),
+            age: 5,
+        };
+        let bob = Person {
+            name: "Bob".to_string(),
+            age: 6,
+        };
+        let bill_key = db.insert(bill.clone()).ok().unwrap();
+        let bob_key = db.insert(bob.clone()).ok().unwrap();
+        db.connect(bill_key.clone(), bob_key.clone()).ok().unwrap();
+        assert!(db.remove(bill_key.clone()).is_ok());
+        assert_eq!(db.get::<Person>(bill_key.clone()), None);
+        assert!(db.remove(bill_key).is_err());
+        assert_eq!(db.get_connected(bob_key.clone()).len(), 0);
+        assert_eq!(db.get::<Person>(bob_key).unwrap(), bob);
+        let new_key = db.insert(bill.clone()).ok().unwrap();
+        assert_eq!(db.get::<Person>(new_key).unwrap(), bill);
+    }
+    #[test]
+    fn removed_key_links() {
+        let mut db = in_memory_db();
+        let a = db.insert(SizedOnly { age: 1 }).ok().unwrap();
+        let b = db.insert(SizedOnly { age: 2 }).ok().unwrap();
+        db.connect(a.clone(), b.clone()).ok().unwrap();
+        db.remove(a.clone()).ok().unwrap();
+        assert_eq!(db.get_connected(a.clone()).len(), 0);
+        assert_eq!(db.get_outgoing(a.clone()).len(), 0);
+        assert_eq!(db.get_incoming(a.clone()).len(), 0);
+        assert_eq!(db.get_edges::<Road>(a.clone()).len(), 0);
+        assert!(matches!(
+            db.connect(a.clone(), b.clone()),
+            Err(DatabseError::InvalidKey(key)) if key == a
+        ));
+        assert!(matches!(
+            db.connect_directed(b.clone(), a.clone()),
+            Err(DatabseError::InvalidKey(key)) if key == a
+        ));
+        assert!(matches!(
+            db.connect_with(a.clone(), b.clone(), Road { length: 3 }),
+            Err(DatabseError::InvalidKey(key)) if key == a
+        ));
+        assert_eq!(db.get_connected(b).len(), 0);
+    }
+    #[test]
+    fn update() {
+        let mut db = in_memory_db();
+        let bill = Person {
+            name: "Bill".to_string(),
+            age: 5,
+        };
+        let bob = Person {
+            name: "Bob".to_string(),
+            age: 6,
+        };
+        let bill_key = db.insert(bill).ok().unwrap();
+        let bob_key = db.insert(bob.clone()).ok().unwrap();
+        db.connect(bill_key.clone(), bob_key.clone()).ok().unwrap();
+        let new_bill = Person {
+            name: "William the very long named".to_string(),
+            age: 50,
+        };
+        assert!(db.update(bill_key.clone(), new_bill.clone()).is_ok());
+        assert_eq!(db.get::<Person>(bill_key.clone()).unwrap(), new_bill);
+        assert_eq!(db.get::<Person>(bob_key.clone()).unwrap(), bob);
+        assert_eq!(db.get_connected(bill_key.clone()).len(), 1);
+        assert!(db.update(bill_key.clone(), SizedOnly { age: 1 }).is_err());
+        assert_eq!(db.get::<Person>(bill_key).unwrap(), new_bill);
+    }
+    #[test]
+    fn disconnect() {
+        let mut db = in_memory_db();
+        let keys: Vec<_> = (0..4)
+            .map(|age| db.insert(SizedOnly { age }).ok().unwrap())
+            .collect();
+        for key in keys[1..].iter() {
+            db.connect(keys[0].clone(), key.clone()).ok().unwrap();
+        }
+        db.connect(keys[1].clone(), keys[2].clone()).ok().unwrap();
+        assert!(db.disconnect(keys[0].clone(), keys[1].clone()).is_ok());
+        assert_eq!(db.get_connected(keys[0].clone()).len(), 2);
+        assert_eq!(db.get_connected(keys[1].clone()).len(), 1);
+        assert!(db.disconnect_all(keys[2].clone()).is_ok());
+        assert_eq!(db.get_connected(keys[2].clone()).len(), 0);
+        assert_eq!(db.get_connected(keys[1].clone()).len(), 0);
+        let connected = db.get_connected(keys[0].clone());
+        assert_eq!(connected.len(), 1);
+        assert_eq!(db.get::<SizedOnly>(connected[0].clone()).unwrap().age, 3);
+    }
+    #[test]
+    fn directed() {
+        let mut db = in_memory_db();
+        let a = db.insert(SizedOnly { age: 1 }).ok().unwrap();
+        let b = db.insert(SizedOnly { age: 2 }).ok().unwrap();
+        let c = db.insert(SizedOnly { age: 3 }).ok().unwrap();
+        db.connect_directed(a.clone(), b.clone()).ok().unwrap();
+        db.connect_directed(c.clone(), b.clone()).ok().unwrap();
+        assert_eq!(db.get_outgoing(a.clone()).len(), 1);
+        assert_eq!(db.get_incoming(a.clone()).len(), 0);
+        assert_eq!(db.get_outgoing(b.clone()).len(), 0);
+        let incoming: Vec<u64> = db
+            .get_incoming(b.clone())
+            .into_iter()
+            .map(|k| db.get::<SizedOnly>(k).unwrap().age)
+            .collect();
+        assert_eq!(incoming, vec![1, 3]);
+        db.disconnect_directed(c.clone(), b.clone()).ok().unwrap();
+        assert_eq!(db.get_incoming(b.clone()).len(), 1);
+        assert_eq!(db.get_outgoing(c).len(), 0);
+        db.remove(a).ok().unwrap();
+        assert_eq!(db.get_incoming(b).len(), 0);
+    }
+    #[test]
+    fn labelled_edges() {
+        let mut db = in_memory_db();
+        let bill = db
+            .insert(Person {
+                name: "Bill".to_string(),
+                age: 5,
+            })
+            .ok()
+            .unwrap();
+        let item = db
+            .insert(S {
+                name: "hat".to_string(),
+            })
+            .ok()
+            .unwrap();
+        let edge = Purchased {
+            price: 20,
+            note: "on sale".to_string(),
+        };
+        db.connect_with(bill.clone(), item.clone(), edge.clone())
+            .ok()
+            .unwrap();
+        db.connect(bill.clone(), item.clone()).ok().unwrap();
+        assert_eq!(db.get_outgoing(bill.clone()).len(), 2);
+        assert_eq!(db.get_incoming(item.clone()).len(), 2);
+        let edges = db.get_edges::<Purchased>(bill.clone());
+        assert_eq!(edges.len(), 1);
+        assert_eq!(db.get::<S>(edges[0].0.clone()).unwrap().name, "hat");
+        assert_eq!(edges[0].1, edge);
+        assert_eq!(db.get_edges::<SizedOnly>(bill.clone()).len(), 0);
+        assert_eq!(db.get_edges::<Purchased>(item.clone()).len(), 0);
+        db.disconnect_directed(bill.clone(), item.clone())
+            .ok()
+            .unwrap();
+        assert_eq!(db.get_edges::<Purchased>(bill.clone()).len(), 0);
+        assert_eq!(db.get_outgoing(item.clone()).len(), 1);
+        db.connect_with(item.clone(), bill.clone(), edge.clone())
+            .ok()
+            .unwrap();
+        db.remove(bill).ok().unwrap();
+        assert_eq!(db.get_edges::<Purchased>(item.clone()).len(), 0);
+        assert_eq!(db.get_outgoing(item).len(), 0);
+    }
+    #[test]
+    fn iter() {
+        let mut db = in_memory_db();
+        let keys: Vec<Key> = (0..10)
+            .map(|age| {
+                db.insert(Person {
+                    name: format!("person {}", age),
+                    age,
+                })
+                .ok()
+                .unwrap()
+            })
+            .collect();
+        db.insert(SizedOnly { age: 100 }).ok().unwrap();
+        db.connect_with(
+            keys[0].clone(),
+            keys[1].clone(),
+            Person {
+                name: "edge".to_string(),
+                age: 100,
+            },
+        )
+        .ok()
+        .unwrap();
+        db.remove(keys[3].clone()).ok().unwrap();
+        let mut people: Vec<(Key, Person)> = db.iter::<Person>().collect();
+        people.sort_by_key(|(_, p)| p.age);
+        let ages: Vec<u64> = people.iter().map(|(_, p)| p.age).collect();
+        assert_eq!(ages, vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        for (key, person) in people {
+            assert_eq!(db.get::<Person>(key).unwrap(), person);
+        }
+        assert_eq!(db.iter::<SizedOnly>().count(), 1);
+        assert_eq!(db.iter::<Empty>().count(), 0);
+    }
+    fn traversal_graph() -> (Database<InMemoryManager>, Vec<Key>) {
+        let mut db = in_memory_db();
+        let keys: Vec<Key> = (0..5)
+            .map(|age| db.insert(SizedOnly { age }).ok().unwrap())
+            .collect();
+        for (from, to) in [(0, 1), (0, 2), (1, 3), (2, 3), (3, 4)].iter() {
+            db.connect_directed(keys[*from].clone(), keys[*to].clone())
+                .ok()
+                .unwrap();
+        }
+        (db, keys)
+    }
+    #[test]
+    fn bfs() {
+        let (db, keys) = traversal_graph();
+        let order: Vec<(u64, usize)> = db
+            .bfs(keys[0].clone())
+            .map(|(k, depth)| (db.get::<SizedOnly>(k).unwrap().age, depth))
+            .collect();
+        assert_eq!(order, vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 3)]);
+        assert_eq!(db.bfs(keys[0].clone()).max_depth(1).count(), 3);
+        assert_eq!(db.bfs(keys[3].clone()).count(), 2);
+    }
+    #[test]
+    fn dfs() {
+        let (db, keys) = traversal_graph();
+        let order: Vec<(u64, usize)> = db
+            .dfs(keys[0].clone())
+            .map(|(k, depth)| (db.get::<SizedOnly>(k).unwrap().age, depth))
+            .collect();
+        assert_eq!(order, vec![(0, 0), (1, 1), (3, 2), (4, 3), (2, 1)]);
+        assert_eq!(db.dfs(keys[0].clone()).max_depth(2).count(), 4);
+    }
+    #[test]
+    fn traversal_filter() {
+        let (mut db, keys) = traversal_graph();
+        let person = db
+            .insert(Person {
+                name: "Bill".to_string(),
+                age: 5,
+            })
+            .ok()
+            .unwrap();
+        db.connect_directed(keys[4].clone(), person.clone())
+            .ok()
+            .unwrap();
+        let found: Vec<(Key, usize)> = db.bfs(keys[0].clone()).filter::<Person>().collect();
+        assert_eq!(found, vec![(person.clone(), 4)]);
+        let found: Vec<(Key, usize)> = db.dfs(keys[0].clone()).filter::<Person>().collect();
+        assert_eq!(found, vec![(person, 4)]);
+    }
+    #[test]
+    fn shortest_path() {
+        let mut db = in_memory_db();
+        let keys: Vec<Key> = (0..5)
+            .map(|age| db.insert(SizedOnly { age }).ok().unwrap())
+            .collect();
+        for (from, to, length) in [(0, 1, 1), (1, 3, 1), (0, 2, 5), (2, 3, 1), (0, 3, 10)].iter() {
+            db.connect_with(
+                keys[*from].clone(),
+                keys[*to].clone(),
+                Road { length: *length },
+            )
+            .ok()
+            .unwrap();
+        }
+        assert_eq!(
+            db.shortest_path(keys[0].clone(), keys[3].clone()),
+            Some(vec![keys[0].clone(), keys[3].clone()])
+        );
+        assert_eq!(
+            db.shortest_path(keys[0].clone(), keys[0].clone()),
+            Some(vec![keys[0].clone()])
+        );
+        assert_eq!(db.shortest_path(keys[3].clone(), keys[0].clone()), None);
+        assert_eq!(db.shortest_path(keys[0].clone(), keys[4].clone()), None);
+        let (cost, path) = db
+            .weighted_shortest_path(keys[0].clone(), keys[3].clone(), |road: &Road| {
+                road.length as f64
+            })
+            .unwrap();
+        assert_eq!(cost, 2.0);
+        assert_eq!(
+            path,
+            vec![keys[0].clone(), keys[1].clone(), keys[3].clone()]
+        );
+        let (cost, path) = db
+            .a_star(
+                keys[0].clone(),
+                keys[3].clone(),
+                |road: &Road| road.length as f64,
+                |key| if key == &keys[3] { 0.0 } else { 1.0 },
+            )
+            .unwrap();
+        assert_eq!(cost, 2.0);
+        assert_eq!(path.len(), 3);
+        assert!(db
+            .weighted_shortest_path(keys[0].clone(), keys[4].clone(), |road: &Road| {
+                road.length as f64
+            })
+            .is_none());
+    }
+    fn find_by_name(db: &Database<InMemoryManager>, name: &str) -> Vec<Key> {
+        db.find_by::<Person, _>(Person::get_variable_hashes()[0].clone(), name.to_string())
+    }
+    #[test]
+    fn find_by() {
+        let mut db = in_memory_db();
+        let keys: Vec<Key> = (0..20)
+            .map(|age| {
+                db.insert(Person {
+                    name: format!("person {}", age % 5),
+                    age,
+                })
+                .ok()
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(find_by_name(&db, "person 3").len(), 4);
+        assert!(db
+            .create_index::<Person>(Person::get_variable_hashes()[0].clone())
+            .is_ok());
+        assert!(db
+            .create_index::<Person>(SizedOnly::get_sized_hashes()[0].clone())
+            .is_err());
+        assert_eq!(find_by_name(&db, "person 3").len(), 4);
+        assert!(find_by_name(&db, "person 3").contains(&keys[8]));
+        let bill = db
+            .insert(Person {
+                name: "Bill".to_string(),
+                age: 100,
+            })
+            .ok()
+            .unwrap();
+        assert_eq!(find_by_name(&db, "Bill"), vec![bill.clone()]);
+        db.update(
+            bill.clone(),
+            Person {
+                name: "Bob".to_string(),
+                age: 100,
+            },
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(find_by_name(&db, "Bill").len(), 0);
+        assert_eq!(find_by_name(&db, "Bob"), vec![bill.clone()]);
+        db.remove(bill).ok().unwrap();
+        db.remove(keys[8].clone()).ok().unwrap();
+        assert_eq!(find_by_name(&db, "Bob").len(), 0);
+        assert_eq!(find_by_name(&db, "person 3").len(), 3);
+        assert!(db
+            .create_index::<Person>(Person::get_sized_hashes()[0].clone())
+            .is_ok());
+        assert_eq!(
+            db.find_by::<Person, _>(Person::get_sized_hashes()[0].clone(), 13u64),
+            vec![keys[13].clone()]
+        );
+    }
+    fn ages_between(db: &Database<InMemoryManager>, lo: u64, hi: u64) -> Vec<u64> {
+        db.range::<Person, u64>(Person::get_sized_hashes()[0].clone(), lo..hi)
+            .map(|(_, person)| person.age)
+            .collect()
+    }
+    #[test]
+    fn range() {
+        let mut db = in_memory_db();
+        let keys: Vec<Key> = (0..50)
+            .map(|i| {
+                db.insert(Person {
+                    name: format!("person {}", i),
+                    age: (i * 37) % 50,
+                })
+                .ok()
+                .unwrap()
+            })
+            .collect();
+        let expected: Vec<u64> = (20..30).collect();
+        assert_eq!(ages_between(&db, 20, 30), expected);
+        assert!(db
+            .create_ordered_index::<Person, u64>(Person::get_variable_hashes()[0].clone())
+            .is_err());
+        assert!(db
+            .create_ordered_index::<Person, u64>(Person::get_sized_hashes()[0].clone())
+            .is_ok());
+        assert_eq!(ages_between(&db, 20, 30), expected);
+        db.remove(keys[1].clone()).ok().unwrap();
+        db.update(
+            keys[2].clone(),
+            Person {
+                name: "Bob".to_string(),
+                age: 21,
+            },
+        )
+        .ok()
+        .unwrap();
+        db.insert(Person {
+            name: "Bill".to_string(),
+            age: 25,
+        })
+        .ok()
+        .unwrap();
+        assert_eq!(
+            ages_between(&db, 20, 30),
+            vec![20, 21, 21, 22, 23, 25, 25, 26, 27, 28, 29]
+        );
+        let all: Vec<u64> = db
+            .range::<Person, u64>(Person::get_sized_hashes()[0].clone(), ..)
+            .map(|(_, person)| person.age)
+            .collect();
+        assert_eq!(all.len(), 50);
+        assert!(all.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+    #[test]
+    fn range_float() {
+        let mut db = in_memory_db();
+        for celsius in [3.5f32, -10.0, 0.0, 22.25, -0.5, 100.0].iter() {
+            db.insert(Reading { celsius: *celsius }).ok().unwrap();
+        }
+        let field = Reading::get_sized_hashes()[0].clone();
+        let scanned: Vec<f32> = db
+            .range::<Reading, f32>(field.clone(), -1.0..=22.25)
+            .map(|(_, reading)| reading.celsius)
+            .collect();
+        assert_eq!(scanned, vec![-0.5, 0.0, 3.5, 22.25]);
+        db.create_ordered_index::<Reading, f32>(field.clone())
+            .ok()
+            .unwrap();
+        let indexed: Vec<f32> = db
+            .range::<Reading, f32>(field, -1.0..=22.25)
+            .map(|(_, reading)| reading.celsius)
+            .collect();
+        assert_eq!(indexed, scanned);
+    }
+    /// Gets an empty directory for a test database
+    fn test_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("graph_test_{}_{}", name, std::process::id()));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        path
+    }
+    #[test]
+    fn reopen() {
+        let path = test_dir("reopen");
+        let (bill, hat) = {
+            let mut db = on_disk_db(&path).ok().unwrap();
+            let bill = db
+                .insert(Person {
+                    name: "Bill".to_string(),
+                    age: 25,
+                })
+                .ok()
+                .unwrap();
+            let hat = db
+                .insert(S {
+                    name: "hat".to_string(),
+                })
+                .ok()
+                .unwrap();
+            db.connect_with(
+                bill.clone(),
+                hat.clone(),
+                Purchased {
+                    price: 20,
+                    note: "on sale".to_string(),
+                },
+            )
+            .ok()
+            .unwrap();
+            db.create_index::<Person>(Person::get_variable_hashes()[0].clone())
+                .ok()
+                .unwrap();
+            db.create_ordered_index::<Person, u64>(Person::get_sized_hashes()[0].clone())
+                .ok()
+                .unwrap();
+            (bill, hat)
+        };
+        {
+            let mut db = on_disk_db(&path).ok().unwrap();
+            assert_eq!(
+                db.get::<Person>(bill.clone()).unwrap(),
+                Person {
+                    name: "Bill".to_string(),
+                    age: 25,
+                }
+            );
+            assert_eq!(db.get::<S>(hat.clone()).unwrap().name, "hat");
+            let edges = db.get_edges::<Purchased>(bill.clone());
+            assert_eq!(edges.len(), 1);
+            assert_eq!(edges[0].0, hat);
+            assert_eq!(edges[0].1.note, "on sale");
+            let bob = db
+                .insert(Person {
+                    name: "Bob".to_string(),
+                    age: 20,
+                })
+                .ok()
+                .unwrap();
+            assert_eq!(
+                db.find_by::<Person, _>(
+                    Person::get_variable_hashes()[0].clone(),
+                    "Bob".to_string()
+                ),
+                vec![bob.clone()]
+            );
+            let ordered: Vec<Key> = db
+                .range::<Person, u64>(Person::get_sized_hashes()[0].clone(), ..)
+                .map(|(key, _)| key)
+                .collect();
+            assert_eq!(ordered, vec![bob, bill.clone()]);
+            db.remove(hat).ok().unwrap();
+        }
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 2);
+        assert_eq!(db.iter::<S>().count(), 0);
+        assert_eq!(db.get_outgoing(bill).len(), 0);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn schema_mismatch() {
+        let path = test_dir("schema_mismatch");
+        let bill = {
+            let mut db = on_disk_db(&path).ok().unwrap();
+            let bill = db
+                .insert(Person {
+                    name: "Bill".to_string(),
+                    age: 25,
+                })
+                .ok()
+                .unwrap();
+            assert!(db.register::<changed::Person>().is_err());
+            bill
+        };
+        let mut db = on_disk_db(&path).ok().unwrap();
+        assert!(db.register::<Person>().is_ok());
+        assert!(db.register::<changed::Person>().is_err());
+        assert!(db.register::<Road>().is_ok());
+        assert!(db
+            .insert(changed::Person {
+                name: "Bill".to_string(),
+                age: 25.0,
+            })
+            .is_err());
+        assert!(db.get::<changed::Person>(bill.clone()).is_none());
+        assert_eq!(db.iter::<changed::Person>().count(), 0);
+        assert!(db.get::<Person>(bill).is_some());
+        assert_eq!(db.iter::<Person>().count(), 1);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn transaction() {
+        let mut db = in_memory_db();
+        let shop = db
+            .insert(S {
+                name: "shop".to_string(),
+            })
+            .ok()
+            .unwrap();
+        let bill = db
+            .transaction(|tx| -> Result<Key, ()> {
+                let bill = tx
+                    .insert(Person {
+                        name: "Bill".to_string(),
+                        age: 5,
+                    })
+                    .ok()
+                    .unwrap();
+                assert_eq!(tx.get::<Person>(bill.clone()).unwrap().age, 5);
+                tx.connect(bill.clone(), shop.clone()).ok().unwrap();
+                Ok(bill)
+            })
+            .ok()
+            .unwrap();
+        assert_eq!(db.get_connected(shop.clone()), vec![bill.clone()]);
+        let failed: Result<(), &str> = db.transaction(|tx| {
+            let bob = tx
+                .insert(Person {
+                    name: "Bob".to_string(),
+                    age: 6,
+                })
+                .ok()
+                .unwrap();
+            tx.connect(bob.clone(), shop.clone()).ok().unwrap();
+            tx.connect(bob, bill.clone()).ok().unwrap();
+            Err("import failed")
+        });
+        assert_eq!(failed, Err("import failed"));
+        assert_eq!(db.iter::<Person>().count(), 1);
+        assert_eq!(db.get_connected(shop.clone()), vec![bill.clone()]);
+        assert_eq!(db.get_connected(bill), vec![shop]);
+    }
+    #[test]
+    fn transaction_panic() {
+        let path = test_dir("transaction_panic");
+        let mut db = on_disk_db(&path).ok().unwrap();
+        let shop = db
+            .insert(S {
+                name: "shop".to_string(),
+            })
+            .ok()
+            .unwrap();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.transaction(|tx| -> Result<(), ()> {
+                let bob = tx
+                    .insert(Person {
+                        name: "Bob".to_string(),
+                        age: 6,
+                    })
+                    .ok()
+                    .unwrap();
+                tx.connect(bob, shop.clone()).ok().unwrap();
+                panic!("importer crashed");
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(db.iter::<Person>().count(), 0);
+        assert_eq!(db.get_connected(shop.clone()).len(), 0);
+        drop(db);
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 0);
+        assert_eq!(db.get::<S>(shop).unwrap().name, "shop");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn transaction_panic_in_insert() {
+        let path = test_dir("transaction_panic_in_insert");
+        let mut db = on_disk_db(&path).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::EveryOperation);
+        let shop = db
+            .insert(S {
+                name: "shop".to_string(),
+            })
+            .ok()
+            .unwrap();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.transaction(|tx| -> Result<(), ()> {
+                let bob = tx
+                    .insert(Person {
+                        name: "Bob".to_string(),
+                        age: 6,
+                    })
+                    .ok()
+                    .unwrap();
+                tx.connect(bob, shop.clone()).ok().unwrap();
+                tx.insert(Fragile {
+                    age: 7,
+                    reads: Default::default(),
+                })
+                .ok()
+                .unwrap();
+                Ok(())
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(db.iter::<Person>().count(), 0);
+        assert_eq!(db.iter::<Fragile>().count(), 0);
+        assert_eq!(db.get_connected(shop.clone()).len(), 0);
+        //the database is usable and flushes again
+        let bill = db
+            .insert(Person {
+                name: "Bill".to_string(),
+                age: 5,
+            })
+            .ok()
+            .unwrap();
+        drop(db);
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(db.iter::<Fragile>().count(), 0);
+        assert_eq!(db.get::<Person>(bill).unwrap().name, "Bill");
+        assert_eq!(db.get::<S>(shop).unwrap().name, "shop");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn snapshot() {
+        let db = SharedDatabase::new(in_memory_db());
+        let bill = db
+            .insert(Person {
+                name: "Bill".to_string(),
+                age: 5,
+            })
+            .ok()
+            .unwrap();
+        let before = db.snapshot();
+        db.update(
+            bill.clone(),
+            Person {
+                name: "Bill".to_string(),
+                age: 6,
+            },
+        )
+        .ok()
+        .unwrap();
+        let bob = db
+            .insert(Person {
+                name: "Bob".to_string(),
+                age: 7,
+            })
+            .ok()
+            .unwrap();
+        db.connect(bill.clone(), bob.clone()).ok().unwrap();
+        let after = db.snapshot();
+        db.remove(bill.clone()).ok().unwrap();
+        assert_eq!(before.get::<Person>(bill.clone()).unwrap().age, 5);
+        assert_eq!(before.get::<Person>(bob.clone()), None);
+        assert_eq!(before.iter::<Person>().len(), 1);
+        assert_eq!(before.get_outgoing(bill.clone()).len(), 0);
+        assert_eq!(after.get::<Person>(bill.clone()).unwrap().age, 6);
+        assert_eq!(after.get_outgoing(bill.clone()), vec![bob.clone()]);
+        assert_eq!(after.get_incoming(bob.clone()), vec![bill.clone()]);
+        let ages: Vec<u64> = after
+            .iter::<Person>()
+            .into_iter()
+            .map(|(_, p)| p.age)
+            .collect();
+        assert_eq!(ages, vec![6, 7]);
+        assert_eq!(db.get::<Person>(bill.clone()), None);
+        assert_eq!(db.snapshot().get_incoming(bob.clone()).len(), 0);
+        drop(before);
+        drop(after);
+        assert_eq!(db.snapshot().iter::<Person>().len(), 1);
+    }
+    #[test]
+    fn snapshot_threads() {
+        let path = test_dir("snapshot_threads");
+        let db = SharedDatabase::new(on_disk_db(&path).ok().unwrap());
+        let first = db.insert(SizedOnly { age: 0 }).ok().unwrap();
+        let writer = {
+            let db = db.clone();
+            let first = first.clone();
+            std::thread::spawn(move || {
+                for age in 1..50 {
+                    let key = db.insert(SizedOnly { age }).ok().unwrap();
+                    db.connect(first.clone(), key).ok().unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                let first = first.clone();
+                std::thread::spawn(move || {
+                    let snapshot = db.snapshot();
+                    let linked_ages = || {
+                        let mut ages: Vec<u64> = snapshot
+                            .get_outgoing(first.clone())
+                            .into_iter()
+                            .map(|key| snapshot.get::<SizedOnly>(key).unwrap().age)
+                            .collect();
+                        ages.sort_unstable();
+                        ages
+                    };
+                    let count = snapshot.iter::<SizedOnly>().len();
+                    let ages = linked_ages();
+                    //the newest node may not be linked yet
+                    assert!(ages.len() + 2 >= count);
+                    assert_eq!(ages, (1..=ages.len() as u64).collect::<Vec<_>>());
+                    for _ in 0..20 {
+                        assert_eq!(snapshot.iter::<SizedOnly>().len(), count);
+                        assert_eq!(linked_ages(), ages);
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(db.snapshot().iter::<SizedOnly>().len(), 50);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    fn ages(db: &Database<BackedManager>) -> Vec<u64> {
+        let mut ages: Vec<u64> = db.iter::<SizedOnly>().map(|(_, node)| node.age).collect();
+        ages.sort_unstable();
+        ages
+    }
+    /// Copies the files of an open database as they would be found if the process stopped
+    fn crash_copy(from: &Path, name: &str) -> PathBuf {
+        fn copy(from: &Path, to: &Path) {
+            std::fs::create_dir(to).unwrap();
+            for entry in from.read_dir().unwrap() {
+                let path = entry.unwrap().path();
+                let target = to.join(path.file_name().unwrap());
+                if path.is_dir() {
+                    copy(&path, &target);
+                } else {
+                    std::fs::copy(&path, &target).unwrap();
+                }
+            }
+        }
+        let to = test_dir(name);
+        copy(from, &to);
+        to
+    }
+    #[test]
+    fn sync_policy() {
+        let path = test_dir("sync_policy");
+        let mut db = on_disk_db(&path).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::Manual);
+        db.insert(SizedOnly { age: 1 }).ok().unwrap();
+        db.insert(SizedOnly { age: 2 }).ok().unwrap();
+        db.flush().ok().unwrap();
+        db.insert(SizedOnly { age: 3 }).ok().unwrap();
+        let crashed = crash_copy(&path, "sync_policy_manual");
+        assert_eq!(ages(&on_disk_db(&crashed).ok().unwrap()), vec![1, 2]);
+        std::fs::remove_dir_all(&crashed).unwrap();
+
+        db.set_sync_policy(SyncPolicy::EveryN(2));
+        db.insert(SizedOnly { age: 4 }).ok().unwrap();
+        db.insert(SizedOnly { age: 5 }).ok().unwrap();
+        let crashed = crash_copy(&path, "sync_policy_every_n");
+        assert_eq!(ages(&on_disk_db(&crashed).ok().unwrap()), vec![1, 2, 3, 4]);
+        std::fs::remove_dir_all(&crashed).unwrap();
+
+        //dropping the database flushes it
+        drop(db);
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(ages(&db), vec![1, 2, 3, 4, 5]);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn locking() {
+        let path = test_dir("locking");
+        let mut db = on_disk_db(&path).ok().unwrap();
+        db.insert(SizedOnly { age: 3 }).ok().unwrap();
+        let error = on_disk_db(&path).err().unwrap();
+        assert!(error.to_string().contains("already open"));
+        assert!(on_disk_db_read_only(&path).is_err());
+        drop(db);
+
+        let first = on_disk_db_read_only(&path).ok().unwrap();
+        let second = on_disk_db_read_only(&path).ok().unwrap();
+        assert_eq!(ages(&first), vec![3]);
+        assert_eq!(ages(&second), vec![3]);
+        assert!(on_disk_db(&path).is_err());
+        drop(first);
+        drop(second);
+
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(ages(&db), vec![3]);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn read_only_insert() {
+        let path = test_dir("read_only_insert");
+        let key = {
+            let mut db = on_disk_db(&path).ok().unwrap();
+            db.insert(SizedOnly { age: 2 }).ok().unwrap()
+        };
+        let mut db = on_disk_db_read_only(&path).ok().unwrap();
+        assert!(matches!(
+            db.insert(SizedOnly { age: 3 }),
+            Err(DatabseError::ReadOnly)
+        ));
+        assert!(matches!(
+            db.update(key.clone(), SizedOnly { age: 4 }),
+            Err(DatabseError::ReadOnly)
+        ));
+        assert!(matches!(
+            db.connect(key.clone(), key.clone()),
+            Err(DatabseError::ReadOnly)
+        ));
+        assert!(matches!(
+            db.remove(key.clone()),
+            Err(DatabseError::ReadOnly)
+        ));
+        assert_eq!(db.get::<SizedOnly>(key), Some(SizedOnly { age: 2 }));
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn paged_extent() {
+        let path = test_dir("paged_extent");
+        let open = |path: &Path| Database::new(BackedManager::<PagedFileExtent>::new(path)?);
+        let mut db = open(&path).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::EveryOperation);
+        let keys: Vec<Key> = (0..200)
+            .map(|i| {
+                db.insert(Person {
+                    name: format!("person {}", i),
+                    age: i,
+                })
+                .ok()
+                .unwrap()
+            })
+            .collect();
+        db.connect(keys[0].clone(), keys[1].clone()).ok().unwrap();
+        db.remove(keys[2].clone()).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::Manual);
+        db.insert(SizedOnly { age: 1 }).ok().unwrap();
+        let crashed = crash_copy(&path, "paged_extent_crash");
+        drop(db);
+        let db = open(&crashed).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 199);
+        assert_eq!(db.iter::<SizedOnly>().count(), 0);
+        drop(db);
+        std::fs::remove_dir_all(&crashed).unwrap();
+
+        let db = open(&path).ok().unwrap();
+        assert_eq!(db.iter::<SizedOnly>().count(), 1);
+        drop(db);
+        //the files are the same whichever extent wrote them
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 199);
+        let first = db
+            .iter::<Person>()
+            .find(|(_, person)| person.age == 0)
+            .unwrap()
+            .0;
+        assert_eq!(db.get_connected(first), vec![keys[1].clone()]);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn cached_extent() {
+        let path = test_dir("cached_extent");
+        let open =
+            |path: &Path| Database::new(BackedManager::<CachedExtent<FileExtent>>::new(path)?);
+        let mut db = open(&path).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::EveryOperation);
+        //the first insert also registers the type, which is an operation of its own
+        db.insert(Person {
+            name: "first".to_string(),
+            age: 0,
+        })
+        .ok()
+        .unwrap();
+        db.set_sync_policy(SyncPolicy::EveryN(50));
+        for i in 0..120 {
+            db.insert(Person {
+                name: format!("person {}", i),
+                age: i,
+            })
+            .ok()
+            .unwrap();
+        }
+        let crashed = crash_copy(&path, "cached_extent_crash");
+        drop(db);
+        let db = open(&crashed).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 101);
+        drop(db);
+        std::fs::remove_dir_all(&crashed).unwrap();
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 121);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn checksums() {
+        let path = test_dir("checksums");
+        let open =
+            |path: &Path| Database::new(BackedManager::<ChecksummedExtent<FileExtent>>::new(path)?);
+        let mut db = open(&path).ok().unwrap();
+        for i in 0..100 {
+            db.insert(Person {
+                name: format!("person {}", i),
+                age: i,
+            })
+            .ok()
+            .unwrap();
+        }
+        drop(db);
+        let mut db = open(&path).ok().unwrap();
+        assert!(db.verify().is_ok());
+        assert_eq!(db.iter::<Person>().count(), 100);
+        //rolling back unflushed changes leaves valid checksums
+        db.set_sync_policy(SyncPolicy::Manual);
+        db.insert(Person {
+            name: "unflushed".to_string(),
+            age: 100,
+        })
+        .ok()
+        .unwrap();
+        let crashed = crash_copy(&path, "checksums_crash");
+        drop(db);
+        let db = open(&crashed).ok().unwrap();
+        assert!(db.verify().is_ok());
+        assert_eq!(db.iter::<Person>().count(), 100);
+        drop(db);
+        std::fs::remove_dir_all(&crashed).unwrap();
+
+        //flips a bit in the second page of the names, the damage is found when the table is
+        //loaded instead of panicking on a later read
+        let file = path
+            .join("variable")
+            .read_dir()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut bytes = std::fs::read(&file).unwrap();
+        bytes[32 + 4096 + 4 + 100] ^= 1;
+        std::fs::write(&file, bytes).unwrap();
+        let error = open(&path).err().unwrap();
+        let error = error.downcast_ref::<ChecksumError>().unwrap();
+        assert_eq!(error.path, file.to_str().unwrap());
+        assert_eq!(error.offset, 4096);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn compressed_extent() {
+        //size of the files in a directory of the database
+        fn size(path: &Path, directory: &str) -> u64 {
+            path.join(directory)
+                .read_dir()
+                .unwrap()
+                .map(|entry| entry.unwrap().metadata().unwrap().len())
+                .sum()
+        }
+        let fill = |db: &mut Database<BackedManager<CompressedExtent<FileExtent>>>| {
+            for i in 0..200 {
+                db.insert(Person {
+                    name: format!("{} {}", "a long and very repetitive name".repeat(20), i),
+                    age: i,
+                })
+                .ok()
+                .unwrap();
+            }
+        };
+        let path = test_dir("compressed_extent");
+        let open =
+            |path: &Path| Database::new(BackedManager::<CompressedExtent<FileExtent>>::new(path)?);
+        let mut db = open(&path).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::EveryOperation);
+        fill(&mut db);
+        db.set_sync_policy(SyncPolicy::Manual);
+        db.insert(Person {
+            name: "unflushed".to_string(),
+            age: 1000,
+        })
+        .ok()
+        .unwrap();
+        let crashed = crash_copy(&path, "compressed_extent_crash");
+        drop(db);
+        let db = open(&crashed).ok().unwrap();
+        assert!(db.verify().is_ok());
+        assert_eq!(db.iter::<Person>().count(), 200);
+        drop(db);
+        std::fs::remove_dir_all(&crashed).unwrap();
+        let db = open(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 201);
+        let name = db
+            .iter::<Person>()
+            .find(|(_, person)| person.age == 7)
+            .unwrap()
+            .1
+            .name;
+        assert!(name.ends_with(" 7"));
+        drop(db);
+        let compressed = size(&path, "variable");
+
+        //overwrites the stored first page of the names, found through the directory the header
+        //points at, so that it no longer decompresses
+        let file = path
+            .join("variable")
+            .read_dir()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut bytes = std::fs::read(&file).unwrap();
+        let read = |bytes: &[u8], start: usize| {
+            u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap()) as usize
+        };
+        let page = read(&bytes, read(&bytes, 24));
+        for byte in bytes[page..page + 20].iter_mut() {
+            *byte = 0xff;
+        }
+        std::fs::write(&file, bytes).unwrap();
+        let error = open(&path).err().unwrap();
+        assert!(error.to_string().contains(file.to_str().unwrap()));
+        assert_eq!(
+            error.downcast_ref::<CompressedExtentError>(),
+            Some(&CompressedExtentError::InvalidPage { page: 0 })
+        );
+        std::fs::remove_dir_all(&path).unwrap();
+
+        //the same names stored with compression turned off for the variable sized fields
+        let path = test_dir("compressed_extent_off");
+        let manager = BackedManager::<CompressedExtent<FileExtent>>::new(&path)
+            .ok()
+            .unwrap()
+            .with_compression(|name| !name.starts_with("variable/"));
+        let mut db = Database::new(manager).ok().unwrap();
+        fill(&mut db);
+        drop(db);
+        let uncompressed = size(&path, "variable");
+        assert!(compressed * 5 < uncompressed);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn encrypted_extent() {
+        //whether any file under path holds bytes
+        fn contains(path: &Path, bytes: &[u8]) -> bool {
+            path.read_dir().unwrap().any(|entry| {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    contains(&path, bytes)
+                } else {
+                    let data = std::fs::read(&path).unwrap();
+                    data.windows(bytes.len()).any(|window| window == bytes)
+                }
+            })
+        }
+        const KEY: [u8; 32] = [3; 32];
+        let path = test_dir("encrypted_extent");
+        let mut db = on_disk_db_encrypted(&path, KEY).ok().unwrap();
+        db.set_sync_policy(SyncPolicy::EveryOperation);
+        for i in 0..50 {
+            db.insert(Person {
+                name: format!("private person {}", i),
+                age: i,
+            })
+            .ok()
+            .unwrap();
+        }
+        db.set_sync_policy(SyncPolicy::Manual);
+        db.insert(Person {
+            name: "unflushed".to_string(),
+            age: 100,
+        })
+        .ok()
+        .unwrap();
+        let crashed = crash_copy(&path, "encrypted_extent_crash");
+        drop(db);
+        assert!(!contains(&path, b"private person"));
+        //the log holds the pages changed by the unflushed insert, they are encrypted as well
+        assert!(crashed.join("wal.log").metadata().unwrap().len() > 0);
+        assert!(!contains(&crashed, b"private person"));
+        let db = on_disk_db_encrypted(&crashed, KEY).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 50);
+        drop(db);
+        std::fs::remove_dir_all(&crashed).unwrap();
+
+        assert!(on_disk_db_encrypted(&path, [4; 32]).is_err());
+        assert!(on_disk_db(&path).is_err());
+        let db = on_disk_db_encrypted(&path, KEY).ok().unwrap();
+        assert!(db.verify().is_ok());
+        assert_eq!(db.iter::<Person>().count(), 51);
+        drop(db);
+
+        //flips a bit in the first page of the names so that it no longer decrypts
+        let file = path
+            .join("variable")
+            .read_dir()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut bytes = std::fs::read(&file).unwrap();
+        bytes[80 + 24 + 10] ^= 1;
+        std::fs::write(&file, bytes).unwrap();
+        let error = on_disk_db_encrypted(&path, KEY).err().unwrap();
+        assert!(error.to_string().contains(file.to_str().unwrap()));
+        assert_eq!(
+            error.downcast_ref::<EncryptedExtentError>(),
+            Some(&EncryptedExtentError::InvalidPage { page: 0 })
+        );
+        std::fs::remove_dir_all(&path).unwrap();
+
+        //compression has to happen before encryption to be of any use
+        let path = test_dir("encrypted_extent_compressed");
+        let open = |path: &Path| {
+            Database::new(BackedManager::<
+                CompressedExtent<EncryptedExtent<FileExtent>>,
+            >::new_encrypted(path, KEY)?)
+        };
+        let mut db = open(&path).ok().unwrap();
+        for i in 0..50 {
+            db.insert(Person {
+                name: format!("private person {}", i),
+                age: i,
+            })
+            .ok()
+            .unwrap();
+        }
+        drop(db);
+        assert!(!contains(&path, b"private person"));
+        let db = open(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 50);
+        drop(db);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    #[test]
+    fn single_file() {
+        let directory = test_dir("single_file");
+        std::fs::create_dir(&directory).unwrap();
+        let path = directory.join("people.db");
+        let mut db = single_file_db(&path).ok().unwrap();
+        for i in 0..50 {
+            db.insert(Person {
+                name: format!("person {}", i),
+                age: i,
+            })
+            .ok()
+            .unwrap();
+        }
+        db.flush().ok().unwrap();
+        db.set_sync_policy(SyncPolicy::Manual);
+        db.insert(Person {
+            name: "unflushed".to_string(),
+            age: 100,
+        })
+        .ok()
+        .unwrap();
+        let crashed = directory.join("crashed.db");
+        std::fs::copy(&path, &crashed).unwrap();
+        std::fs::copy(
+            directory.join("people.db-wal"),
+            directory.join("crashed.db-wal"),
+        )
+        .unwrap();
+        assert!(single_file_db(&path).is_err());
+        drop(db);
+
+        let mut files: Vec<_> = directory
+            .read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["crashed.db", "crashed.db-wal", "people.db", "people.db-wal"]
+        );
+        let db = single_file_db(&crashed).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 50);
+        drop(db);
+
+        let db = Database::new(ContainerManager::open_read_only(&path).ok().unwrap())
+            .ok()
+            .unwrap();
+        assert_eq!(db.iter::<Person>().count(), 51);
+        assert!(db
+            .iter::<Person>()
+            .any(|(_, person)| person.name == "unflushed"));
+        drop(db);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+    #[test]
+    fn format_upgrade() {
+        let path = test_dir("format_upgrade");
+        let mut db = on_disk_db(&path).ok().unwrap();
+        for i in 0..20 {
+            db.insert(Person {
+                name: format!("person {}", i),
+                age: i,
+            })
+            .ok()
+            .unwrap();
+        }
+        drop(db);
+        //version 0 tables are the current ones without the 16 byte format header
+        let table = path
+            .join("contents")
+            .read_dir()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let data = std::fs::read(&table).unwrap();
+        std::fs::write(&table, &data[16..]).unwrap();
+        let error = on_disk_db(&path).err().unwrap().to_string();
+        assert!(error.contains(table.to_str().unwrap()));
+        assert!(error.contains("format version 0"));
+        assert!(error.contains("upgrade"));
+        assert_eq!(upgrade(&path).ok().unwrap(), 1);
+        assert_eq!(upgrade(&path).ok().unwrap(), 0);
+        let db = on_disk_db(&path).ok().unwrap();
+        assert_eq!(db.iter::<Person>().count(), 20);
+        drop(db);
+
+        //a format from a newer version is not touched by upgrade
+        let catalog = path.join("catalog.var");
+        let mut data = std::fs::read(&catalog).unwrap();
+        data[4] = 2;
+        std::fs::write(&catalog, &data).unwrap();
+        let error = on_disk_db(&path).err().unwrap().to_string();
+        assert!(error.contains("newer"));
+        assert!(upgrade(&path).is_err());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
 }
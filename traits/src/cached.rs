@@ -0,0 +1,402 @@
+use super::Extent;
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut, Range};
+use std::sync::Mutex;
+/// Size of the pages kept by a `CachedExtent`
+pub(crate) const PAGE_SIZE: usize = 4096;
+/// Number of cache hits and misses of a `CachedExtent`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+/// Storage read and written a page of `CachedExtent::PAGE_SIZE` bytes at a time. Every extent
+/// stores its own bytes as pages, other stores keep pages in a different form such as
+/// compressed or encrypted and are only reached through a `CachedExtent`
+pub trait PageStore {
+    /// Gets the number of bytes stored
+    fn byte_len(&self) -> usize;
+    /// Sets the number of bytes stored. Grown space reads as zero
+    fn set_len(&mut self, new_size: usize) -> anyhow::Result<()>;
+    /// Reads page number page into buffer, which is zeroed and a page long. Bytes past the end
+    /// are left alone
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> anyhow::Result<()>;
+    /// Overwrites page number page with data, bytes of data past the end are dropped
+    fn write_page(&mut self, page: usize, data: &[u8]) -> anyhow::Result<()>;
+    /// Makes the written pages survive a crash, see `Extent::flush`
+    fn sync(&mut self, range: Option<Range<usize>>) -> anyhow::Result<()>;
+    /// Checks the stored pages for damage, see `Extent::verify`
+    fn check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Fails if pages can not be written, so that changes are refused before they are cached
+    fn check_writable(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+impl<E: Extent> PageStore for E {
+    fn byte_len(&self) -> usize {
+        self.len()
+    }
+    fn set_len(&mut self, new_size: usize) -> anyhow::Result<()> {
+        self.resize(new_size)
+    }
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> anyhow::Result<()> {
+        let start = page * buffer.len();
+        let end = (start + buffer.len()).min(self.len());
+        if start < end {
+            self.read_at(start, &mut buffer[..end - start]);
+        }
+        Ok(())
+    }
+    fn write_page(&mut self, page: usize, data: &[u8]) -> anyhow::Result<()> {
+        let start = page * data.len();
+        let end = (start + data.len()).min(self.len());
+        if start < end {
+            self.write_at(start, &data[..end - start]);
+        }
+        Ok(())
+    }
+    fn sync(&mut self, range: Option<Range<usize>>) -> anyhow::Result<()> {
+        self.flush(range)
+    }
+    fn check(&self) -> anyhow::Result<()> {
+        self.verify()
+    }
+}
+struct Page {
+    data: Box<[u8]>,
+    dirty: bool,
+    //value of the clock when the page was last used
+    used: u64,
+}
+#[derive(Default)]
+struct Cache {
+    pages: HashMap<usize, Page>,
+    clock: u64,
+    stats: CacheStats,
+}
+impl Cache {
+    /// Gets the least recently used page, only looking at clean pages if clean is set
+    fn least_used(&self, clean: bool) -> Option<usize> {
+        self.pages
+            .iter()
+            .filter(|(_, entry)| !clean || !entry.dirty)
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(page, _)| *page)
+    }
+}
+/// Every byte value, `index` hands out references into it so that no reference into the cache
+/// outlives the lock
+static BYTE_VALUES: [u8; 256] = byte_values();
+const fn byte_values() -> [u8; 256] {
+    let mut values = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        values[i] = i as u8;
+        i += 1;
+    }
+    values
+}
+/// Keeps the pages of a `PageStore` in memory. At most `capacity` pages are kept, the least
+/// recently used page is evicted to make room. Changed pages are written back to the store
+/// when they are evicted, on `flush` and when the cache is dropped.
+///
+/// Reading through `&self` only evicts pages that have not changed, a page read while every
+/// cached page has changed is not cached. A page that can not be read panics.
+pub struct CachedExtent<P: PageStore> {
+    inner: P,
+    cache: Mutex<Cache>,
+    capacity: usize,
+}
+impl<P: PageStore> CachedExtent<P> {
+    pub const PAGE_SIZE: usize = PAGE_SIZE;
+    /// Number of pages cached by extents made with `new`
+    pub const DEFAULT_CAPACITY: usize = 256;
+    pub fn new(inner: P) -> Self {
+        Self::with_capacity(inner, Self::DEFAULT_CAPACITY)
+    }
+    /// Caches at most capacity pages of inner
+    pub fn with_capacity(inner: P, capacity: usize) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(Cache::default()),
+            capacity: capacity.max(1),
+        }
+    }
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats
+    }
+    /// Gets the number of pages in the cache
+    pub fn cached_pages(&self) -> usize {
+        self.cache.lock().unwrap().pages.len()
+    }
+    /// Gets the inner store. Changes that are still cached have not reached it yet
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+    /// Gets the inner store to change settings that do not touch the stored pages
+    pub(crate) fn inner_mut(&mut self) -> &mut P {
+        &mut self.inner
+    }
+    /// Reads page from the store
+    fn load(&self, page: usize) -> Box<[u8]> {
+        let mut data = vec![0; Self::PAGE_SIZE].into_boxed_slice();
+        if let Err(error) = self.inner.read_page(page, &mut data) {
+            panic!("{}", error);
+        }
+        data
+    }
+    /// Copies the bytes of page starting at start into buffer
+    fn copy_page(&self, page: usize, start: usize, buffer: &mut [u8]) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let clock = cache.clock;
+        if let Some(entry) = cache.pages.get_mut(&page) {
+            entry.used = clock;
+            buffer.copy_from_slice(&entry.data[start..start + buffer.len()]);
+            cache.stats.hits += 1;
+            return;
+        }
+        cache.stats.misses += 1;
+        let data = self.load(page);
+        buffer.copy_from_slice(&data[start..start + buffer.len()]);
+        if cache.pages.len() >= self.capacity {
+            match cache.least_used(true) {
+                Some(evicted) => {
+                    cache.pages.remove(&evicted);
+                }
+                //changed pages are only written back through &mut self
+                None => return,
+            }
+        }
+        cache.pages.insert(
+            page,
+            Page {
+                data,
+                dirty: false,
+                used: clock,
+            },
+        );
+    }
+    /// Makes room for a page that is not cached, writing back the evicted page if it changed
+    fn make_room(&mut self) {
+        let cache = self.cache.get_mut().unwrap();
+        while cache.pages.len() >= self.capacity {
+            let page = cache.least_used(false).unwrap();
+            let entry = cache.pages.remove(&page).unwrap();
+            if entry.dirty {
+                if let Err(error) = self.inner.write_page(page, &entry.data) {
+                    panic!("failed to write back page {}: {}", page, error);
+                }
+            }
+        }
+    }
+    /// Gets a cached page to change, the page is marked as dirty. If data is given it replaces
+    /// the page, which is then not read from the store
+    fn page_mut(&mut self, page: usize, data: Option<&[u8]>) -> &mut [u8] {
+        if let Err(error) = self.inner.check_writable() {
+            panic!("{}", error);
+        }
+        let cached = self.cache.get_mut().unwrap().pages.contains_key(&page);
+        if !cached {
+            self.make_room();
+        }
+        let loaded = match (cached, data) {
+            (_, Some(data)) => Some(data.to_vec().into_boxed_slice()),
+            (false, None) => Some(self.load(page)),
+            (true, None) => None,
+        };
+        let cache = self.cache.get_mut().unwrap();
+        cache.clock += 1;
+        if cached {
+            cache.stats.hits += 1;
+        } else {
+            cache.stats.misses += 1;
+        }
+        let used = cache.clock;
+        if let Some(data) = loaded {
+            cache.pages.insert(
+                page,
+                Page {
+                    data,
+                    dirty: true,
+                    used,
+                },
+            );
+        }
+        let entry = cache.pages.get_mut(&page).unwrap();
+        entry.dirty = true;
+        entry.used = used;
+        &mut entry.data
+    }
+    /// Writes back the dirty pages that overlap range
+    fn write_dirty(&mut self, range: Range<usize>) -> anyhow::Result<()> {
+        let cache = self.cache.get_mut().unwrap();
+        for (page, entry) in cache.pages.iter_mut() {
+            let start = page * Self::PAGE_SIZE;
+            if entry.dirty && start < range.end && range.start < start + Self::PAGE_SIZE {
+                self.inner.write_page(*page, &entry.data)?;
+                entry.dirty = false;
+            }
+        }
+        Ok(())
+    }
+}
+impl<P: PageStore> Drop for CachedExtent<P> {
+    fn drop(&mut self) {
+        self.write_dirty(0..self.inner.byte_len()).ok();
+    }
+}
+impl<P: PageStore> Extent for CachedExtent<P> {
+    /// Grown space is zeroed
+    fn resize(&mut self, new_size: usize) -> anyhow::Result<()> {
+        self.inner.check_writable()?;
+        //bytes cut off by shrinking read as zero if the extent grows again
+        if new_size < self.len() && !new_size.is_multiple_of(Self::PAGE_SIZE) {
+            let start = new_size % Self::PAGE_SIZE;
+            for byte in self.page_mut(new_size / Self::PAGE_SIZE, None)[start..].iter_mut() {
+                *byte = 0;
+            }
+        }
+        self.inner.set_len(new_size)?;
+        self.cache
+            .get_mut()
+            .unwrap()
+            .pages
+            .retain(|page, _| page * Self::PAGE_SIZE < new_size);
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        self.inner.byte_len()
+    }
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        assert!(offset + buffer.len() <= self.len(), "index out of bounds");
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done;
+            let start = position % Self::PAGE_SIZE;
+            let count = (Self::PAGE_SIZE - start).min(buffer.len() - done);
+            self.copy_page(
+                position / Self::PAGE_SIZE,
+                start,
+                &mut buffer[done..done + count],
+            );
+            done += count;
+        }
+    }
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= self.len(), "index out of bounds");
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done;
+            let page = position / Self::PAGE_SIZE;
+            let start = position % Self::PAGE_SIZE;
+            let count = (Self::PAGE_SIZE - start).min(data.len() - done);
+            let data = &data[done..done + count];
+            if count == Self::PAGE_SIZE {
+                //the whole page is replaced so the old one is not read, which lets the log
+                //restore pages that were torn by a crash
+                self.page_mut(page, Some(data));
+            } else {
+                self.page_mut(page, None)[start..start + count].copy_from_slice(data);
+            }
+            done += count;
+        }
+    }
+    /// Writes back the dirty pages in range then flushes the inner store
+    fn flush(&mut self, range: Option<Range<usize>>) -> anyhow::Result<()> {
+        self.write_dirty(range.clone().unwrap_or(0..self.len()))?;
+        self.inner.sync(range)
+    }
+    /// Checks the inner store, changes that are still cached are not checked
+    fn verify(&self) -> anyhow::Result<()> {
+        self.inner.check()
+    }
+}
+impl<P: PageStore> Index<usize> for CachedExtent<P> {
+    type Output = u8;
+    fn index(&self, idx: usize) -> &Self::Output {
+        if idx >= self.len() {
+            panic!("index out of bounds")
+        }
+        let mut byte = [0];
+        self.read_at(idx, &mut byte);
+        &BYTE_VALUES[byte[0] as usize]
+    }
+}
+impl<P: PageStore> IndexMut<usize> for CachedExtent<P> {
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        if idx >= self.len() {
+            panic!("index mut out of bounds")
+        }
+        &mut self.page_mut(idx / Self::PAGE_SIZE, None)[idx % Self::PAGE_SIZE]
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryExtent;
+    #[test]
+    fn write_back() {
+        let mut extent = CachedExtent::with_capacity(InMemoryExtent::new(), 2);
+        extent
+            .resize(CachedExtent::<InMemoryExtent>::PAGE_SIZE * 8)
+            .unwrap();
+        for page in 0..8 {
+            extent.write_at(page * 4096 + 5, &[page as u8 + 1; 10]);
+        }
+        assert_eq!(extent.cached_pages(), 2);
+        for page in 0..8 {
+            assert_eq!(extent[page * 4096 + 14], page as u8 + 1);
+        }
+        extent.flush(None).unwrap();
+        let inner = extent.inner();
+        for page in 0..8 {
+            assert_eq!(
+                inner.as_slice(page * 4096 + 5..page * 4096 + 15),
+                &[page as u8 + 1; 10][..]
+            );
+        }
+    }
+    #[test]
+    fn bounded_by_indexing() {
+        let mut inner = InMemoryExtent::new();
+        inner.resize(4096 * 10).unwrap();
+        let mut extent = CachedExtent::with_capacity(inner, 3);
+        for page in 0..10 {
+            assert_eq!(extent[page * 4096], 0);
+            assert!(extent.cached_pages() <= 3);
+        }
+        for page in 0..10 {
+            extent[page * 4096 + 1] = page as u8;
+            assert!(extent.cached_pages() <= 3);
+        }
+        //every cached page has changed, reading other pages does not cache them
+        for page in 0..10 {
+            assert_eq!(extent[page * 4096 + 1], page as u8);
+            assert!(extent.cached_pages() <= 3);
+        }
+    }
+    #[test]
+    fn stats() {
+        let mut inner = InMemoryExtent::new();
+        inner.resize(10_000).unwrap();
+        let extent = CachedExtent::new(inner);
+        let mut buffer = [0; 100];
+        extent.read_at(0, &mut buffer);
+        extent.read_at(100, &mut buffer);
+        extent.read_at(4090, &mut buffer);
+        assert_eq!(extent.stats(), CacheStats { hits: 2, misses: 2 });
+    }
+    #[test]
+    fn shrink() {
+        let mut extent = CachedExtent::new(InMemoryExtent::new());
+        extent.resize(5000).unwrap();
+        extent.write_at(4000, &[1; 1000]);
+        extent.resize(4500).unwrap();
+        extent.resize(5000).unwrap();
+        assert_eq!(extent.as_slice(4000..4500), &[1; 500][..]);
+        assert_eq!(extent.as_slice(4500..5000), &[0; 500][..]);
+    }
+}
@@ -0,0 +1,362 @@
+use super::{Extent, FormatHeader};
+use crc32fast::hash;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::ops::{Index, IndexMut, Range};
+use std::sync::Mutex;
+use thiserror::Error;
+/// The stored checksum of part of an extent does not match its contents
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("checksum mismatch in {path} at offset {offset}")]
+pub struct ChecksumError {
+    /// Name of the extent given to `ChecksummedExtent::new`
+    pub path: String,
+    /// Offset of the first byte covered by the failed checksum, the header is at offset 0
+    pub offset: usize,
+}
+///# Checksummed extent
+///
+///Stores the bytes of another extent in pages, each followed by the CRC32 of the page.
+///`verify` checks every page and returns a `ChecksumError` naming the first damaged one, the
+///structures stored in extents call it when they are loaded so that damage is reported when a
+///table is opened. A page that has not been checked yet is checked the first time it is read
+///and a mismatch there panics, `try_read_at` returns the error instead. Writes do not check the
+///page they change.
+///
+///A changed page is only marked stale, its checksum is computed once on `flush` or when the
+///extent is dropped however many writes changed it.
+///
+///Layout of the inner extent:
+///
+///|`FormatHeader` (16 bytes)|length (8 bytes)|crc of the format header and length (4 bytes)|
+///|padding (4 bytes)|page 0|crc 0|page 1|crc 1|...
+///
+///Bytes of the last page past the length are zero.
+pub struct ChecksummedExtent<E: Extent> {
+    inner: E,
+    //name used in errors
+    path: String,
+    len: usize,
+    //pages whose checksum has been checked or written
+    verified: Mutex<HashSet<usize>>,
+    //changed pages that need a new checksum
+    stale: HashSet<usize>,
+}
+impl<E: Extent> ChecksummedExtent<E> {
+    pub const PAGE_SIZE: usize = 4096;
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WCRC";
+    const HEADER_SIZE: usize = FormatHeader::SIZE + 16;
+    const CRC_SIZE: usize = 4;
+    /// Wraps inner, which is formatted first if it is empty. Fails with a `ChecksumError` if
+    /// the header of inner is damaged and with a `FormatError` if inner is stored in another
+    /// version. path names the extent in errors
+    pub fn new(mut inner: E, path: String) -> anyhow::Result<Self> {
+        if inner.len() == 0 {
+            inner.resize(Self::HEADER_SIZE)?;
+            let mut extent = Self {
+                inner,
+                path,
+                len: 0,
+                verified: Mutex::new(HashSet::new()),
+                stale: HashSet::new(),
+            };
+            extent.write_header();
+            return Ok(extent);
+        }
+        let error = ChecksumError {
+            path: path.clone(),
+            offset: 0,
+        };
+        if inner.len() < Self::HEADER_SIZE {
+            return Err(error.into());
+        }
+        let header = inner.as_slice(0..Self::HEADER_SIZE);
+        match FormatHeader::parse(&header, Self::MAGIC) {
+            Some(format) => format.check("checksummed extent", Self::VERSION, 0)?,
+            None => return Err(error.into()),
+        }
+        let len = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+        let stored = u32::from_le_bytes(header[24..28].try_into().unwrap());
+        if hash(&header[0..24]) != stored || inner.len() != Self::physical_len(len) {
+            return Err(error.into());
+        }
+        Ok(Self {
+            inner,
+            path,
+            len,
+            verified: Mutex::new(HashSet::new()),
+            stale: HashSet::new(),
+        })
+    }
+    /// Gets the inner extent
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    /// Length of the inner extent needed to hold len bytes
+    fn physical_len(len: usize) -> usize {
+        Self::HEADER_SIZE + Self::pages(len) * (Self::PAGE_SIZE + Self::CRC_SIZE)
+    }
+    /// Number of pages needed to hold len bytes
+    fn pages(len: usize) -> usize {
+        len.div_ceil(Self::PAGE_SIZE)
+    }
+    /// Offset in the inner extent of the start of page
+    fn page_start(page: usize) -> usize {
+        Self::HEADER_SIZE + page * (Self::PAGE_SIZE + Self::CRC_SIZE)
+    }
+    /// Offset in the inner extent of byte offset
+    fn physical(offset: usize) -> usize {
+        Self::page_start(offset / Self::PAGE_SIZE) + offset % Self::PAGE_SIZE
+    }
+    fn write_header(&mut self) {
+        let mut header = vec![0; Self::HEADER_SIZE];
+        header[0..16]
+            .copy_from_slice(&FormatHeader::new(Self::MAGIC, Self::VERSION, 0).to_binary());
+        header[16..24].copy_from_slice(&(self.len as u64).to_le_bytes());
+        let crc = hash(&header[0..24]);
+        header[24..28].copy_from_slice(&crc.to_le_bytes());
+        self.inner.write_at(0, &header);
+    }
+    /// Writes the checksum of the contents of page
+    fn seal(&mut self, page: usize) {
+        let start = Self::page_start(page);
+        let crc = hash(&self.inner.as_slice(start..start + Self::PAGE_SIZE));
+        self.inner
+            .write_at(start + Self::PAGE_SIZE, &crc.to_le_bytes());
+        self.stale.remove(&page);
+        self.verified.lock().unwrap().insert(page);
+    }
+    fn seal_stale(&mut self) {
+        let stale: Vec<usize> = self.stale.iter().cloned().collect();
+        for page in stale {
+            self.seal(page);
+        }
+    }
+    /// Compares the contents of page with its checksum
+    fn check(&self, page: usize) -> Result<(), ChecksumError> {
+        let start = Self::page_start(page);
+        let data = self
+            .inner
+            .as_slice(start..start + Self::PAGE_SIZE + Self::CRC_SIZE);
+        let stored = u32::from_le_bytes(data[Self::PAGE_SIZE..].try_into().unwrap());
+        if hash(&data[..Self::PAGE_SIZE]) == stored {
+            Ok(())
+        } else {
+            Err(ChecksumError {
+                path: self.path.clone(),
+                offset: page * Self::PAGE_SIZE,
+            })
+        }
+    }
+    /// Checks the pages holding range if they have not been checked yet
+    fn check_range(&self, range: Range<usize>) -> Result<(), ChecksumError> {
+        if range.start >= range.end {
+            return Ok(());
+        }
+        let mut verified = self.verified.lock().unwrap();
+        for page in range.start / Self::PAGE_SIZE..=(range.end - 1) / Self::PAGE_SIZE {
+            if !verified.contains(&page) && !self.stale.contains(&page) {
+                self.check(page)?;
+                verified.insert(page);
+            }
+        }
+        Ok(())
+    }
+    /// Reads the bytes starting at offset, failing if a page they are on is damaged
+    pub fn try_read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<(), ChecksumError> {
+        assert!(offset + buffer.len() <= self.len, "index out of bounds");
+        self.check_range(offset..offset + buffer.len())?;
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done;
+            let count = (Self::PAGE_SIZE - position % Self::PAGE_SIZE).min(buffer.len() - done);
+            self.inner
+                .read_at(Self::physical(position), &mut buffer[done..done + count]);
+            done += count;
+        }
+        Ok(())
+    }
+}
+impl<E: Extent> Drop for ChecksummedExtent<E> {
+    fn drop(&mut self) {
+        self.seal_stale();
+    }
+}
+impl<E: Extent> Extent for ChecksummedExtent<E> {
+    fn resize(&mut self, new_size: usize) -> anyhow::Result<()> {
+        let old_pages = Self::pages(self.len);
+        let new_pages = Self::pages(new_size);
+        //keeps the bytes of the last page past the length zeroed
+        let cut = new_size < self.len && !new_size.is_multiple_of(Self::PAGE_SIZE);
+        if cut {
+            let start = Self::physical(new_size);
+            let end = Self::page_start(new_size / Self::PAGE_SIZE) + Self::PAGE_SIZE;
+            self.inner.write_at(start, &vec![0; end - start]);
+        }
+        self.inner.resize(Self::physical_len(new_size))?;
+        self.len = new_size;
+        self.stale.retain(|page| *page < new_pages);
+        self.verified
+            .get_mut()
+            .unwrap()
+            .retain(|page| *page < new_pages);
+        if cut {
+            self.stale.insert(new_pages - 1);
+        }
+        self.stale.extend(old_pages..new_pages);
+        self.write_header();
+        Ok(())
+    }
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        if let Err(error) = self.try_read_at(offset, buffer) {
+            panic!("{}", error);
+        }
+    }
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= self.len, "index out of bounds");
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done;
+            let count = (Self::PAGE_SIZE - position % Self::PAGE_SIZE).min(data.len() - done);
+            self.inner
+                .write_at(Self::physical(position), &data[done..done + count]);
+            self.stale.insert(position / Self::PAGE_SIZE);
+            done += count;
+        }
+    }
+    /// Writes the checksums of the stale pages then flushes the inner extent
+    fn flush(&mut self, _range: Option<Range<usize>>) -> anyhow::Result<()> {
+        self.seal_stale();
+        self.inner.flush(None)
+    }
+    /// Checks the header and the checksum of every page. Pages that pass are not checked again
+    /// when they are read
+    fn verify(&self) -> anyhow::Result<()> {
+        self.inner.verify()?;
+        let mut verified = self.verified.lock().unwrap();
+        for page in 0..Self::pages(self.len) {
+            if !self.stale.contains(&page) && !verified.contains(&page) {
+                self.check(page)?;
+                verified.insert(page);
+            }
+        }
+        Ok(())
+    }
+}
+impl<E: Extent> Index<usize> for ChecksummedExtent<E> {
+    type Output = u8;
+    fn index(&self, idx: usize) -> &Self::Output {
+        if idx >= self.len {
+            panic!("index out of bounds")
+        }
+        if let Err(error) = self.check_range(idx..idx + 1) {
+            panic!("{}", error);
+        }
+        &self.inner[Self::physical(idx)]
+    }
+}
+impl<E: Extent> IndexMut<usize> for ChecksummedExtent<E> {
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        if idx >= self.len {
+            panic!("index mut out of bounds")
+        }
+        self.stale.insert(idx / Self::PAGE_SIZE);
+        &mut self.inner[Self::physical(idx)]
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DrianableExtent, FormatError, InMemoryExtent};
+    const PAGE_SIZE: usize = 4096;
+    fn open(memory: &mut InMemoryExtent) -> anyhow::Result<ChecksummedExtent<DrianableExtent>> {
+        ChecksummedExtent::new(DrianableExtent::new(memory), "test".to_string())
+    }
+    #[test]
+    fn round_trip() {
+        let mut memory = InMemoryExtent::new();
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        {
+            let mut extent = open(&mut memory).unwrap();
+            extent.resize(12_000).unwrap();
+            extent.write_at(1000, &data);
+            extent[11_999] = 9;
+            extent.resize(11_500).unwrap();
+        }
+        let extent = open(&mut memory).unwrap();
+        assert_eq!(extent.len(), 11_500);
+        assert_eq!(extent.as_slice(1000..11_000), &data[..]);
+        extent.verify().unwrap();
+    }
+    #[test]
+    fn damaged_page() {
+        let mut memory = InMemoryExtent::new();
+        {
+            let mut extent = open(&mut memory).unwrap();
+            extent.resize(3 * PAGE_SIZE).unwrap();
+            extent.write_at(0, &[1; 3 * PAGE_SIZE]);
+        }
+        //flips a bit in the second page
+        memory[32 + PAGE_SIZE + 4 + 10] ^= 1;
+        let extent = open(&mut memory).unwrap();
+        let mut buffer = [0; 10];
+        assert!(extent.try_read_at(0, &mut buffer).is_ok());
+        let expected = ChecksumError {
+            path: "test".to_string(),
+            offset: PAGE_SIZE,
+        };
+        assert_eq!(
+            extent.try_read_at(PAGE_SIZE + 5, &mut buffer),
+            Err(expected.clone())
+        );
+        let error = extent.verify().unwrap_err();
+        assert_eq!(error.downcast_ref::<ChecksumError>(), Some(&expected));
+    }
+    #[test]
+    fn sealed_on_flush() {
+        let mut memory = InMemoryExtent::new();
+        let mut extent = open(&mut memory).unwrap();
+        extent.resize(2 * PAGE_SIZE).unwrap();
+        extent.flush(None).unwrap();
+        let crc_offset = 32 + PAGE_SIZE;
+        let crc = |extent: &ChecksummedExtent<DrianableExtent>| {
+            extent.inner().as_slice(crc_offset..crc_offset + 4).to_vec()
+        };
+        let before = crc(&extent);
+        for offset in 0..100 {
+            extent.write_at(offset * 10, &[1; 10]);
+        }
+        assert_eq!(crc(&extent), before);
+        assert_eq!(extent.stale.len(), 1);
+        extent.flush(None).unwrap();
+        assert_ne!(crc(&extent), before);
+        assert!(extent.stale.is_empty());
+        extent.verify().unwrap();
+    }
+    #[test]
+    fn damaged_header() {
+        let mut memory = InMemoryExtent::new();
+        open(&mut memory).unwrap().resize(100).unwrap();
+        //flips a bit in the length
+        memory[19] ^= 1;
+        let error = open(&mut memory).err().unwrap();
+        assert_eq!(
+            error
+                .downcast_ref::<ChecksumError>()
+                .map(|error| error.offset),
+            Some(0)
+        );
+        //a newer version is reported even though the checksum no longer matches
+        memory[19] ^= 1;
+        memory[4] = 2;
+        let error = open(&mut memory).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<FormatError>(),
+            Some(FormatError::Newer { found: 2, .. })
+        ));
+    }
+}
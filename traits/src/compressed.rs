@@ -0,0 +1,484 @@
+use super::cached::PAGE_SIZE;
+use super::{CachedExtent, Extent, FormatHeader, PageStore};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::ops::Range;
+use thiserror::Error;
+/// The inner extent of a `CompressedExtent` is damaged
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CompressedExtentError {
+    #[error("extent does not start with a valid compressed extent header")]
+    InvalidHeader,
+    #[error("page {page} of the compressed extent could not be decompressed")]
+    InvalidPage { page: usize },
+}
+/// Where a page is stored in the inner extent. Pages of zeros are not stored and have a length
+/// of 0
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    offset: usize,
+    len: usize,
+    compressed: bool,
+}
+///# Compressed extent
+///
+///Stores the pages of another extent compressed with LZ4. Pages that do not get smaller are
+///stored as they are and pages of zeros are not stored at all. Pages are decompressed into a
+///`CachedExtent` when they are used. Changed pages are written into free space when they are
+///evicted or flushed, but the header only points at them once the extent is flushed or
+///dropped, so the inner extent only ever holds flushed states.
+///
+///`verify` decompresses every page and returns a `CompressedExtentError` naming the first page
+///that fails, the structures stored in extents call it when they are loaded so that damage is
+///reported when a table is opened. A page that fails when it is first read panics.
+///
+///Layout of the inner extent:
+///
+///|`FormatHeader` (16 bytes)|length (8 bytes)|directory offset (8 bytes)|
+///|directory length (8 bytes)|padding to 64 bytes|pages and directory...|
+///
+///Flag 1 of the format header is set if pages written from now on are compressed.
+///
+///The directory holds an entry per page of the offset (8 bytes), the stored length (4 bytes) and
+///whether the page is compressed (4 bytes). A flush writes the changed pages and a new
+///directory into free space then points the header at the new directory, space used by the
+///old versions is only reused after that.
+pub type CompressedExtent<E> = CachedExtent<CompressedPages<E>>;
+/// Pages of a `CompressedExtent` as they are stored in the inner extent
+pub struct CompressedPages<E: Extent> {
+    inner: E,
+    len: usize,
+    //compress pages when they are written
+    compress: bool,
+    slots: Vec<Slot>,
+    //pages have been written or the length changed since the last flush
+    changed: bool,
+    //space in the inner extent not used by the flushed directory, offset to length
+    free: BTreeMap<usize, usize>,
+    //space given up since the last flush, it is still used by the flushed directory
+    released: Vec<(usize, usize)>,
+    //offset and length of the flushed directory
+    directory: (usize, usize),
+}
+impl<E: Extent> CompressedExtent<E> {
+    /// Turns compression of pages written from now on on or off. Pages already written stay
+    /// readable either way, the setting is stored on the next flush
+    pub fn set_compress(&mut self, compress: bool) {
+        self.inner_mut().compress = compress;
+    }
+}
+impl<E: Extent> CompressedPages<E> {
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WCMP";
+    //flag set if pages are compressed when they are written
+    const COMPRESS: u32 = 1;
+    const HEADER_SIZE: usize = 64;
+    const ENTRY_SIZE: usize = 16;
+    //space in the inner extent is handed out in multiples of this
+    const UNIT: usize = 64;
+    /// Wraps inner. An empty inner extent is formatted with compression turned on, otherwise
+    /// the setting stored in inner is used
+    pub fn new(mut inner: E) -> anyhow::Result<Self> {
+        if inner.len() == 0 {
+            inner.resize(Self::HEADER_SIZE)?;
+            let mut extent = Self::empty(inner);
+            extent.inner.write_at(0, &extent.header());
+            return Ok(extent);
+        }
+        let invalid = || anyhow::Error::from(CompressedExtentError::InvalidHeader);
+        if inner.len() < Self::HEADER_SIZE {
+            return Err(invalid());
+        }
+        let header = inner.as_slice(0..Self::HEADER_SIZE).into_owned();
+        let format = match FormatHeader::parse(&header, Self::MAGIC) {
+            Some(format) => format,
+            None => return Err(invalid()),
+        };
+        format.check("compressed extent", Self::VERSION, Self::COMPRESS)?;
+        let read = |range: Range<usize>| u64::from_le_bytes(header[range].try_into().unwrap());
+        let len = read(16..24) as usize;
+        let directory = (read(24..32) as usize, read(32..40) as usize);
+        if directory.0 + directory.1 > inner.len()
+            || directory.1 != Self::pages(len) * Self::ENTRY_SIZE
+        {
+            return Err(invalid());
+        }
+        let slots: Vec<Slot> = inner
+            .as_slice(directory.0..directory.0 + directory.1)
+            .chunks(Self::ENTRY_SIZE)
+            .map(|entry| Slot {
+                offset: u64::from_le_bytes(entry[0..8].try_into().unwrap()) as usize,
+                len: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize,
+                compressed: entry[12] == 1,
+            })
+            .collect();
+        //every byte not used by the directory or a page is free
+        let mut used: Vec<(usize, usize)> = slots
+            .iter()
+            .map(|slot| (slot.offset, slot.len))
+            .chain(Some(directory))
+            .filter(|(_, len)| *len > 0)
+            .map(|(offset, len)| (offset, Self::round(len)))
+            .collect();
+        used.sort_unstable();
+        let mut free = BTreeMap::new();
+        let mut position = Self::HEADER_SIZE;
+        for (offset, len) in used {
+            if offset < Self::HEADER_SIZE || offset + len > inner.len() {
+                return Err(invalid());
+            }
+            if offset > position {
+                free.insert(position, offset - position);
+            }
+            position = position.max(offset + len);
+        }
+        if inner.len() > position {
+            free.insert(position, inner.len() - position);
+        }
+        let compress = format.flags & Self::COMPRESS != 0;
+        let mut extent = Self::empty(inner);
+        extent.len = len;
+        extent.compress = compress;
+        extent.slots = slots;
+        extent.free = free;
+        extent.directory = directory;
+        Ok(extent)
+    }
+    fn empty(inner: E) -> Self {
+        Self {
+            inner,
+            len: 0,
+            compress: true,
+            slots: vec![],
+            changed: false,
+            free: BTreeMap::new(),
+            released: vec![],
+            directory: (0, 0),
+        }
+    }
+    /// Gets the inner extent, it holds the state of the last flush
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    /// Number of pages needed to hold len bytes
+    fn pages(len: usize) -> usize {
+        len.div_ceil(PAGE_SIZE)
+    }
+    /// Rounds a size up to the unit space is handed out in
+    fn round(size: usize) -> usize {
+        size.div_ceil(Self::UNIT) * Self::UNIT
+    }
+    fn header(&self) -> Vec<u8> {
+        let mut header = vec![0; Self::HEADER_SIZE];
+        let flags = if self.compress { Self::COMPRESS } else { 0 };
+        header[0..16]
+            .copy_from_slice(&FormatHeader::new(Self::MAGIC, Self::VERSION, flags).to_binary());
+        header[16..24].copy_from_slice(&(self.len as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&(self.directory.0 as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(self.directory.1 as u64).to_le_bytes());
+        header
+    }
+    /// Decompresses page from the inner extent into buffer, pages of zeros are left alone
+    fn load(&self, page: usize, buffer: &mut [u8]) -> Result<(), CompressedExtentError> {
+        let slot = self.slots[page];
+        if slot.len == 0 {
+            return Ok(());
+        }
+        let stored = self.inner.as_slice(slot.offset..slot.offset + slot.len);
+        let invalid = CompressedExtentError::InvalidPage { page };
+        if !slot.compressed {
+            if stored.len() != PAGE_SIZE {
+                return Err(invalid);
+            }
+            buffer.copy_from_slice(&stored);
+            return Ok(());
+        }
+        match lz4_flex::block::decompress_into(&stored, buffer) {
+            Ok(PAGE_SIZE) => Ok(()),
+            _ => Err(invalid),
+        }
+    }
+    /// Finds room for size bytes in the inner extent, growing it if there is no free space
+    fn allocate(&mut self, size: usize) -> anyhow::Result<usize> {
+        let size = Self::round(size);
+        let found = self
+            .free
+            .iter()
+            .find(|(_, len)| **len >= size)
+            .map(|(offset, len)| (*offset, *len));
+        if let Some((offset, len)) = found {
+            self.free.remove(&offset);
+            if len > size {
+                self.free.insert(offset + size, len - size);
+            }
+            return Ok(offset);
+        }
+        //free space at the end is grown instead of starting after it
+        let end = self.inner.len();
+        let offset = match self.free.iter().next_back() {
+            Some((offset, len)) if offset + len == end => *offset,
+            _ => end,
+        };
+        self.free.remove(&offset);
+        self.inner.resize(offset + size)?;
+        Ok(offset)
+    }
+    /// Returns space to the free list, joining it with free space next to it
+    fn release(&mut self, mut offset: usize, mut len: usize) {
+        if let Some((before, before_len)) = self.free.range(..offset).next_back() {
+            if before + before_len == offset {
+                offset = *before;
+                len += before_len;
+            }
+        }
+        self.free.remove(&offset);
+        if let Some(after_len) = self.free.remove(&(offset + len)) {
+            len += after_len;
+        }
+        self.free.insert(offset, len);
+    }
+    /// Writes a changed page into free space
+    fn store(&mut self, page: usize, data: &[u8]) -> anyhow::Result<()> {
+        let old = self.slots[page];
+        if old.len > 0 {
+            self.released.push((old.offset, Self::round(old.len)));
+        }
+        if data.iter().all(|byte| *byte == 0) {
+            self.slots[page] = Slot::default();
+            return Ok(());
+        }
+        let compressed = if self.compress {
+            Some(lz4_flex::block::compress(data)).filter(|bytes| bytes.len() < PAGE_SIZE)
+        } else {
+            None
+        };
+        let (bytes, compressed) = match compressed {
+            Some(bytes) => (bytes, true),
+            None => (data.to_vec(), false),
+        };
+        let offset = self.allocate(bytes.len())?;
+        self.inner.write_at(offset, &bytes);
+        self.slots[page] = Slot {
+            offset,
+            len: bytes.len(),
+            compressed,
+        };
+        Ok(())
+    }
+    /// Writes a new directory then points the header at it
+    fn commit(&mut self) -> anyhow::Result<()> {
+        //nothing is written if nothing changed, so read only extents can be dropped
+        let unchanged =
+            !self.changed && self.inner.as_slice(0..Self::HEADER_SIZE)[..] == self.header()[..];
+        if unchanged {
+            return Ok(());
+        }
+        let mut entries = Vec::with_capacity(self.slots.len() * Self::ENTRY_SIZE);
+        for slot in self.slots.iter() {
+            entries.extend_from_slice(&(slot.offset as u64).to_le_bytes());
+            entries.extend_from_slice(&(slot.len as u32).to_le_bytes());
+            entries.extend_from_slice(&(slot.compressed as u32).to_le_bytes());
+        }
+        let offset = if entries.is_empty() {
+            0
+        } else {
+            self.allocate(entries.len())?
+        };
+        self.inner.write_at(offset, &entries);
+        //the pages and directory reach storage before the header points at them
+        self.inner.flush(None)?;
+        let old = self.directory;
+        self.directory = (offset, entries.len());
+        self.inner.write_at(0, &self.header());
+        self.inner.flush(Some(0..Self::HEADER_SIZE))?;
+        if old.1 > 0 {
+            self.released.push((old.0, Self::round(old.1)));
+        }
+        for (offset, len) in std::mem::take(&mut self.released) {
+            self.release(offset, len);
+        }
+        //gives free space at the end back to the inner extent
+        let end = self.inner.len();
+        if let Some((offset, len)) = self.free.iter().next_back().map(|(o, l)| (*o, *l)) {
+            if offset + len == end {
+                self.free.remove(&offset);
+                self.inner.resize(offset)?;
+            }
+        }
+        self.changed = false;
+        Ok(())
+    }
+}
+impl<E: Extent> Drop for CompressedPages<E> {
+    fn drop(&mut self) {
+        self.commit().ok();
+    }
+}
+impl<E: Extent> PageStore for CompressedPages<E> {
+    fn byte_len(&self) -> usize {
+        self.len
+    }
+    /// Grown pages are pages of zeros
+    fn set_len(&mut self, new_size: usize) -> anyhow::Result<()> {
+        let new_pages = Self::pages(new_size);
+        for page in new_pages..self.slots.len() {
+            let slot = self.slots[page];
+            if slot.len > 0 {
+                self.released.push((slot.offset, Self::round(slot.len)));
+            }
+        }
+        self.slots.resize(new_pages, Slot::default());
+        self.len = new_size;
+        self.changed = true;
+        Ok(())
+    }
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> anyhow::Result<()> {
+        Ok(self.load(page, buffer)?)
+    }
+    fn write_page(&mut self, page: usize, data: &[u8]) -> anyhow::Result<()> {
+        self.store(page, data)?;
+        self.changed = true;
+        Ok(())
+    }
+    /// Writes the directory of the pages written so far, the range is ignored
+    fn sync(&mut self, _range: Option<Range<usize>>) -> anyhow::Result<()> {
+        self.commit()
+    }
+    /// Checks the inner extent and that every stored page can be decompressed
+    fn check(&self) -> anyhow::Result<()> {
+        self.inner.verify()?;
+        let mut buffer = vec![0; PAGE_SIZE];
+        for page in 0..self.slots.len() {
+            self.load(page, &mut buffer)?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DrianableExtent, FormatError, InMemoryExtent};
+    fn open(memory: &mut InMemoryExtent) -> CompressedExtent<DrianableExtent> {
+        CompressedExtent::new(CompressedPages::new(DrianableExtent::new(memory)).unwrap())
+    }
+    #[test]
+    fn round_trip() {
+        let mut memory = InMemoryExtent::new();
+        let text: Vec<u8> = "the quick brown fox jumps over the lazy dog "
+            .bytes()
+            .cycle()
+            .take(50_000)
+            .collect();
+        {
+            let mut extent = open(&mut memory);
+            extent.resize(100_000).unwrap();
+            extent.write_at(10, &text);
+            extent[99_999] = 3;
+            extent.flush(None).unwrap();
+            assert_eq!(extent.as_slice(10..50_010), &text[..]);
+        }
+        //text and zeros take up far less room than they would uncompressed
+        assert!(memory.len() < 10_000);
+        let mut extent = open(&mut memory);
+        assert_eq!(extent.len(), 100_000);
+        assert_eq!(extent.as_slice(10..50_010), &text[..]);
+        assert_eq!(extent[99_999], 3);
+        extent.verify().unwrap();
+        extent.resize(20).unwrap();
+        extent.resize(5000).unwrap();
+        assert_eq!(extent.as_slice(10..20), &text[..10]);
+        assert_eq!(extent.as_slice(20..5000), &[0; 4980][..]);
+    }
+    #[test]
+    fn reuse_space() {
+        let mut memory = InMemoryExtent::new();
+        let mut extent = open(&mut memory);
+        extent.resize(40_000).unwrap();
+        let data: Vec<u8> = (0..40_000).map(|i| (i * 7919 % 256) as u8).collect();
+        extent.write_at(0, &data);
+        extent.flush(None).unwrap();
+        let size = extent.inner().inner().len();
+        for _ in 0..10 {
+            extent.write_at(0, &data);
+            extent.flush(None).unwrap();
+        }
+        //old copies of the pages are reused once the new directory is written
+        assert!(extent.inner().inner().len() <= size * 2);
+        assert_eq!(extent.as_slice(0..40_000), &data[..]);
+    }
+    #[test]
+    fn evicted_pages() {
+        let mut memory = InMemoryExtent::new();
+        let pages = CompressedPages::new(DrianableExtent::new(&mut memory)).unwrap();
+        let mut extent = CompressedExtent::with_capacity(pages, 2);
+        extent.resize(8 * 4096).unwrap();
+        extent.flush(None).unwrap();
+        for page in 0..8 {
+            extent.write_at(page * 4096, &[page as u8 + 1; 100]);
+            assert!(extent.cached_pages() <= 2);
+        }
+        //evicted pages are written to the inner extent but only the flushed state is pointed at
+        let inner = extent.inner().inner();
+        let mut copy = InMemoryExtent::new();
+        copy.resize(inner.len()).unwrap();
+        copy.write_at(0, &inner.as_slice(0..inner.len()));
+        assert_eq!(open(&mut copy).as_slice(0..4096), &[0; 4096][..]);
+        extent.flush(None).unwrap();
+        drop(extent);
+        let extent = open(&mut memory);
+        for page in 0..8 {
+            assert_eq!(extent[page * 4096 + 99], page as u8 + 1);
+        }
+    }
+    #[test]
+    fn uncompressed() {
+        let mut memory = InMemoryExtent::new();
+        {
+            let mut extent = open(&mut memory);
+            extent.set_compress(false);
+            extent.resize(10_000).unwrap();
+            extent.write_at(0, &[5; 10_000]);
+        }
+        assert!(memory.len() > 10_000);
+        let extent = open(&mut memory);
+        assert_eq!(extent.as_slice(0..10_000), &[5; 10_000][..]);
+    }
+    #[test]
+    fn damaged_page() {
+        let mut memory = InMemoryExtent::new();
+        {
+            let mut extent = open(&mut memory);
+            extent.resize(2 * 4096).unwrap();
+            extent.write_at(4096, &[9; 4096]);
+        }
+        //the second page is the only one stored, it starts after the header
+        for byte in 64..84 {
+            memory[byte] = 0xff;
+        }
+        let error = open(&mut memory).verify().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CompressedExtentError>(),
+            Some(&CompressedExtentError::InvalidPage { page: 1 })
+        );
+    }
+    #[test]
+    fn invalid_header() {
+        let mut memory = InMemoryExtent::new();
+        memory.resize(100).unwrap();
+        assert!(CompressedPages::new(DrianableExtent::new(&mut memory)).is_err());
+        let mut memory = InMemoryExtent::new();
+        CompressedPages::new(DrianableExtent::new(&mut memory)).unwrap();
+        //sets a flag that is not known
+        memory[8] |= 2;
+        let error = CompressedPages::new(DrianableExtent::new(&mut memory))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<FormatError>(),
+            Some(&FormatError::UnknownFlags {
+                structure: "compressed extent",
+                flags: 2
+            })
+        );
+    }
+}
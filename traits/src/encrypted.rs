@@ -0,0 +1,410 @@
+use super::cached::PAGE_SIZE;
+use super::{CachedExtent, Extent, FormatHeader, PageStore};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::convert::TryInto;
+use std::ops::Range;
+use thiserror::Error;
+/// The inner extent of an `EncryptedExtent` is damaged or encrypted with another key
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum EncryptedExtentError {
+    #[error("extent does not start with a valid encrypted extent header")]
+    InvalidHeader,
+    #[error("extent could not be decrypted, the key is wrong or the header is damaged")]
+    WrongKey,
+    #[error("page {page} of the encrypted extent could not be decrypted")]
+    InvalidPage { page: usize },
+    #[error("failed to encrypt the extent")]
+    EncryptFailed,
+}
+///# Encrypted extent
+///
+///Stores the bytes of another extent in pages encrypted with XChaCha20-Poly1305. Every time a
+///page is written it gets a new random nonce. Every extent gets a random id when it is made, the
+///id and page number are authenticated along with the page so that pages can not be swapped
+///around within an extent or moved between extents sharing the key. Pages are decrypted into
+///a `CachedExtent` when they are used and written back encrypted when they are evicted, when
+///the extent is flushed and when it is dropped.
+///
+///The header authenticates the format header, length and id with the key, so opening with the
+///wrong key fails straight away instead of when the first page is read. `verify` decrypts every
+///page and returns an `EncryptedExtentError` naming the first page that fails, the structures
+///stored in extents call it when they are loaded so that damage is reported when a table is
+///opened. A page that fails when it is first read panics.
+///
+///Layout of the inner extent:
+///
+///|`FormatHeader` (16 bytes)|length (8 bytes)|id (16 bytes)|nonce (24 bytes)|tag (16 bytes)|
+///|nonce 0|page 0|tag 0|nonce 1|page 1|tag 1|...
+///
+///Flag 1 of the format header is set if the header holds the id. Extents made before the id was
+///added do not have it and only authenticate the page number.
+pub type EncryptedExtent<E> = CachedExtent<EncryptedPages<E>>;
+/// Pages of an `EncryptedExtent` as they are stored in the inner extent
+pub struct EncryptedPages<E: Extent> {
+    inner: E,
+    cipher: XChaCha20Poly1305,
+    //authenticated with every page, None for extents made before ids were added
+    id: Option<[u8; 16]>,
+    len: usize,
+}
+impl<E: Extent> EncryptedPages<E> {
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WENC";
+    //flag set if the header holds the id of the extent
+    const FILE_ID: u32 = 1;
+    const ID_SIZE: usize = 16;
+    const NONCE_SIZE: usize = 24;
+    const TAG_SIZE: usize = 16;
+    /// Wraps inner, which is formatted first if it is empty. Fails if inner was encrypted with
+    /// a different key or is stored in another version
+    pub fn new(inner: E, key: &[u8; 32]) -> anyhow::Result<Self> {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+        if inner.len() == 0 {
+            let mut id = [0; 16];
+            OsRng.fill_bytes(&mut id);
+            let mut extent = Self::empty(inner, cipher, Some(id));
+            extent.inner.resize(extent.header_size())?;
+            extent.write_header()?;
+            return Ok(extent);
+        }
+        let header = inner
+            .as_slice(0..std::cmp::min(inner.len(), Self::header_size_of(true)))
+            .into_owned();
+        let has_id = match FormatHeader::parse(&header, Self::MAGIC) {
+            Some(format) => {
+                format.check("encrypted extent", Self::VERSION, Self::FILE_ID)?;
+                format.flags & Self::FILE_ID != 0
+            }
+            None => return Err(EncryptedExtentError::InvalidHeader.into()),
+        };
+        let header_size = Self::header_size_of(has_id);
+        if header.len() < header_size {
+            return Err(EncryptedExtentError::InvalidHeader.into());
+        }
+        //the fields before the nonce are authenticated
+        let fields = header_size - Self::NONCE_SIZE - Self::TAG_SIZE;
+        let nonce = XNonce::from_slice(&header[fields..fields + Self::NONCE_SIZE]);
+        let payload = Payload {
+            msg: &header[fields + Self::NONCE_SIZE..header_size],
+            aad: &header[0..fields],
+        };
+        if cipher.decrypt(nonce, payload).is_err() {
+            return Err(EncryptedExtentError::WrongKey.into());
+        }
+        let len = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+        let id = if has_id {
+            Some(header[24..24 + Self::ID_SIZE].try_into().unwrap())
+        } else {
+            None
+        };
+        let mut extent = Self::empty(inner, cipher, id);
+        //the inner extent is grown before the header and shrunk after it
+        if extent.inner.len() < extent.physical_len(len) {
+            return Err(EncryptedExtentError::InvalidHeader.into());
+        }
+        extent.len = len;
+        Ok(extent)
+    }
+    fn empty(inner: E, cipher: XChaCha20Poly1305, id: Option<[u8; 16]>) -> Self {
+        Self {
+            inner,
+            cipher,
+            id,
+            len: 0,
+        }
+    }
+    /// Gets the inner extent, changes that are still cached have not reached it yet
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+    /// Size of a page in the inner extent
+    const fn slot_size() -> usize {
+        Self::NONCE_SIZE + PAGE_SIZE + Self::TAG_SIZE
+    }
+    /// Number of pages needed to hold len bytes
+    fn pages(len: usize) -> usize {
+        len.div_ceil(PAGE_SIZE)
+    }
+    fn header_size_of(has_id: bool) -> usize {
+        let id = if has_id { Self::ID_SIZE } else { 0 };
+        FormatHeader::SIZE + 8 + id + Self::NONCE_SIZE + Self::TAG_SIZE
+    }
+    fn header_size(&self) -> usize {
+        Self::header_size_of(self.id.is_some())
+    }
+    /// Length of the inner extent needed to hold len bytes
+    fn physical_len(&self, len: usize) -> usize {
+        self.header_size() + Self::pages(len) * Self::slot_size()
+    }
+    fn slot_start(&self, page: usize) -> usize {
+        self.header_size() + page * Self::slot_size()
+    }
+    /// Data authenticated along with page, the id of the extent then the page number
+    fn page_aad(&self, page: usize) -> Vec<u8> {
+        let mut aad = self.id.map(|id| id.to_vec()).unwrap_or_default();
+        aad.extend_from_slice(&(page as u64).to_le_bytes());
+        aad
+    }
+    fn write_header(&mut self) -> anyhow::Result<()> {
+        let flags = if self.id.is_some() { Self::FILE_ID } else { 0 };
+        let mut header = FormatHeader::new(Self::MAGIC, Self::VERSION, flags)
+            .to_binary()
+            .to_vec();
+        header.extend_from_slice(&(self.len as u64).to_le_bytes());
+        if let Some(id) = self.id {
+            header.extend_from_slice(&id);
+        }
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: &[],
+            aad: &header,
+        };
+        let tag = match self.cipher.encrypt(&nonce, payload) {
+            Ok(tag) => tag,
+            Err(_) => return Err(EncryptedExtentError::EncryptFailed.into()),
+        };
+        header.extend_from_slice(&nonce);
+        header.extend_from_slice(&tag);
+        self.inner.write_at(0, &header);
+        Ok(())
+    }
+    /// Decrypts page from the inner extent
+    fn load(&self, page: usize) -> Result<Vec<u8>, EncryptedExtentError> {
+        let start = self.slot_start(page);
+        let slot = self.inner.as_slice(start..start + Self::slot_size());
+        let aad = self.page_aad(page);
+        let payload = Payload {
+            msg: &slot[Self::NONCE_SIZE..],
+            aad: &aad,
+        };
+        match self
+            .cipher
+            .decrypt(XNonce::from_slice(&slot[..Self::NONCE_SIZE]), payload)
+        {
+            Ok(data) if data.len() == PAGE_SIZE => Ok(data),
+            _ => Err(EncryptedExtentError::InvalidPage { page }),
+        }
+    }
+    /// Encrypts data and writes it to the slot of page
+    fn seal(&mut self, page: usize, data: &[u8]) -> anyhow::Result<()> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = self.page_aad(page);
+        let payload = Payload {
+            msg: data,
+            aad: &aad,
+        };
+        let encrypted = match self.cipher.encrypt(&nonce, payload) {
+            Ok(encrypted) => encrypted,
+            Err(_) => return Err(EncryptedExtentError::EncryptFailed.into()),
+        };
+        let start = self.slot_start(page);
+        self.inner.write_at(start, &nonce);
+        self.inner.write_at(start + Self::NONCE_SIZE, &encrypted);
+        Ok(())
+    }
+}
+impl<E: Extent> PageStore for EncryptedPages<E> {
+    fn byte_len(&self) -> usize {
+        self.len
+    }
+    /// Grown pages are encrypted pages of zeros
+    fn set_len(&mut self, new_size: usize) -> anyhow::Result<()> {
+        let old_pages = Self::pages(self.len);
+        let new_pages = Self::pages(new_size);
+        //the header never claims pages the inner extent does not hold
+        if new_pages > old_pages {
+            self.inner.resize(self.physical_len(new_size))?;
+            let zeros = vec![0; PAGE_SIZE];
+            for page in old_pages..new_pages {
+                self.seal(page, &zeros)?;
+            }
+        }
+        self.len = new_size;
+        self.write_header()?;
+        if new_pages < old_pages {
+            self.inner.resize(self.physical_len(new_size))?;
+        }
+        Ok(())
+    }
+    fn read_page(&self, page: usize, buffer: &mut [u8]) -> anyhow::Result<()> {
+        buffer.copy_from_slice(&self.load(page)?);
+        Ok(())
+    }
+    fn write_page(&mut self, page: usize, data: &[u8]) -> anyhow::Result<()> {
+        self.seal(page, data)
+    }
+    /// Flushes the inner extent, the range is ignored
+    fn sync(&mut self, _range: Option<Range<usize>>) -> anyhow::Result<()> {
+        self.inner.flush(None)
+    }
+    /// Checks the inner extent and that every page can be decrypted
+    fn check(&self) -> anyhow::Result<()> {
+        self.inner.verify()?;
+        for page in 0..Self::pages(self.len) {
+            self.load(page)?;
+        }
+        Ok(())
+    }
+}
+/// Encrypts data with key under a new random nonce, aad is authenticated along with it. The
+/// nonce is stored in front of the encrypted data. Used for data kept outside of extents, such
+/// as log records
+pub fn seal(key: &[u8; 32], data: &[u8], aad: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload { msg: data, aad };
+    match cipher.encrypt(&nonce, payload) {
+        Ok(encrypted) => Ok(nonce.iter().copied().chain(encrypted).collect()),
+        Err(_) => Err(EncryptedExtentError::EncryptFailed.into()),
+    }
+}
+/// Decrypts data made by `seal`, returns None if it was sealed with a different key or aad or
+/// is damaged
+pub fn unseal(key: &[u8; 32], data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    const NONCE_SIZE: usize = 24;
+    if data.len() < NONCE_SIZE {
+        return None;
+    }
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let payload = Payload {
+        msg: &data[NONCE_SIZE..],
+        aad,
+    };
+    cipher
+        .decrypt(XNonce::from_slice(&data[..NONCE_SIZE]), payload)
+        .ok()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DrianableExtent, InMemoryExtent};
+    const KEY: [u8; 32] = [7; 32];
+    fn open(
+        memory: &mut InMemoryExtent,
+        key: &[u8; 32],
+    ) -> anyhow::Result<EncryptedExtent<DrianableExtent>> {
+        let pages = EncryptedPages::new(DrianableExtent::new(memory), key)?;
+        Ok(EncryptedExtent::new(pages))
+    }
+    #[test]
+    fn round_trip() {
+        let mut memory = InMemoryExtent::new();
+        let text = b"personal data that must not reach the disk in plaintext";
+        {
+            let mut extent = open(&mut memory, &KEY).unwrap();
+            extent.resize(10_000).unwrap();
+            extent.write_at(5000, text);
+            extent[9999] = 4;
+            extent.flush(None).unwrap();
+            assert_eq!(extent.as_slice(5000..5000 + text.len()), &text[..]);
+        }
+        let stored = memory.as_slice(0..memory.len());
+        assert!(!stored.windows(text.len()).any(|window| window == &text[..]));
+        let mut extent = open(&mut memory, &KEY).unwrap();
+        assert_eq!(extent.len(), 10_000);
+        assert_eq!(extent.as_slice(5000..5000 + text.len()), &text[..]);
+        assert_eq!(extent[9999], 4);
+        extent.verify().unwrap();
+        extent.resize(5010).unwrap();
+        extent.resize(9000).unwrap();
+        assert_eq!(extent.as_slice(5000..5010), &text[..10]);
+        assert_eq!(extent.as_slice(5010..9000), &[0; 3990][..]);
+    }
+    #[test]
+    fn evicted_pages() {
+        let mut memory = InMemoryExtent::new();
+        {
+            let pages = EncryptedPages::new(DrianableExtent::new(&mut memory), &KEY).unwrap();
+            let mut extent = EncryptedExtent::with_capacity(pages, 2);
+            extent.resize(8 * 4096).unwrap();
+            for page in 0..8 {
+                extent.write_at(page * 4096, &[page as u8 + 1; 100]);
+                assert!(extent.cached_pages() <= 2);
+            }
+        }
+        let extent = open(&mut memory, &KEY).unwrap();
+        extent.verify().unwrap();
+        for page in 0..8 {
+            assert_eq!(extent[page * 4096 + 99], page as u8 + 1);
+        }
+    }
+    #[test]
+    fn wrong_key() {
+        let mut memory = InMemoryExtent::new();
+        open(&mut memory, &KEY).unwrap().resize(100).unwrap();
+        assert!(open(&mut memory, &[8; 32]).is_err());
+        assert!(open(&mut memory, &KEY).is_ok());
+    }
+    #[test]
+    fn tampered_page() {
+        let mut memory = InMemoryExtent::new();
+        {
+            let mut extent = open(&mut memory, &KEY).unwrap();
+            extent.resize(3 * 4096).unwrap();
+            extent.write_at(0, &[1; 3 * 4096]);
+        }
+        //flips a bit in the second page
+        memory[80 + 4136 + 100] ^= 1;
+        let extent = open(&mut memory, &KEY).unwrap();
+        assert_eq!(extent[10], 1);
+        let error = extent.verify().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<EncryptedExtentError>(),
+            Some(&EncryptedExtentError::InvalidPage { page: 1 })
+        );
+    }
+    #[test]
+    fn sealed() {
+        let sealed = seal(&KEY, b"record", b"aad").unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"record"));
+        assert_eq!(unseal(&KEY, &sealed, b"aad"), Some(b"record".to_vec()));
+        assert_eq!(unseal(&[2; 32], &sealed, b"aad"), None);
+        assert_eq!(unseal(&KEY, &sealed, b"other"), None);
+        assert_eq!(unseal(&KEY, &sealed[..10], b"aad"), None);
+    }
+    #[test]
+    fn swapped_page() {
+        let mut first = InMemoryExtent::new();
+        let mut second = InMemoryExtent::new();
+        for memory in [&mut first, &mut second].iter_mut() {
+            let mut extent = open(memory, &KEY).unwrap();
+            extent.resize(4096).unwrap();
+            extent.write_at(0, &[1; 4096]);
+        }
+        //the page of the second extent is valid under the same key but not in the first
+        let slot = second.as_slice(80..second.len()).into_owned();
+        first.write_at(80, &slot);
+        let error = open(&mut first, &KEY).unwrap().verify().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<EncryptedExtentError>(),
+            Some(&EncryptedExtentError::InvalidPage { page: 0 })
+        );
+    }
+    #[test]
+    fn without_id() {
+        //an extent made before ids were added, the header has no id and pages only
+        //authenticate their number
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&KEY));
+        let mut memory = InMemoryExtent::new();
+        {
+            let mut pages = EncryptedPages::empty(DrianableExtent::new(&mut memory), cipher, None);
+            pages.inner.resize(64).unwrap();
+            pages.write_header().unwrap();
+            let mut extent = EncryptedExtent::new(pages);
+            extent.resize(5000).unwrap();
+            extent.write_at(4000, &[3; 1000]);
+        }
+        assert_eq!(memory.len(), 64 + 2 * (24 + 4096 + 16));
+        assert_eq!(memory[8], 0);
+        let mut extent = open(&mut memory, &KEY).unwrap();
+        extent.verify().unwrap();
+        assert_eq!(extent.as_slice(4000..5000), &[3; 1000][..]);
+        extent.resize(9000).unwrap();
+        extent.write_at(8000, &[4; 1000]);
+        drop(extent);
+        let extent = open(&mut memory, &KEY).unwrap();
+        assert_eq!(extent.as_slice(8000..9000), &[4; 1000][..]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::convert::TryInto;
+use thiserror::Error;
+/// A structure on disk was written in a format that can not be read
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    #[error("{structure} is stored in format version {found} but version {supported} is needed, rewrite it with `upgrade`")]
+    Outdated {
+        structure: &'static str,
+        found: u32,
+        supported: u32,
+    },
+    #[error("{structure} is stored in format version {found} which is newer than the supported version {supported}")]
+    Newer {
+        structure: &'static str,
+        found: u32,
+        supported: u32,
+    },
+    #[error("{structure} is stored with unsupported features {flags:#x}")]
+    UnknownFlags { structure: &'static str, flags: u32 },
+}
+///# Format header
+///
+///Every structure stored on disk starts with this header so that a change to its format is
+///found when it is opened instead of its contents being misread.
+///
+///|magic (4 bytes)|version (4 bytes)|flags (4 bytes)|padding (4 bytes)|
+///
+///The magic names the kind of structure. Flags mark optional features the structure was written
+///with, a reader fails on flags it does not know.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub flags: u32,
+}
+impl FormatHeader {
+    pub const SIZE: usize = 16;
+    pub fn new(magic: [u8; 4], version: u32, flags: u32) -> Self {
+        Self {
+            magic,
+            version,
+            flags,
+        }
+    }
+    pub fn to_binary(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.magic);
+        bytes[4..8].copy_from_slice(&self.version.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+    /// Parses the header at the start of bytes. Returns None if bytes do not start with magic
+    pub fn parse(bytes: &[u8], magic: [u8; 4]) -> Option<Self> {
+        if bytes.len() < Self::SIZE || bytes[0..4] != magic[..] {
+            return None;
+        }
+        let read = |start: usize| u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+        Some(Self::new(magic, read(4), read(8)))
+    }
+    /// Checks that the structure can be read by a reader of version that knows the features in
+    /// known_flags. structure names the kind of structure in errors
+    pub fn check(
+        &self,
+        structure: &'static str,
+        version: u32,
+        known_flags: u32,
+    ) -> Result<(), FormatError> {
+        if self.version < version {
+            return Err(FormatError::Outdated {
+                structure,
+                found: self.version,
+                supported: version,
+            });
+        }
+        if self.version > version {
+            return Err(FormatError::Newer {
+                structure,
+                found: self.version,
+                supported: version,
+            });
+        }
+        if self.flags & !known_flags != 0 {
+            return Err(FormatError::UnknownFlags {
+                structure,
+                flags: self.flags & !known_flags,
+            });
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn round_trip() {
+        let header = FormatHeader::new(*b"TEST", 3, 5);
+        let parsed = FormatHeader::parse(&header.to_binary(), *b"TEST").unwrap();
+        assert_eq!(parsed, header);
+        assert!(parsed.check("test", 3, 7).is_ok());
+        assert!(FormatHeader::parse(&header.to_binary(), *b"ELSE").is_none());
+        assert!(FormatHeader::parse(&[0; 4], *b"TEST").is_none());
+    }
+    #[test]
+    fn check() {
+        let header = FormatHeader::new(*b"TEST", 2, 1);
+        assert!(matches!(
+            header.check("test", 3, 1),
+            Err(FormatError::Outdated { found: 2, .. })
+        ));
+        assert!(matches!(
+            header.check("test", 1, 1),
+            Err(FormatError::Newer { found: 2, .. })
+        ));
+        assert_eq!(
+            header.check("test", 2, 0),
+            Err(FormatError::UnknownFlags {
+                structure: "test",
+                flags: 1
+            })
+        );
+    }
+}
@@ -1,10 +1,23 @@
 #[macro_use]
 extern crate macro_lib;
-use std::ops::{Index, IndexMut};
+use std::borrow::Cow;
+use std::ops::{Index, IndexMut, Range};
+mod cached;
+mod checksum;
+mod compressed;
+mod encrypted;
+mod format;
 mod insertable_impl;
 mod node_base;
+mod ordered;
+pub use cached::{CacheStats, CachedExtent, PageStore};
+pub use checksum::{ChecksumError, ChecksummedExtent};
+pub use compressed::{CompressedExtent, CompressedExtentError, CompressedPages};
 use dyn_clonable::*;
+pub use encrypted::{seal, unseal, EncryptedExtent, EncryptedExtentError, EncryptedPages};
+pub use format::{FormatError, FormatHeader};
 pub use node_base::{Node, NodeElementHash, NodeHash};
+pub use ordered::{OrderKind, Ordered};
 pub unsafe trait Insertable {
     const SIZE: usize;
     fn from_binary(data: Vec<u8>) -> Self;
@@ -30,6 +43,35 @@ pub trait Extent: Index<usize, Output = u8> + IndexMut<usize, Output = u8> {
     fn resize(&mut self, new_size: usize) -> anyhow::Result<()>;
     /// Gets the number of availible bytes
     fn len(&self) -> usize;
+    /// Copies the bytes starting at offset into buffer. Panics if the bytes are out of bounds
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self[offset + i];
+        }
+    }
+    /// Overwrites the bytes starting at offset with data. Panics if the bytes are out of bounds
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self[offset + i] = *byte;
+        }
+    }
+    /// Makes sure that the bytes in range, or every byte if range is None, have reached
+    /// storage that survives a crash. Extents that are not persistent have nothing to do
+    fn flush(&mut self, _range: Option<Range<usize>>) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Checks the stored bytes for damage. Extents that do not store checksums have nothing to
+    /// check
+    fn verify(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Gets the bytes in range. Extents kept in contiguous memory borrow the bytes, others copy
+    /// them with `read_at`
+    fn as_slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        let mut buffer = vec![0; range.end - range.start];
+        self.read_at(range.start, &mut buffer);
+        Cow::Owned(buffer)
+    }
 }
 pub struct InMemoryExtent {
     data: Vec<u8>,
@@ -64,6 +106,15 @@ impl Extent for InMemoryExtent {
     fn len(&self) -> usize {
         self.data.len()
     }
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self.data[offset..offset + buffer.len()]);
+    }
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+    fn as_slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.data[range])
+    }
 }
 ///# Extent that allows contents to be read after it is sent to table
 ///To be used only for testing
@@ -82,23 +133,40 @@ impl DrianableExtent {
         }
     }
 }
+impl DrianableExtent {
+    fn inner(&self) -> &InMemoryExtent {
+        unsafe { &*self.extent }
+    }
+    fn inner_mut(&mut self) -> &mut InMemoryExtent {
+        unsafe { &mut *self.extent }
+    }
+}
 impl Index<usize> for DrianableExtent {
     type Output = u8;
     fn index(&self, idx: usize) -> &Self::Output {
-        unsafe { (*self.extent).index(idx) }
+        self.inner().index(idx)
     }
 }
 impl IndexMut<usize> for DrianableExtent {
     fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
-        unsafe { (*self.extent).index_mut(idx) }
+        self.inner_mut().index_mut(idx)
     }
 }
 impl Extent for DrianableExtent {
     fn resize(&mut self, new_size: usize) -> anyhow::Result<()> {
-        unsafe { (*self.extent).resize(new_size) }
+        self.inner_mut().resize(new_size)
     }
     fn len(&self) -> usize {
-        unsafe { (*self.extent).len() }
+        self.inner().len()
+    }
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        self.inner().read_at(offset, buffer)
+    }
+    fn write_at(&mut self, offset: usize, data: &[u8]) {
+        self.inner_mut().write_at(offset, data)
+    }
+    fn as_slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        self.inner().as_slice(range)
     }
 }
 #[cfg(test)]
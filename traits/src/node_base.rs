@@ -42,8 +42,15 @@ unsafe impl Insertable for NodeHash {
 pub trait Node {
     //hash of the database name
     const SELF_HASH: NodeHash;
+    //name of the type, recorded in the schema catalog
+    const NAME: &'static str;
     fn get_sized_hashes() -> Vec<NodeElementHash>;
     fn get_variable_hashes() -> Vec<NodeElementHash>;
+    /// Path of every field returned by `get_sized_hashes` in the same order. Fields of
+    /// primitives have an empty path
+    fn get_sized_names() -> Vec<String>;
+    /// Path of every field returned by `get_variable_hashes` in the same order
+    fn get_variable_names() -> Vec<String>;
     fn get_data(
         &self,
     ) -> (
@@ -57,12 +64,19 @@ pub trait Node {
 }
 impl Node for f32 {
     const SELF_HASH: NodeHash = NodeHash { hash: hash!(f32) };
+    const NAME: &'static str = "f32";
     fn get_sized_hashes() -> Vec<NodeElementHash> {
         vec![NodeElementHash { hash: hash!(f32) }]
     }
     fn get_variable_hashes() -> Vec<NodeElementHash> {
         vec![]
     }
+    fn get_sized_names() -> Vec<String> {
+        vec![String::new()]
+    }
+    fn get_variable_names() -> Vec<String> {
+        vec![]
+    }
 
     fn get_data(
         &self,
@@ -91,12 +105,19 @@ impl Node for f32 {
 
 impl Node for u64 {
     const SELF_HASH: NodeHash = NodeHash { hash: hash!(u64) };
+    const NAME: &'static str = "u64";
     fn get_sized_hashes() -> Vec<NodeElementHash> {
         vec![NodeElementHash { hash: hash!(u64) }]
     }
     fn get_variable_hashes() -> Vec<NodeElementHash> {
         vec![]
     }
+    fn get_sized_names() -> Vec<String> {
+        vec![String::new()]
+    }
+    fn get_variable_names() -> Vec<String> {
+        vec![]
+    }
 
     fn get_data(
         &self,
@@ -127,6 +148,7 @@ impl Node for String {
     const SELF_HASH: NodeHash = NodeHash {
         hash: hash!(String),
     };
+    const NAME: &'static str = "String";
     fn get_sized_hashes() -> Vec<NodeElementHash> {
         vec![]
     }
@@ -136,6 +158,13 @@ impl Node for String {
         }]
     }
 
+    fn get_sized_names() -> Vec<String> {
+        vec![]
+    }
+    fn get_variable_names() -> Vec<String> {
+        vec![String::new()]
+    }
+
     fn get_data(
         &self,
     ) -> (
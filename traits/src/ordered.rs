@@ -0,0 +1,90 @@
+use crate::InsertableDyn;
+/// How the little endian bytes of a sized value compare
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderKind {
+    /// Unsigned integer of up to 8 bytes
+    Unsigned = 0,
+    /// IEEE 754 float of 4 or 8 bytes
+    Float = 1,
+}
+impl OrderKind {
+    /// Maps the bytes of a value to a u64 that sorts in the same order as the value
+    pub fn to_ordered(&self, data: &[u8]) -> u64 {
+        match self {
+            Self::Unsigned => {
+                let mut buffer = [0u8; 8];
+                buffer[..data.len()].copy_from_slice(data);
+                u64::from_le_bytes(buffer)
+            }
+            Self::Float => {
+                if data.len() == 4 {
+                    let bits = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                    let bits = if bits & 0x8000_0000 != 0 {
+                        !bits
+                    } else {
+                        bits | 0x8000_0000
+                    };
+                    bits as u64
+                } else {
+                    let bits = u64::from_le_bytes([
+                        data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+                    ]);
+                    if bits & 0x8000_0000_0000_0000 != 0 {
+                        !bits
+                    } else {
+                        bits | 0x8000_0000_0000_0000
+                    }
+                }
+            }
+        }
+    }
+    pub fn from_u64(kind: u64) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Unsigned),
+            1 => Some(Self::Float),
+            _ => None,
+        }
+    }
+}
+/// Sized values that can be kept in an ordered index
+pub trait Ordered: InsertableDyn {
+    const ORDER: OrderKind;
+}
+impl Ordered for u8 {
+    const ORDER: OrderKind = OrderKind::Unsigned;
+}
+impl Ordered for u32 {
+    const ORDER: OrderKind = OrderKind::Unsigned;
+}
+impl Ordered for u64 {
+    const ORDER: OrderKind = OrderKind::Unsigned;
+}
+impl Ordered for usize {
+    const ORDER: OrderKind = OrderKind::Unsigned;
+}
+impl Ordered for f32 {
+    const ORDER: OrderKind = OrderKind::Float;
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn float_order() {
+        let values = [-100.5f32, -1.0, -0.0, 0.0, 0.25, 1.0, 3.5, 1000.0];
+        let ordered: Vec<u64> = values
+            .iter()
+            .map(|v| OrderKind::Float.to_ordered(&v.to_binary()))
+            .collect();
+        for pair in ordered.windows(2) {
+            assert!(pair[0] <= pair[1]);
+        }
+    }
+    #[test]
+    fn unsigned_order() {
+        assert!(
+            OrderKind::Unsigned.to_ordered(&300u32.to_binary())
+                > OrderKind::Unsigned.to_ordered(&20u32.to_binary())
+        );
+        assert_eq!(OrderKind::Unsigned.to_ordered(&7u8.to_binary()), 7);
+    }
+}
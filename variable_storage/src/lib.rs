@@ -1,5 +1,12 @@
 use std::cmp::min;
-use traits::{Extent, Insertable, InsertableDyn};
+use std::convert::TryInto;
+use thiserror::Error;
+use traits::{Extent, FormatError, FormatHeader, Insertable, InsertableDyn};
+#[derive(Error, Debug)]
+enum VariableExtentError {
+    #[error("extent does not hold a variable extent")]
+    InvalidExtent,
+}
 unsafe impl InsertableDyn for Key {
     fn size(&self) -> u32 {
         8
@@ -16,79 +23,214 @@ unsafe impl Insertable for Key {
         }
     }
 }
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Key {
     pub index: usize,
 }
+///# Variable extent
+///
+///Stores entries of any length in a chain of fixed size blocks. Every block starts with a used
+///flag (4 bytes), the number of bytes used in the block (4 bytes) and the number of the next
+///block in the chain (8 bytes), 0 if it is the last block.
+///
+///Block 0 holds the `FormatHeader` and block 1 starts the key listing, which holds the number
+///of the first block of every entry and 0 for removed keys. Version 0 extents have no format
+///block and start the key listing in block 0, they are rewritten by `upgrade`.
 pub struct VariableExtent<ExtentT: Extent> {
     data_store: ExtentT,
 }
 impl<ExtentT: Extent> VariableExtent<ExtentT> {
+    pub const VERSION: u32 = 1;
+    const MAGIC: [u8; 4] = *b"WVAR";
     const FAT_BLOCK_SIZE: usize = 100;
     const HEADER_SIZE: usize = 0x4 + 0x4 + 0x8;
     const BLOCK_USABLE_SIZE: usize = Self::FAT_BLOCK_SIZE - Self::HEADER_SIZE;
+    //block that starts the key listing
+    const LISTING: usize = 1;
     /// Creates a new Extent
     pub fn new(mut data_store: ExtentT) -> Self {
-        data_store.resize(Self::FAT_BLOCK_SIZE);
+        data_store.resize(2 * Self::FAT_BLOCK_SIZE).ok().unwrap();
 
-        let one_buff = (1 as u32).to_le_bytes();
-        for i in 0..4 {
-            data_store[i] = one_buff[i];
+        let mut block = vec![0; 2 * Self::FAT_BLOCK_SIZE];
+        block[..FormatHeader::SIZE].copy_from_slice(&Self::format_header().to_binary());
+        block[Self::FAT_BLOCK_SIZE..Self::FAT_BLOCK_SIZE + 4].copy_from_slice(&1_u32.to_le_bytes());
+        data_store.write_at(0, &block);
+        Self { data_store }
+    }
+    /// Loads an Extent created by `new`. Fails if data_store is not made of whole blocks or is
+    /// stored in another version
+    pub fn load(data_store: ExtentT) -> anyhow::Result<Self> {
+        Self::check_format(&data_store)?;
+        Ok(Self { data_store })
+    }
+    /// Rewrites an extent stored in an older version in the current one. Returns whether the
+    /// extent was rewritten
+    pub fn upgrade(data_store: &mut ExtentT) -> anyhow::Result<bool> {
+        if let Err(error) = Self::check_format(data_store) {
+            return match error.downcast_ref::<FormatError>() {
+                Some(FormatError::Outdated { found: 0, .. }) => {
+                    Self::upgrade_unversioned(data_store)?;
+                    Ok(true)
+                }
+                _ => Err(error),
+            };
         }
-        for i in 4..Self::FAT_BLOCK_SIZE {
-            data_store[i as usize] = 0;
+        Ok(false)
+    }
+    /// Moves every block of a version 0 extent up by one to make room for the format block
+    fn upgrade_unversioned(data_store: &mut ExtentT) -> anyhow::Result<()> {
+        let mut data = data_store.as_slice(0..data_store.len()).into_owned();
+        let blocks = data.len() / Self::FAT_BLOCK_SIZE;
+        let read_u64 = |data: &[u8], start: usize| {
+            u64::from_le_bytes(data[start..start + 8].try_into().unwrap())
+        };
+        let invalid = || anyhow::anyhow!("{}", VariableExtentError::InvalidExtent);
+        //offsets of the bytes of the key listing, entries may be split between blocks
+        let mut listing = vec![];
+        let mut block = 0;
+        loop {
+            let start = block * Self::FAT_BLOCK_SIZE;
+            let size = u32::from_le_bytes(data[start + 4..start + 8].try_into().unwrap()) as usize;
+            if size > Self::BLOCK_USABLE_SIZE || listing.len() > data.len() {
+                return Err(invalid());
+            }
+            listing.extend(start + Self::HEADER_SIZE..start + Self::HEADER_SIZE + size);
+            block = read_u64(&data, start + 8) as usize;
+            if block == 0 {
+                break;
+            }
+            if block >= blocks {
+                return Err(invalid());
+            }
         }
-        Self { data_store }
+        for entry in listing.chunks(8) {
+            let number = u64::from_le_bytes(
+                entry
+                    .iter()
+                    .map(|offset| data[*offset])
+                    .collect::<Vec<u8>>()
+                    .try_into()
+                    .map_err(|_| invalid())?,
+            );
+            if number != 0 {
+                for (offset, byte) in entry.iter().zip((number + 1).to_le_bytes().iter()) {
+                    data[*offset] = *byte;
+                }
+            }
+        }
+        for block in 0..blocks {
+            let start = block * Self::FAT_BLOCK_SIZE;
+            let next = read_u64(&data, start + 8);
+            if data[start..start + 4] == 1u32.to_le_bytes() && next != 0 {
+                data[start + 8..start + 16].copy_from_slice(&(next + 1).to_le_bytes());
+            }
+        }
+        let mut format_block = vec![0; Self::FAT_BLOCK_SIZE];
+        format_block[..FormatHeader::SIZE].copy_from_slice(&Self::format_header().to_binary());
+        data_store.resize(data.len() + Self::FAT_BLOCK_SIZE)?;
+        data_store.write_at(0, &format_block);
+        data_store.write_at(Self::FAT_BLOCK_SIZE, &data);
+        data_store.flush(None)
+    }
+    fn format_header() -> FormatHeader {
+        FormatHeader::new(Self::MAGIC, Self::VERSION, 0)
+    }
+    /// Checks data_store for damage and that it holds an extent stored in the current version
+    fn check_format(data_store: &ExtentT) -> anyhow::Result<()> {
+        data_store.verify()?;
+        let invalid = || anyhow::anyhow!("{}", VariableExtentError::InvalidExtent);
+        let blocks = data_store.len() / Self::FAT_BLOCK_SIZE;
+        if blocks == 0 || blocks * Self::FAT_BLOCK_SIZE != data_store.len() {
+            return Err(invalid());
+        }
+        let start = data_store.as_slice(0..FormatHeader::SIZE);
+        match FormatHeader::parse(&start, Self::MAGIC) {
+            Some(header) => {
+                header.check("variable extent", Self::VERSION, 0)?;
+                if blocks <= Self::LISTING {
+                    return Err(invalid());
+                }
+                Ok(())
+            }
+            //version 0 starts with the used flag of the key listing
+            None if start[..4] == 1u32.to_le_bytes() => Err(FormatError::Outdated {
+                structure: "variable extent",
+                found: 0,
+                supported: Self::VERSION,
+            }
+            .into()),
+            None => Err(invalid()),
+        }
+    }
+    /// Writes the extent back to storage, see `Extent::flush`
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.data_store.flush(None)
+    }
+    /// Checks the storage of the extent for damage, see `Extent::verify`
+    pub fn verify(&self) -> anyhow::Result<()> {
+        self.data_store.verify()
     }
     /// Gets the data associated with a key
     pub fn get_entry(&self, key: Key) -> Vec<u8> {
         let fat = self.find_key(key);
         self.load_block(fat)
     }
-    /// Adds a new Entery with the specified data
+    /// Adds a new Entery with the specified data. Every entry gets a key that has not been
+    /// handed out before, so a key kept after its entry was removed never finds another entry
     pub fn add_entry(&mut self, buffer: Vec<u8>) -> Key {
-        let key_buffer = self.load_block(0);
+        let key_buffer = self.load_block(Self::LISTING);
         let free_key: usize = self.find_free_entery();
         self.initilize_block(free_key);
         self.append_block(free_key, buffer);
-        self.append_block(0, free_key.to_le_bytes().to_vec());
-        return Key {
+        self.append_block(Self::LISTING, free_key.to_le_bytes().to_vec());
+        Key {
             index: key_buffer.len() / std::mem::size_of::<u64>(),
-        };
+        }
+    }
+    /// Removes an entry and frees all of the blocks used by it. The key stays invalid, the
+    /// blocks are reused by later entries
+    pub fn remove_entry(&mut self, key: Key) {
+        let block = self.find_key(key.clone());
+        self.write_listing(key.index, 0);
+        self.free_chain(block);
     }
     pub fn contains_key(&self, key: Key) -> bool {
-        let listing = self.load_block(0);
+        let listing = self.load_block(Self::LISTING);
         if key.index * 8 >= listing.len() {
             false
         } else {
-            true
+            listing[key.index * 8..key.index * 8 + 8] != [0; 8]
         }
     }
     /// Finds a free fat entery. Does not initilize entry
     fn find_free_entery(&mut self) -> usize {
-        for i in 1..self.get_number_blocks() {
-            let is_used = {
-                let buff: Vec<u8> = (i * Self::FAT_BLOCK_SIZE..(i + 1) * Self::FAT_BLOCK_SIZE)
-                    .map(|j| self.data_store[j])
-                    .collect();
-                u32::from_le_bytes([buff[0], buff[1], buff[2], buff[3]])
-            };
+        for i in Self::LISTING + 1..self.get_number_blocks() {
+            let is_used = self.read_u32(i * Self::FAT_BLOCK_SIZE);
             if is_used == 0 {
                 return i;
             }
         }
         let new_key = self.get_number_blocks();
-        self.data_store.resize((new_key + 1) * Self::FAT_BLOCK_SIZE);
-        return new_key;
+        self.data_store
+            .resize((new_key + 1) * Self::FAT_BLOCK_SIZE)
+            .ok()
+            .unwrap();
+        new_key
     }
     fn get_number_blocks(&self) -> usize {
-        return self.data_store.len() / Self::FAT_BLOCK_SIZE;
+        self.data_store.len() / Self::FAT_BLOCK_SIZE
     }
-    /// Writes new data to a entery specified at a index. Resizes if needed
+    /// Writes new data to a entery specified at a index. The entry is resized so that it ends
+    /// at the end of buffer.
     /// Index must be inside of buffer. todo: add better error handeling
     pub fn write_entry(&mut self, key: Key, index: usize, buffer: Vec<u8>) {
         let (block_num, index) = self.find_start(self.find_key(key), index);
-        self.write(buffer, 0, block_num, index);
+        self.write(buffer, 0, block_num, index, true);
+    }
+    /// Sets the block number stored at index in the key listing
+    fn write_listing(&mut self, index: usize, block_num: usize) {
+        let (block, start) = self.find_start(Self::LISTING, index * 8);
+        self.write(block_num.to_le_bytes().to_vec(), 0, block, start, false);
     }
     /// Finds the block in a start block. Returns (block_number,index relative to start of
     /// current_block)
@@ -106,21 +248,18 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
             }
         }
     }
+    /// Writes data starting at start_index in block. If truncate is set the blocks after the
+    /// end of data are freed, otherwise existing data past the end is kept
     fn write(
         &mut self,
         data: Vec<u8>,
         mut data_start: usize,
         mut block: usize,
         mut start_index: usize,
+        truncate: bool,
     ) {
         loop {
-            let is_used = {
-                let buff: Vec<u8> = (block * Self::FAT_BLOCK_SIZE
-                    ..block * Self::FAT_BLOCK_SIZE + 4)
-                    .map(|i| self.data_store[i])
-                    .collect();
-                u32::from_le_bytes([buff[0], buff[1], buff[2], buff[3]])
-            };
+            let is_used = self.read_u32(block * Self::FAT_BLOCK_SIZE);
             if is_used != 1 {
                 panic!()
             }
@@ -129,14 +268,15 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
                 Self::BLOCK_USABLE_SIZE - start_index,
                 data.len() - data_start,
             );
-            assert!(block != 0);
 
             //copying data
-            for i in 0..copy_size {
-                self.data_store[block * Self::FAT_BLOCK_SIZE + Self::HEADER_SIZE + start_index] =
-                    data[i + data_start];
+            self.data_store.write_at(
+                block * Self::FAT_BLOCK_SIZE + Self::HEADER_SIZE + start_index,
+                &data[data_start..data_start + copy_size],
+            );
+            if truncate || copy_size + start_index > self.get_block_size(block) {
+                self.set_block_size(block, copy_size + start_index);
             }
-            self.set_block_size(block, copy_size + start_index);
             if data_start + copy_size < data.len() {
                 let mut new_block = self.get_next_block(block);
                 if new_block == 0 {
@@ -148,27 +288,34 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
                 block = new_block;
                 start_index = 0;
             } else {
+                if truncate {
+                    let rest = self.get_next_block(block);
+                    self.set_next_block(block, 0);
+                    self.free_chain(rest);
+                }
                 return;
             }
         }
     }
+    /// Marks every block in the chain starting at block_num as unused
+    fn free_chain(&mut self, mut block_num: usize) {
+        let empty_header = vec![0; Self::HEADER_SIZE];
+        while block_num != 0 {
+            let next_block = self.get_next_block(block_num);
+            self.data_store
+                .write_at(block_num * Self::FAT_BLOCK_SIZE, &empty_header);
+            block_num = next_block;
+        }
+    }
     /// Initilizes a block to zero size
     fn initilize_block(&mut self, block_num: usize) {
         assert!(block_num * Self::FAT_BLOCK_SIZE <= self.data_store.len());
         let block_start = block_num * Self::FAT_BLOCK_SIZE;
 
-        let is_used_bytes = 1u32.to_le_bytes();
-        for i in 0..4 {
-            self.data_store[i + block_start] = is_used_bytes[i];
-        }
-        let size_bytes = 0u32.to_le_bytes();
-        for i in 0..4 {
-            self.data_store[i + 4 + block_start] = size_bytes[i];
-        }
-        let next_addr_bytes = 0u64.to_le_bytes();
-        for i in 0..8 {
-            self.data_store[i + 8 + block_start] = next_addr_bytes[i];
-        }
+        //used flag then a size and next address of zero
+        let mut header = vec![0; Self::HEADER_SIZE];
+        header[..4].copy_from_slice(&1u32.to_le_bytes());
+        self.data_store.write_at(block_start, &header);
     }
     /// Appends data to the end of a block. allocates new blocks as needed
     fn append_block(&mut self, mut block_num: usize, data: Vec<u8>) {
@@ -181,11 +328,10 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
                     data.len() - start_index,
                 );
                 let block_size = self.get_block_size(block_num);
-                for i in 0..copy_size {
-                    self.data_store
-                        [Self::FAT_BLOCK_SIZE * block_num + Self::HEADER_SIZE + i + block_size] =
-                        data[i + start_index];
-                }
+                self.data_store.write_at(
+                    Self::FAT_BLOCK_SIZE * block_num + Self::HEADER_SIZE + block_size,
+                    &data[start_index..start_index + copy_size],
+                );
                 self.set_block_size(block_num, copy_size + block_size);
                 if start_index + copy_size == data.len() {
                     return;
@@ -205,35 +351,34 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
     fn get_next_block(&self, block: usize) -> usize {
         assert!(block * Self::FAT_BLOCK_SIZE <= self.data_store.len());
         let mut next_buff = [0; 8];
-        for i in 0..8 {
-            next_buff[i] = self.data_store[block * Self::FAT_BLOCK_SIZE + 8 + i];
-        }
+        self.data_store
+            .read_at(block * Self::FAT_BLOCK_SIZE + 8, &mut next_buff);
         u64::from_le_bytes(next_buff) as usize
     }
     /// Sets the next block header in block
     fn set_next_block(&mut self, block: usize, next_block: usize) {
         assert!(block * Self::FAT_BLOCK_SIZE <= self.data_store.len());
         assert!(next_block * Self::FAT_BLOCK_SIZE <= self.data_store.len());
-        let next_buff = next_block.to_le_bytes();
-        for i in 0..8 {
-            self.data_store[block * Self::FAT_BLOCK_SIZE + 8 + i] = next_buff[i];
-        }
+        self.data_store
+            .write_at(block * Self::FAT_BLOCK_SIZE + 8, &next_block.to_le_bytes());
+    }
+    /// Reads the u32 stored at start
+    fn read_u32(&self, start: usize) -> u32 {
+        let mut buffer = [0; 4];
+        self.data_store.read_at(start, &mut buffer);
+        u32::from_le_bytes(buffer)
     }
     //gets the allocated size of a given block
     fn get_block_size(&self, block: usize) -> usize {
         assert!(block * Self::FAT_BLOCK_SIZE <= self.data_store.len());
-        let mut size_buff = [0; 4];
-        for i in 0..4 {
-            size_buff[i] = self.data_store[block * Self::FAT_BLOCK_SIZE + 4 + i];
-        }
-        u32::from_le_bytes(size_buff) as usize
+        self.read_u32(block * Self::FAT_BLOCK_SIZE + 4) as usize
     }
     fn set_block_size(&mut self, block: usize, new_size: usize) {
         assert!(block * Self::FAT_BLOCK_SIZE <= self.data_store.len());
-        let size_buff = new_size.to_le_bytes();
-        for i in 0..4 {
-            self.data_store[block * Self::FAT_BLOCK_SIZE + 4 + i] = size_buff[i];
-        }
+        self.data_store.write_at(
+            block * Self::FAT_BLOCK_SIZE + 4,
+            &(new_size as u32).to_le_bytes(),
+        );
     }
     fn load_block(&self, block_num: usize) -> Vec<u8> {
         if block_num * Self::FAT_BLOCK_SIZE >= self.data_store.len() {
@@ -242,32 +387,18 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
         let mut block_start = block_num * Self::FAT_BLOCK_SIZE;
         let mut buff = vec![];
         loop {
-            let is_used = {
-                let buff: Vec<u8> = (block_start..block_start + 4)
-                    .map(|i| self.data_store[i])
-                    .collect();
-                u32::from_le_bytes([buff[0], buff[1], buff[2], buff[3]])
-            };
+            let mut header = vec![0; Self::HEADER_SIZE];
+            self.data_store.read_at(block_start, &mut header);
+            let is_used = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
             if is_used != 1 {
                 panic!()
             }
-            let size = {
-                let buff: Vec<u8> = (block_start + 4..block_start + 8)
-                    .map(|i| self.data_store[i])
-                    .collect();
-                u32::from_le_bytes([buff[0], buff[1], buff[2], buff[3]])
-            };
-            let next_addr = {
-                let buff: Vec<u8> = (block_start + 8..block_start + 16)
-                    .map(|i| self.data_store[i])
-                    .collect();
-                u64::from_le_bytes([
-                    buff[0], buff[1], buff[2], buff[3], buff[4], buff[5], buff[6], buff[7],
-                ])
-            };
-            for i in 0..size {
-                buff.push(self.data_store[i as usize + block_start + 16]);
-            }
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let mut next_buff = [0; 8];
+            next_buff.copy_from_slice(&header[8..16]);
+            let next_addr = u64::from_le_bytes(next_buff);
+            let data_start = block_start + Self::HEADER_SIZE;
+            buff.extend_from_slice(&self.data_store.as_slice(data_start..data_start + size));
             if next_addr == 0 {
                 return buff;
             } else {
@@ -277,20 +408,23 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
     }
     /// Finds the fat block associated with the key in the key listing table
     fn find_key(&self, key: Key) -> usize {
-        let listing = self.load_block(0);
+        let listing = self.load_block(Self::LISTING);
         let mut block_number = [0; 8];
         for i in 0..8 {
             block_number[i] = listing[key.index * 8 + i]
         }
         let number = u64::from_le_bytes(block_number) as usize;
+        if number == 0 {
+            panic!("key has been removed");
+        }
         if number * Self::FAT_BLOCK_SIZE >= self.data_store.len() {
             panic!("fat key out of bounds");
         }
-        return number;
+        number
     }
     pub fn is_consistant(&self) -> bool {
-        let block = self.load_block(0);
-        if block.len() % 8 != 0 {
+        let block = self.load_block(Self::LISTING);
+        if !block.len().is_multiple_of(8) {
             panic!("fat size wrong");
         }
         for i in 0..block.len() / 8 {
@@ -308,7 +442,7 @@ impl<ExtentT: Extent> VariableExtent<ExtentT> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use traits::InMemoryExtent;
+    use traits::{DrianableExtent, InMemoryExtent};
     #[test]
     fn create_extent() {
         let _ = VariableExtent::new(InMemoryExtent::new());
@@ -360,20 +494,108 @@ mod tests {
         let v: Vec<(Key, u8)> = (0..100)
             .map(|i| {
                 assert!(e.is_consistant());
-                (e.add_entry(vec![i.clone()]), i.clone())
+                (e.add_entry(vec![i]), i)
             })
             .collect();
         for (key, data) in v.iter() {
             assert!(e.is_consistant());
-            assert_eq!(e.get_entry(key.clone()), vec![data.clone()]);
+            assert_eq!(e.get_entry(key.clone()), vec![*data]);
         }
     }
     #[test]
     fn contains_key() {
         let mut e = VariableExtent::new(InMemoryExtent::new());
         let fake = Key { index: 100 };
-        assert_eq!(e.contains_key(fake), false);
+        assert!(!e.contains_key(fake));
         let real = e.add_entry(vec![]);
-        assert_eq!(e.contains_key(real), true);
+        assert!(e.contains_key(real));
+    }
+    #[test]
+    fn write_shrink() {
+        let mut e = VariableExtent::new(InMemoryExtent::new());
+        let k = e.add_entry((0..255).collect());
+        e.write_entry(k.clone(), 0, vec![1, 2, 3]);
+        assert_eq!(e.get_entry(k.clone()), vec![1, 2, 3]);
+        e.write_entry(k.clone(), 2, (0..200).collect());
+        let mut expected = vec![1, 2];
+        expected.append(&mut (0..200).collect());
+        assert_eq!(e.get_entry(k), expected);
+    }
+    #[test]
+    fn remove_entry() {
+        let mut e = VariableExtent::new(InMemoryExtent::new());
+        let k1 = e.add_entry(vec![1; 300]);
+        let k2 = e.add_entry(vec![2]);
+        let len = e.data_store.len();
+        e.remove_entry(k1.clone());
+        assert!(!e.contains_key(k1.clone()));
+        assert!(e.contains_key(k2.clone()));
+        assert!(e.is_consistant());
+        let k3 = e.add_entry(vec![3; 300]);
+        assert_ne!(k3, k1);
+        assert!(!e.contains_key(k1));
+        assert_eq!(e.data_store.len(), len);
+        assert_eq!(e.get_entry(k3), vec![3; 300]);
+        assert_eq!(e.get_entry(k2), vec![2]);
+    }
+    #[test]
+    fn load() {
+        let mut e = VariableExtent::new(InMemoryExtent::new());
+        let k1 = e.add_entry(vec![1; 300]);
+        let k2 = e.add_entry(vec![2]);
+        let e = VariableExtent::load(e.data_store).unwrap();
+        assert_eq!(e.get_entry(k1), vec![1; 300]);
+        assert_eq!(e.get_entry(k2), vec![2]);
+        assert!(VariableExtent::load(InMemoryExtent::new()).is_err());
+    }
+    #[test]
+    fn upgrade() {
+        //version 0 extent, the key listing in block 0 continues in block 1 and holds keys to
+        //block 2, a removed key and block 3 which continues in block 4 followed by removed keys
+        const BLOCK: usize = 100;
+        let mut old = vec![0; 5 * BLOCK];
+        let mut header = |block: usize, size: u32, next: u64| {
+            old[block * BLOCK..block * BLOCK + 4].copy_from_slice(&1u32.to_le_bytes());
+            old[block * BLOCK + 4..block * BLOCK + 8].copy_from_slice(&size.to_le_bytes());
+            old[block * BLOCK + 8..block * BLOCK + 16].copy_from_slice(&next.to_le_bytes());
+        };
+        header(0, 84, 1);
+        header(1, 4, 0);
+        header(2, 1, 0);
+        header(3, 84, 4);
+        header(4, 16, 0);
+        let mut listing = vec![0; 84];
+        listing[0..8].copy_from_slice(&2u64.to_le_bytes());
+        listing[16..24].copy_from_slice(&3u64.to_le_bytes());
+        old[16..100].copy_from_slice(&listing);
+        old[2 * BLOCK + 16] = 7;
+        for i in 0..100 {
+            let offset = if i < 84 {
+                3 * BLOCK + 16 + i
+            } else {
+                4 * BLOCK + i - 68
+            };
+            old[offset] = i as u8;
+        }
+        let mut extent = InMemoryExtent::new();
+        extent.resize(old.len()).ok().unwrap();
+        extent.write_at(0, &old);
+        let error = VariableExtent::load(DrianableExtent::new(&mut extent))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<FormatError>(),
+            Some(FormatError::Outdated { found: 0, .. })
+        ));
+        assert!(VariableExtent::upgrade(&mut extent).ok().unwrap());
+        assert!(!VariableExtent::upgrade(&mut extent).ok().unwrap());
+        let mut e = VariableExtent::load(extent).ok().unwrap();
+        assert!(e.is_consistant());
+        assert_eq!(e.get_entry(Key { index: 0 }), vec![7]);
+        assert!(!e.contains_key(Key { index: 1 }));
+        assert_eq!(e.get_entry(Key { index: 2 }), (0..100).collect::<Vec<u8>>());
+        let key = e.add_entry(vec![8]);
+        assert!(key.index > 2);
+        assert_eq!(e.get_entry(key), vec![8]);
     }
 }